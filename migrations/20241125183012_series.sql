@@ -0,0 +1,45 @@
+-- 1. Create the `series` table
+CREATE TABLE IF NOT EXISTS series (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    name TEXT UNIQUE NOT NULL, -- Name shown in the series selector
+    prefix TEXT, -- Value of the {prefix} placeholder, NULL uses the configured default
+    format TEXT, -- Code format template, NULL uses the configured default
+    description TEXT NOT NULL DEFAULT '',
+    is_active INTEGER NOT NULL DEFAULT 1, -- Inactive series can't be used for new reservations
+    created_at DATETIME DEFAULT (
+        STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW')
+    ) NOT NULL
+);
+
+-- 2. Insert the default series which takes over all existing codes
+INSERT INTO
+    series (id, name, description)
+VALUES (1, 'Default', 'Default number series');
+
+-- 3. Rename the existing `codes` table to prepare for migration
+ALTER TABLE codes RENAME TO codes_old;
+
+-- 4. Create a new `codes` table with the `series_id` column
+CREATE TABLE IF NOT EXISTS codes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    code VARCHAR(255) UNIQUE NOT NULL,
+    user_id INTEGER NOT NULL DEFAULT 1, -- Foreign key to the `users` table, defaults to Admin's ID
+    series_id INTEGER NOT NULL DEFAULT 1 REFERENCES series (id), -- Defaults to the default series
+    created_at DATETIME DEFAULT (
+        STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW')
+    ) NOT NULL
+);
+
+-- 5. Copy data from the old `codes` table to the new one
+INSERT INTO
+    codes (id, code, user_id, series_id, created_at)
+SELECT id, code, user_id, 1, created_at
+FROM codes_old;
+
+-- 6. Drop the old table
+DROP TABLE codes_old;
+
+-- 7. Add indexes for the foreign key columns
+CREATE INDEX IF NOT EXISTS idx_codes_user_id ON codes (user_id);
+
+CREATE INDEX IF NOT EXISTS idx_codes_series_id ON codes (series_id);
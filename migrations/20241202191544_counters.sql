@@ -0,0 +1,7 @@
+-- 1. Create the `counters` table holding the last allocated number of every sequence
+CREATE TABLE IF NOT EXISTS counters (
+    series_id INTEGER NOT NULL REFERENCES series (id), -- Series the sequence belongs to
+    period_key TEXT NOT NULL, -- Rendered code without the sequence number
+    value INTEGER NOT NULL, -- Last allocated sequence number
+    PRIMARY KEY (series_id, period_key)
+);
@@ -0,0 +1,19 @@
+-- 1. Add structured sequence columns to `codes`
+ALTER TABLE codes ADD COLUMN period_key TEXT; -- Rendered code without the sequence number
+
+ALTER TABLE codes ADD COLUMN seq INTEGER; -- Sequence number within the period
+
+-- 2. Backfill existing codes, which all end with '.<number>'. Everything up to the last '.' is the period key.
+UPDATE codes
+SET
+    period_key = RTRIM(code, REPLACE(code, '.', '')),
+    seq = CAST(
+        SUBSTR(code, LENGTH(RTRIM(code, REPLACE(code, '.', ''))) + 1) AS INTEGER
+    )
+WHERE
+    INSTR(code, '.') > 0
+    AND SUBSTR(code, LENGTH(RTRIM(code, REPLACE(code, '.', ''))) + 1) <> ''
+    AND SUBSTR(code, LENGTH(RTRIM(code, REPLACE(code, '.', ''))) + 1) NOT GLOB '*[^0-9]*';
+
+-- 3. Add an index for looking up the highest number of a period
+CREATE INDEX IF NOT EXISTS idx_codes_sequence ON codes (series_id, period_key, seq);
@@ -0,0 +1,39 @@
+-- 1. Add the reset period to `series`
+ALTER TABLE series ADD COLUMN reset_period TEXT NOT NULL DEFAULT 'daily'; -- daily, weekly, monthly, yearly, fiscal[:<month>] or never
+
+-- 2. Series without a date in their format never started a new sequence
+UPDATE series
+SET
+    reset_period = 'never'
+WHERE
+    format IS NOT NULL
+    AND INSTR(format, '{date') = 0;
+
+-- 3. Period keys are now computed from the reservation time instead of the rendered code
+UPDATE codes
+SET
+    period_key = CASE (
+            SELECT reset_period
+            FROM series
+            WHERE
+                series.id = codes.series_id
+        )
+        WHEN 'never' THEN ''
+        ELSE STRFTIME('%Y-%m-%d', created_at, 'localtime')
+    END
+WHERE
+    period_key IS NOT NULL;
+
+-- 4. Rebuild the counters with the new period keys
+DELETE FROM counters;
+
+INSERT INTO
+    counters (series_id, period_key, value)
+SELECT series_id, period_key, MAX(seq)
+FROM codes
+WHERE
+    period_key IS NOT NULL
+    AND seq IS NOT NULL
+GROUP BY
+    series_id,
+    period_key;
@@ -0,0 +1,2 @@
+-- 1. Add the check digit algorithm to `series`
+ALTER TABLE series ADD COLUMN check_digit TEXT; -- luhn, mod97-10 or damm, NULL for codes without a check digit
@@ -0,0 +1,10 @@
+-- 1. Add the timezone to `series`
+ALTER TABLE series ADD COLUMN timezone TEXT; -- IANA timezone name, NULL uses the configured default
+
+-- 2. Existing timestamps are UTC, store them with an explicit offset like new codes
+UPDATE codes
+SET
+    created_at = STRFTIME('%Y-%m-%dT%H:%M:%f', created_at) || '+00:00'
+WHERE
+    created_at NOT LIKE '%+__:__'
+    AND created_at NOT LIKE '%-__:__';
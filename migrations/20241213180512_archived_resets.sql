@@ -0,0 +1,51 @@
+-- 1. Create the `resets` table recording every reset of the numbering
+CREATE TABLE IF NOT EXISTS resets (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    user_id INTEGER NOT NULL, -- User who made the reset
+    series_id INTEGER REFERENCES series (id), -- NULL resets all series
+    from_date TEXT, -- First archived day (YYYY-MM-DD), NULL for no lower bound
+    to_date TEXT, -- Last archived day (YYYY-MM-DD), NULL for no upper bound
+    created_at DATETIME DEFAULT (
+        STRFTIME('%Y-%m-%dT%H:%M:%f', 'NOW') || '+00:00'
+    ) NOT NULL,
+    undone_at DATETIME -- Set when the archived codes were restored
+);
+
+-- 2. Rename the existing `codes` table to prepare for migration
+ALTER TABLE codes RENAME TO codes_old;
+
+-- 3. Create a new `codes` table where only active codes have to be unique
+CREATE TABLE IF NOT EXISTS codes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    code VARCHAR(255) NOT NULL,
+    user_id INTEGER NOT NULL DEFAULT 1, -- Foreign key to the `users` table, defaults to Admin's ID
+    series_id INTEGER NOT NULL DEFAULT 1 REFERENCES series (id), -- Defaults to the default series
+    created_at DATETIME DEFAULT (
+        STRFTIME('%Y-%m-%dT%H:%M:%f', 'NOW') || '+00:00'
+    ) NOT NULL,
+    period_key TEXT, -- Reset period the code was reserved in
+    seq INTEGER, -- Sequence number within the period
+    reset_id INTEGER REFERENCES resets (id) -- Reset which archived the code, NULL while active
+);
+
+-- 4. Copy data from the old `codes` table to the new one
+INSERT INTO
+    codes (id, code, user_id, series_id, created_at, period_key, seq)
+SELECT id, code, user_id, series_id, created_at, period_key, seq
+FROM codes_old;
+
+-- 5. Drop the old table
+DROP TABLE codes_old;
+
+-- 6. Recreate the indexes
+CREATE INDEX IF NOT EXISTS idx_codes_user_id ON codes (user_id);
+
+CREATE INDEX IF NOT EXISTS idx_codes_series_id ON codes (series_id);
+
+CREATE INDEX IF NOT EXISTS idx_codes_sequence ON codes (series_id, period_key, seq);
+
+CREATE INDEX IF NOT EXISTS idx_codes_reset_id ON codes (reset_id);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_codes_active_code ON codes (code)
+WHERE
+    reset_id IS NULL;
@@ -0,0 +1,20 @@
+-- 1. Create the `audit_events` table recording every mutating action
+CREATE TABLE IF NOT EXISTS audit_events (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    created_at DATETIME NOT NULL, -- Time of the action with the configured timezone's offset
+    actor_id INTEGER, -- User who made the change, NULL when not logged in
+    actor_name TEXT, -- Name of the user at the time of the action
+    action TEXT NOT NULL, -- Kind of change, e.g. 'code.reset'
+    target TEXT, -- What was changed, e.g. the user or series name
+    before_json TEXT, -- State before the change, NULL when created
+    after_json TEXT, -- State after the change, NULL when deleted
+    ip TEXT, -- Client address
+    user_agent TEXT -- Client's User-Agent header
+);
+
+-- 2. Add indexes for the filters of the audit log page
+CREATE INDEX IF NOT EXISTS idx_audit_events_action ON audit_events (action);
+
+CREATE INDEX IF NOT EXISTS idx_audit_events_actor_name ON audit_events (actor_name);
+
+CREATE INDEX IF NOT EXISTS idx_audit_events_created_at ON audit_events (created_at);
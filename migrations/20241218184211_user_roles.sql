@@ -0,0 +1,49 @@
+-- 1. Roles a user can be given, their permissions are defined in the application
+CREATE TABLE IF NOT EXISTS roles (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    name TEXT UNIQUE NOT NULL,
+    description TEXT NOT NULL
+);
+
+INSERT INTO
+    roles (name, description)
+VALUES (
+        'viewer',
+        'Looks up and validates numbers'
+    ),
+    (
+        'reserver',
+        'Reserves numbers'
+    ),
+    (
+        'series_manager',
+        'Manages series and resets their counting'
+    ),
+    (
+        'user_admin',
+        'Manages users'
+    ),
+    (
+        'super_admin',
+        'Can do everything'
+    );
+
+-- 2. A user can have any number of roles
+CREATE TABLE IF NOT EXISTS user_roles (
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    role_id INTEGER NOT NULL REFERENCES roles (id) ON DELETE CASCADE,
+    PRIMARY KEY (user_id, role_id)
+);
+
+-- 3. Admins become super admins, everyone else keeps reserving numbers
+INSERT INTO
+    user_roles (user_id, role_id)
+SELECT users.id, roles.id
+FROM users
+    JOIN roles ON roles.name = CASE
+        WHEN users.is_admin = 1 THEN 'super_admin'
+        ELSE 'reserver'
+    END;
+
+-- 4. Roles replace the admin flag
+ALTER TABLE users DROP COLUMN is_admin;
@@ -0,0 +1,18 @@
+-- Personal access tokens for scripts, only a hash of the secret is kept
+CREATE TABLE IF NOT EXISTS api_tokens (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    token_hash TEXT UNIQUE NOT NULL,
+    -- Start of the token, shown to tell tokens apart
+    token_prefix TEXT NOT NULL,
+    -- Comma separated permission names
+    scopes TEXT NOT NULL,
+    created_at DATETIME DEFAULT (
+        STRFTIME('%Y-%m-%dT%H:%M:%f', 'NOW') || '+00:00'
+    ) NOT NULL,
+    expires_at DATETIME,
+    last_used_at DATETIME
+);
+
+CREATE INDEX IF NOT EXISTS idx_api_tokens_user_id ON api_tokens (user_id);
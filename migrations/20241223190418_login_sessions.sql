@@ -0,0 +1,12 @@
+-- Browser logins, a JWT is only accepted while the session named by its `jti` exists
+CREATE TABLE IF NOT EXISTS login_sessions (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    created_at DATETIME NOT NULL,
+    last_seen_at DATETIME NOT NULL,
+    expires_at DATETIME NOT NULL,
+    ip TEXT,
+    user_agent TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_login_sessions_user_id ON login_sessions (user_id);
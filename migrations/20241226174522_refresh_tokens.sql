@@ -0,0 +1,13 @@
+-- Refresh tokens of login sessions, each one is replaced on use
+CREATE TABLE IF NOT EXISTS refresh_tokens (
+    hash TEXT PRIMARY KEY NOT NULL,
+    session_id TEXT NOT NULL REFERENCES login_sessions (id) ON DELETE CASCADE,
+    created_at DATETIME NOT NULL,
+    -- Set when the token was exchanged, using it again means it leaked
+    used_at DATETIME
+);
+
+CREATE INDEX IF NOT EXISTS idx_refresh_tokens_session_id ON refresh_tokens (session_id);
+
+-- Sessions from before can't be refreshed, end them
+DELETE FROM login_sessions;
@@ -0,0 +1,4 @@
+-- Subject of the identity provider account a user logs in with, NULL for local accounts
+ALTER TABLE users ADD COLUMN oidc_subject TEXT;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_users_oidc_subject ON users (oidc_subject);
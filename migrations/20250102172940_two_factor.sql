@@ -0,0 +1,24 @@
+-- Authenticator app secrets, a user has two-factor authentication while a row exists
+CREATE TABLE IF NOT EXISTS user_totp (
+    user_id INTEGER PRIMARY KEY NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    secret TEXT NOT NULL,
+    created_at DATETIME NOT NULL,
+    -- Time step of the last accepted code, every code works only once
+    last_step INTEGER NOT NULL DEFAULT 0
+);
+
+-- Single-use codes for logging in without the authenticator app
+CREATE TABLE IF NOT EXISTS recovery_codes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,
+    used_at DATETIME
+);
+
+CREATE INDEX IF NOT EXISTS idx_recovery_codes_user_id ON recovery_codes (user_id);
+
+-- Settings changed by admins at runtime, missing keys use their defaults
+CREATE TABLE IF NOT EXISTS settings (
+    key TEXT PRIMARY KEY NOT NULL,
+    value TEXT NOT NULL
+);
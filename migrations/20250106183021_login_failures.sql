@@ -0,0 +1,9 @@
+-- Failed logins per username and per client address, for slowing down password guessing
+CREATE TABLE IF NOT EXISTS login_failures (
+    -- `user:<name>` or `ip:<address>`, names that don't exist are counted too
+    key TEXT PRIMARY KEY NOT NULL,
+    failures INTEGER NOT NULL DEFAULT 0,
+    last_failed_at DATETIME NOT NULL,
+    -- No login is checked for the key before this time
+    locked_until DATETIME
+);
@@ -0,0 +1,25 @@
+-- Users still on the published default password have to pick their own before doing anything else
+ALTER TABLE users ADD COLUMN password_change_required INTEGER NOT NULL DEFAULT 0;
+
+-- 1. Fresh installs create their first admin on the setup page instead of using the seeded one
+DELETE FROM user_roles
+WHERE user_id = 1
+    AND (SELECT COUNT(*) FROM users) = 1
+    AND NOT EXISTS (SELECT 1 FROM codes)
+    AND EXISTS (
+        SELECT 1
+        FROM users
+        WHERE id = 1
+            AND password = '$argon2id$v=19$m=19456,t=2,p=1$FPLq4LNUILUFJssUNFtk5Q$MYcXhclig7w+iXKDj30/eyX0T+iK6LLYJVLGLrO9s0Q'
+    );
+
+DELETE FROM users
+WHERE id = 1
+    AND password = '$argon2id$v=19$m=19456,t=2,p=1$FPLq4LNUILUFJssUNFtk5Q$MYcXhclig7w+iXKDj30/eyX0T+iK6LLYJVLGLrO9s0Q'
+    AND (SELECT COUNT(*) FROM users) = 1
+    AND NOT EXISTS (SELECT 1 FROM codes);
+
+-- 2. Installs already in use keep the account, but its password has to change on the next login
+UPDATE users
+SET password_change_required = 1
+WHERE password = '$argon2id$v=19$m=19456,t=2,p=1$FPLq4LNUILUFJssUNFtk5Q$MYcXhclig7w+iXKDj30/eyX0T+iK6LLYJVLGLrO9s0Q';
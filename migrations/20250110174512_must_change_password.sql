@@ -0,0 +1,2 @@
+-- Set by admins too now, not only for the published default password
+ALTER TABLE users RENAME COLUMN password_change_required TO must_change_password;
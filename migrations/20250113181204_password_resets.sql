@@ -0,0 +1,11 @@
+-- One-time links for setting a new password, handed out by an admin, only a hash of the token is kept
+CREATE TABLE IF NOT EXISTS password_resets (
+    token_hash TEXT PRIMARY KEY NOT NULL,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    created_at DATETIME DEFAULT (
+        STRFTIME('%Y-%m-%dT%H:%M:%f', 'NOW') || '+00:00'
+    ) NOT NULL,
+    expires_at DATETIME NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_password_resets_user_id ON password_resets (user_id);
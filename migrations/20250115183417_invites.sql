@@ -0,0 +1,11 @@
+-- Invitations to create an account, the invitee picks the password, only a hash of the token is kept
+CREATE TABLE IF NOT EXISTS invites (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    name TEXT NOT NULL,
+    -- Comma separated role names
+    roles TEXT NOT NULL,
+    token_hash TEXT UNIQUE NOT NULL,
+    created_by INTEGER REFERENCES users (id) ON DELETE SET NULL,
+    created_at DATETIME NOT NULL,
+    expires_at DATETIME NOT NULL
+);
@@ -0,0 +1,2 @@
+-- Deactivated users keep their row and history but can't log in
+ALTER TABLE users ADD COLUMN deactivated INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,48 @@
+-- 1. Deleted users who still own codes keep their row under a tombstone name
+ALTER TABLE users ADD COLUMN deleted_at DATETIME; -- Set when the user was deleted, NULL for everyone else
+
+-- 2. Bring back the users deleted before, so their codes show up again
+INSERT INTO
+    users (id, name, password, deactivated, deleted_at)
+SELECT DISTINCT user_id, 'Deleted user #' || user_id, '', 1, STRFTIME('%Y-%m-%dT%H:%M:%f', 'NOW') || '+00:00'
+FROM codes
+WHERE user_id NOT IN (SELECT id FROM users);
+
+-- 3. Rename the existing `codes` table to prepare for migration
+ALTER TABLE codes RENAME TO codes_old;
+
+-- 4. Create a new `codes` table whose owner can't be deleted from under it
+CREATE TABLE IF NOT EXISTS codes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    code VARCHAR(255) NOT NULL,
+    user_id INTEGER NOT NULL DEFAULT 1 REFERENCES users (id) ON DELETE RESTRICT, -- Reassigned or kept as a tombstone when the user is deleted
+    series_id INTEGER NOT NULL DEFAULT 1 REFERENCES series (id), -- Defaults to the default series
+    created_at DATETIME DEFAULT (
+        STRFTIME('%Y-%m-%dT%H:%M:%f', 'NOW') || '+00:00'
+    ) NOT NULL,
+    period_key TEXT, -- Reset period the code was reserved in
+    seq INTEGER, -- Sequence number within the period
+    reset_id INTEGER REFERENCES resets (id) -- Reset which archived the code, NULL while active
+);
+
+-- 5. Copy data from the old `codes` table to the new one
+INSERT INTO
+    codes (id, code, user_id, series_id, created_at, period_key, seq, reset_id)
+SELECT id, code, user_id, series_id, created_at, period_key, seq, reset_id
+FROM codes_old;
+
+-- 6. Drop the old table
+DROP TABLE codes_old;
+
+-- 7. Recreate the indexes
+CREATE INDEX IF NOT EXISTS idx_codes_user_id ON codes (user_id);
+
+CREATE INDEX IF NOT EXISTS idx_codes_series_id ON codes (series_id);
+
+CREATE INDEX IF NOT EXISTS idx_codes_sequence ON codes (series_id, period_key, seq);
+
+CREATE INDEX IF NOT EXISTS idx_codes_reset_id ON codes (reset_id);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_codes_active_code ON codes (code)
+WHERE
+    reset_id IS NULL;
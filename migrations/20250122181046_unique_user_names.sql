@@ -0,0 +1,5 @@
+-- 1. Two users can't share a name, not even in different case. Tombstones of deleted users don't count.
+--    Fails while a name is still taken twice, the application names the users at startup.
+CREATE UNIQUE INDEX IF NOT EXISTS idx_users_name ON users (name COLLATE NOCASE)
+WHERE
+    deleted_at IS NULL;
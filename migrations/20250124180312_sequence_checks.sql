@@ -0,0 +1,14 @@
+-- 1. Create the `sequence_checks` table listing codes whose sequence number the application still has to read from the code
+CREATE TABLE IF NOT EXISTS sequence_checks (
+    code_id INTEGER PRIMARY KEY NOT NULL REFERENCES codes (id) ON DELETE CASCADE -- Code to check
+);
+
+-- 2. Only codes in the default format end with '.<number>', codes of series with their own format were backfilled by guesswork.
+--    The application parses them with the series format at startup.
+INSERT OR IGNORE INTO sequence_checks (code_id)
+SELECT codes.id
+FROM codes
+    JOIN series ON series.id = codes.series_id
+WHERE
+    codes.seq IS NULL
+    OR series.format IS NOT NULL;
@@ -0,0 +1,21 @@
+-- 1. Recreate `counters` to describe the period key it holds since series reset by period
+CREATE TABLE IF NOT EXISTS counters_new (
+    series_id INTEGER NOT NULL REFERENCES series (id), -- Series the sequence belongs to
+    period_key TEXT NOT NULL, -- Reset period of the sequence, like '2024-12-30' for daily series, empty when it never resets
+    value INTEGER NOT NULL, -- Last allocated sequence number
+    PRIMARY KEY (series_id, period_key)
+);
+
+INSERT INTO
+    counters_new (series_id, period_key, value)
+SELECT series_id, period_key, value
+FROM counters;
+
+DROP TABLE counters;
+
+ALTER TABLE counters_new RENAME TO counters;
+
+-- 2. Period keys were backfilled with the server's local date, the application recomputes them in the series' timezone at startup
+INSERT OR IGNORE INTO sequence_checks (code_id)
+SELECT id
+FROM codes;
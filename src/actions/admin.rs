@@ -1,21 +1,38 @@
 use crate::{
-    db::read_all_users,
-    errors::delete_user::DeleteUserError,
-    forms::CreateUserSchema,
+    actions::{invites::has_pending_invite, password_reset::PASSWORD_RESET_HOURS},
+    audit::{self, AuditAction, AuditEntry, ClientInfo},
+    db::{
+        create_password_reset, delete_login_sessions, read_all_users, read_audit_events,
+        read_invites, read_settings, read_user, save_settings, set_user_deactivated,
+        user_name_exists,
+    },
+    errors::{
+        delete_user::DeleteUserError, update_settings::UpdateSettingsError,
+        update_user::UpdateUserError,
+    },
+    forms::{AuditLogSchema, DeleteUserSchema, SettingsSchema, UpdateUserSchema},
+    jwt::{generate_refresh_token, hash_refresh_token},
     middleware::FROM_PROTECTED_KEY,
-    models::User,
+    models::{AuditFilter, Settings, User},
+    permissions::{permissions_of, Role},
     templates::{
-        admin::{UserManagementTemplate, UserTemplate},
+        admin::{
+            AuditLogTemplate, PasswordResetLinkTemplate, SettingsSectionTemplate, SettingsTemplate,
+            UserDeleteTemplate, UserEditTemplate, UserManagementTemplate, UserTemplate,
+        },
         errors::Error500Template,
         HtmlTemplate,
     },
+    utils::{get_protected, BaseUrl},
 };
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::StatusCode,
     response::{IntoResponse, Response},
-    Extension, Form,
+    Extension,
 };
+use axum_extra::extract::Form;
+use chrono::{Duration, Utc};
 use tower_sessions::Session;
 
 use crate::state::AppState;
@@ -34,33 +51,110 @@ pub async fn get_users(
     let users = read_all_users(&state.db).await.map_err(|e| {
         HtmlTemplate(Error500Template {
             from_protected,
-            is_admin: user.is_admin,
+            permissions: user.permissions,
             reason: format!("Failed to read users: {}", e),
             logged_user: Some(user.name.clone()),
         })
         .into_response()
     })?;
 
+    let invites = read_invites(&state.db, state.timezone).await.map_err(|e| {
+        HtmlTemplate(Error500Template {
+            from_protected,
+            permissions: user.permissions,
+            reason: format!("Failed to read invites: {}", e),
+            logged_user: Some(user.name.clone()),
+        })
+        .into_response()
+    })?;
+
     Ok(HtmlTemplate(UserManagementTemplate {
         from_protected,
-        is_admin: user.is_admin,
+        permissions: user.permissions,
         logged_user: Some(user.name.clone()),
         users,
+        roles: Role::ALL,
+        invites,
+        created: None,
     })
     .into_response())
 }
 
+/// Row of the user turned into the confirmation of deleting them.
+pub async fn confirm_delete_user(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+    Extension(admin): Extension<User>,
+) -> Result<Response, Response> {
+    let user = read_target_user(&state, id).await?;
+    if user.has_role(&Role::SuperAdmin) && !admin.has_role(&Role::SuperAdmin) {
+        Err((
+            StatusCode::FORBIDDEN,
+            "Only super admins can delete super admins",
+        )
+            .into_response())?
+    }
+
+    let users = read_all_users(&state.db).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to read users: {}", e),
+        )
+            .into_response()
+    })?;
+    let users = users.into_iter().filter(|other| other.id != id).collect();
+
+    Ok(HtmlTemplate(UserDeleteTemplate { user, users }).into_response())
+}
+
 pub async fn delete_user(
-    Path(id): Path<u64>,
+    Path(id): Path<i64>,
     State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    client: ClientInfo,
+    Query(query): Query<DeleteUserSchema>,
 ) -> Result<Response, Response> {
-    let result = crate::db::delete_user(&state.db, id as i64).await;
+    let reassign_to = match query.reassign_to.trim() {
+        "" => None,
+        value => Some(value.parse::<i64>().map_err(|_| {
+            (
+                StatusCode::BAD_REQUEST,
+                DeleteUserError::InvalidReassignment.to_string(),
+            )
+                .into_response()
+        })?),
+    };
+
+    let deleted = read_target_user(&state, id).await?;
+    if deleted.has_role(&Role::SuperAdmin) && !user.has_role(&Role::SuperAdmin) {
+        Err((
+            StatusCode::FORBIDDEN,
+            "Only super admins can delete super admins",
+        )
+            .into_response())?
+    }
+
+    let now = Utc::now().with_timezone(&state.timezone).fixed_offset();
+    let result = crate::db::delete_user(&state.db, id, reassign_to, now).await;
 
     match result {
-        Ok(_) => Ok(().into_response()),
+        Ok(_) => {
+            let mut entry = AuditEntry::new(AuditAction::UserDelete)
+                .target(&deleted.name)
+                .before(&deleted);
+            if let Some(reassign_to) = reassign_to {
+                entry = entry.after(&serde_json::json!({ "codes_reassigned_to": reassign_to }));
+            }
+            audit::record(&state, Some(&user), &client, entry).await;
+
+            Ok(().into_response())
+        }
         Err(DeleteUserError::CantDeleteLastAdmin) => {
             Err((StatusCode::BAD_REQUEST, "Can't delete last admin").into_response())
         }
+        Err(e @ DeleteUserError::InvalidReassignment) => {
+            Err((StatusCode::BAD_REQUEST, e.to_string()).into_response())
+        }
         Err(DeleteUserError::DbError(e)) => Err((
             StatusCode::INTERNAL_SERVER_ERROR,
             format!("Failed to delete user: {}", e),
@@ -69,18 +163,399 @@ pub async fn delete_user(
     }
 }
 
-pub async fn create_user(
+pub async fn get_user(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> Result<Response, Response> {
+    let user = read_target_user(&state, id).await?;
+
+    Ok(HtmlTemplate(UserTemplate { user }).into_response())
+}
+
+/// Row of the user as a form for changing the name and roles.
+pub async fn edit_user(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+    Extension(admin): Extension<User>,
+) -> Result<Response, Response> {
+    let user = read_target_user(&state, id).await?;
+
+    if user.has_role(&Role::SuperAdmin) && !admin.has_role(&Role::SuperAdmin) {
+        Err((
+            StatusCode::FORBIDDEN,
+            UpdateUserError::SuperAdminOnly.to_string(),
+        )
+            .into_response())?
+    }
+
+    Ok(HtmlTemplate(UserEditTemplate {
+        name: user.name.clone(),
+        selected: user.roles.clone(),
+        user,
+        roles: Role::ALL,
+        error: None,
+    })
+    .into_response())
+}
+
+pub async fn update_user(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+    Extension(admin): Extension<User>,
+    client: ClientInfo,
+    Form(form): Form<UpdateUserSchema>,
+) -> Result<Response, Response> {
+    let user = read_target_user(&state, id).await?;
+
+    match validate_and_update_user(&state, &admin, &user, &form).await {
+        Ok(updated) => {
+            let entry = AuditEntry::new(AuditAction::UserUpdate)
+                .target(&updated.name)
+                .before(&user)
+                .after(&updated);
+            audit::record(&state, Some(&admin), &client, entry).await;
+
+            Ok(HtmlTemplate(UserTemplate { user: updated }).into_response())
+        }
+        Err(e @ UpdateUserError::SuperAdminOnly) => {
+            Err((StatusCode::FORBIDDEN, e.to_string()).into_response())
+        }
+        Err(UpdateUserError::DbError(e)) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to update user: {}", e),
+        )
+            .into_response()),
+        // The form stays open with what was entered
+        Err(e) => Err(HtmlTemplate(UserEditTemplate {
+            user,
+            name: form.name,
+            selected: form.roles,
+            roles: Role::ALL,
+            error: Some(e.to_string()),
+        })
+        .into_response()),
+    }
+}
+
+/// Saves the name and roles, returns the user as it is now.
+async fn validate_and_update_user(
+    state: &AppState,
+    admin: &User,
+    user: &User,
+    form: &UpdateUserSchema,
+) -> Result<User, UpdateUserError> {
+    // User admins could otherwise hand out more than they have themselves
+    let touches_super_admin =
+        user.has_role(&Role::SuperAdmin) || form.roles.contains(&Role::SuperAdmin);
+    if touches_super_admin && !admin.has_role(&Role::SuperAdmin) {
+        return Err(UpdateUserError::SuperAdminOnly);
+    }
+
+    let name = form.name.trim();
+    if name.is_empty() {
+        return Err(UpdateUserError::EmptyName);
+    }
+    if user_name_exists(&state.db, name, Some(user.id)).await?
+        || has_pending_invite(&state.db, name).await?
+    {
+        return Err(UpdateUserError::NameTaken(name.to_string()));
+    }
+
+    crate::db::update_user(&state.db, user.id, name, &form.roles).await?;
+
+    let mut updated = user.clone();
+    updated.name = name.to_string();
+    updated.permissions = permissions_of(&form.roles);
+    updated.roles = form.roles.clone();
+
+    Ok(updated)
+}
+
+pub async fn deactivate_user(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+    Extension(admin): Extension<User>,
+    client: ClientInfo,
+) -> Result<Response, Response> {
+    set_deactivated(&state, &admin, &client, id, true).await
+}
+
+pub async fn reactivate_user(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+    Extension(admin): Extension<User>,
+    client: ClientInfo,
+) -> Result<Response, Response> {
+    set_deactivated(&state, &admin, &client, id, false).await
+}
+
+async fn set_deactivated(
+    state: &AppState,
+    admin: &User,
+    client: &ClientInfo,
+    id: i64,
+    deactivated: bool,
+) -> Result<Response, Response> {
+    let user = read_target_user(state, id).await?;
+
+    match validate_and_set_deactivated(state, admin, &user, deactivated).await {
+        Ok(()) => {
+            let action = if deactivated {
+                AuditAction::UserDeactivate
+            } else {
+                AuditAction::UserReactivate
+            };
+            audit::record(
+                state,
+                Some(admin),
+                client,
+                AuditEntry::new(action).target(&user.name),
+            )
+            .await;
+
+            let user = User {
+                deactivated,
+                ..user
+            };
+            Ok(HtmlTemplate(UserTemplate { user }).into_response())
+        }
+        Err(e @ UpdateUserError::SuperAdminOnly) => {
+            Err((StatusCode::FORBIDDEN, e.to_string()).into_response())
+        }
+        Err(UpdateUserError::DbError(e)) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to update user: {}", e),
+        )
+            .into_response()),
+        Err(e) => Err((StatusCode::BAD_REQUEST, e.to_string()).into_response()),
+    }
+}
+
+async fn validate_and_set_deactivated(
+    state: &AppState,
+    admin: &User,
+    user: &User,
+    deactivated: bool,
+) -> Result<(), UpdateUserError> {
+    if user.has_role(&Role::SuperAdmin) && !admin.has_role(&Role::SuperAdmin) {
+        return Err(UpdateUserError::SuperAdminOnly);
+    }
+    if deactivated && user.id == admin.id {
+        return Err(UpdateUserError::CantDeactivateSelf);
+    }
+
+    set_user_deactivated(&state.db, user.id, deactivated).await
+}
+
+/// User the admin action is about, the error response when there's none.
+async fn read_target_user(state: &AppState, id: i64) -> Result<User, Response> {
+    match read_user(&state.db, id).await {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => {
+            Err((StatusCode::NOT_FOUND, UpdateUserError::NotFound.to_string()).into_response())
+        }
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to read user: {}", e),
+        )
+            .into_response()),
+    }
+}
+
+/// Creates a one-time link for the user to set a new password, replacing earlier links.
+pub async fn create_user_password_reset(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+    Extension(admin): Extension<User>,
+    base_url: BaseUrl,
+    client: ClientInfo,
+) -> Result<Response, Response> {
+    let user = read_target_user(&state, id).await?;
+
+    // The link is as good as the password, so the same rule as for creating users applies
+    if user.has_role(&Role::SuperAdmin) && !admin.has_role(&Role::SuperAdmin) {
+        Err((
+            StatusCode::FORBIDDEN,
+            "Only super admins can reset passwords of super admins",
+        )
+            .into_response())?
+    }
+
+    let token = generate_refresh_token();
+    let expires_at = Utc::now().with_timezone(&state.timezone).fixed_offset()
+        + Duration::hours(PASSWORD_RESET_HOURS);
+
+    if let Err(e) =
+        create_password_reset(&state.db, user.id, &hash_refresh_token(&token), expires_at).await
+    {
+        Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to create reset link: {}", e),
+        )
+            .into_response())?
+    }
+
+    let entry = AuditEntry::new(AuditAction::PasswordResetCreate)
+        .target(&user.name)
+        .after(&serde_json::json!({ "expires_at": expires_at }));
+    audit::record(&state, Some(&admin), &client, entry).await;
+
+    Ok(HtmlTemplate(PasswordResetLinkTemplate {
+        username: user.name,
+        link: base_url.join(&format!("/reset-password/{}", token)),
+        expires_at: expires_at.format("%Y-%m-%d %H:%M").to_string(),
+    })
+    .into_response())
+}
+
+/// Number of events on one page of the audit log.
+const AUDIT_PAGE_SIZE: i64 = 50;
+
+pub async fn get_audit_log(
+    session: Session,
     State(state): State<AppState>,
-    Form(user): Form<CreateUserSchema>,
+    Extension(user): Extension<User>,
+    Query(query): Query<AuditLogSchema>,
 ) -> Result<Response, Response> {
-    let user = crate::db::create_user(&state.db, user.name, user.password, user.is_admin).await;
+    let from_protected = get_protected(session).await;
+
+    let non_empty = |value: &str| Some(value.trim().to_string()).filter(|v| !v.is_empty());
+    let filter = AuditFilter {
+        actor: non_empty(&query.actor),
+        action: non_empty(&query.action),
+        from: non_empty(&query.from),
+        to: non_empty(&query.to),
+    };
+    let page = query.page.unwrap_or(1).max(1);
+
+    let (events, total) = read_audit_events(
+        &state.db,
+        &filter,
+        AUDIT_PAGE_SIZE,
+        (page - 1) * AUDIT_PAGE_SIZE,
+    )
+    .await
+    .map_err(|e| {
+        HtmlTemplate(Error500Template {
+            from_protected,
+            permissions: user.permissions,
+            reason: format!("Failed to read the audit log: {}", e),
+            logged_user: Some(user.name.clone()),
+        })
+        .into_response()
+    })?;
 
-    match user {
-        Ok(user) => Ok(HtmlTemplate(UserTemplate { user }).into_response()),
+    Ok(HtmlTemplate(AuditLogTemplate {
+        from_protected,
+        permissions: user.permissions,
+        logged_user: Some(user.name.clone()),
+        events,
+        actions: &AuditAction::ALL,
+        actor: query.actor,
+        action: query.action,
+        from: query.from,
+        to: query.to,
+        page,
+        pages: (total + AUDIT_PAGE_SIZE - 1) / AUDIT_PAGE_SIZE,
+    })
+    .into_response())
+}
+
+pub async fn get_settings(
+    session: Session,
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+) -> Result<Response, Response> {
+    let from_protected = get_protected(session).await;
+
+    let settings = read_settings(&state.db).await.map_err(|e| {
+        HtmlTemplate(Error500Template {
+            from_protected,
+            permissions: user.permissions,
+            reason: format!("Failed to read settings: {}", e),
+            logged_user: Some(user.name.clone()),
+        })
+        .into_response()
+    })?;
+
+    Ok(HtmlTemplate(SettingsTemplate {
+        from_protected,
+        permissions: user.permissions,
+        logged_user: Some(user.name.clone()),
+        settings,
+        error: None,
+        saved: false,
+    })
+    .into_response())
+}
+
+pub async fn update_settings(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    client: ClientInfo,
+    Form(form): Form<SettingsSchema>,
+) -> Result<Response, Response> {
+    let settings = Settings {
+        require_admin_two_factor: form.require_admin_two_factor.is_some(),
+    };
+
+    let result = validate_and_save_settings(&state, &user, &settings).await;
+
+    match result {
+        Ok((previous, revoked)) => {
+            let entry = AuditEntry::new(AuditAction::SettingsUpdate)
+                .before(&previous)
+                .after(&serde_json::json!({ "settings": settings, "revoked_sessions": revoked }));
+            audit::record(&state, Some(&user), &client, entry).await;
+
+            Ok(HtmlTemplate(SettingsSectionTemplate {
+                settings,
+                error: None,
+                saved: true,
+            })
+            .into_response())
+        }
+        Err(UpdateSettingsError::OwnTwoFactorMissing) => {
+            Err(HtmlTemplate(SettingsSectionTemplate {
+                settings: Settings {
+                    require_admin_two_factor: false,
+                },
+                error: Some(UpdateSettingsError::OwnTwoFactorMissing.to_string()),
+                saved: false,
+            })
+            .into_response())
+        }
         Err(e) => Err((
             StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to create user: {}", e),
+            format!("Failed to save settings: {}", e),
         )
             .into_response()),
     }
 }
+
+/// Saves the settings, returns the previous ones and how many sessions had to end.
+async fn validate_and_save_settings(
+    state: &AppState,
+    user: &User,
+    settings: &Settings,
+) -> Result<(Settings, u64), UpdateSettingsError> {
+    // Otherwise the admin would lock themselves out on the next login
+    if settings.require_admin_two_factor && !user.two_factor {
+        return Err(UpdateSettingsError::OwnTwoFactorMissing);
+    }
+
+    let previous = read_settings(&state.db).await?;
+    save_settings(&state.db, settings).await?;
+
+    // Admins without two-factor authentication log in again, setting it up on the way
+    let mut revoked = 0;
+    if settings.require_admin_two_factor && !previous.require_admin_two_factor {
+        for admin in read_all_users(&state.db).await? {
+            if admin.is_admin() && !admin.two_factor {
+                revoked += delete_login_sessions(&state.db, admin.id, None).await?;
+            }
+        }
+    }
+
+    Ok((previous, revoked))
+}
@@ -1,69 +1,127 @@
 use crate::{
-    forms::{ChangePasswordSchema, LoginUserSchema},
+    actions::two_factor::{check_second_factor, confirm_two_factor, two_factor_state},
+    audit::{self, AuditAction, AuditEntry, ClientInfo},
+    errors::{
+        check_user_password::CheckUserPasswordError, oidc_login::OidcLoginError,
+        password_change::ChangePasswordError, two_factor::TwoFactorError,
+    },
+    forms::{ChangePasswordSchema, LoginUserSchema, OidcCallbackSchema, TwoFactorCodeSchema},
+    login_throttle::{
+        describe_wait, login_keys, record_success, refund_attempt, start_attempt, Attempt, Limits,
+    },
     models::User,
+    oidc::{PendingLogin, OIDC_LOGIN_KEY},
+    permissions::{permissions_of, Permissions},
     templates::{
         auth::{
             ChangePasswordPageTemplate, ChangePasswordSectionTemplate,
-            ChangePasswordSuccessTemplate, LoginPageTemplate, LoginSectionTemplate,
+            ChangePasswordSuccessTemplate, LoginPageTemplate, LoginRecoveryCodesTemplate,
+            LoginSectionTemplate, LoginTwoFactorPageTemplate, LoginTwoFactorTemplate,
         },
         errors::Error500Template,
         HtmlTemplate,
     },
+    totp::{self, Enrollment},
     utils::get_protected,
 };
 use axum::{
-    extract::State,
-    http::{header::SET_COOKIE, HeaderName},
+    extract::{Query, State},
+    http::{header::SET_COOKIE, HeaderName, StatusCode},
     response::{AppendHeaders, IntoResponse, Redirect, Response},
     Extension, Form,
 };
-use jsonwebtoken::{encode, EncodingKey, Header};
-use tower_sessions::{
-    cookie::{time::Duration, Cookie, SameSite},
-    Session,
-};
-use tracing::error;
+use axum_extra::extract::CookieJar;
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+use tower_sessions::{cookie::Cookie, Session};
+use tracing::{error, warn};
+use url::Url;
 
 use crate::{
-    db::check_email_password, jwt::TokenClaims, middleware::FROM_PROTECTED_KEY, state::AppState,
+    db::{
+        check_email_password, create_login_session, create_oidc_user, delete_login_session,
+        delete_login_session_by_refresh_token, delete_login_sessions, read_settings, read_user,
+        read_user_by_oidc_subject, set_user_roles,
+    },
+    jwt::{
+        access_cookie, decode_access_token, encode_access_token, generate_refresh_token,
+        generate_session_id, hash_refresh_token, refresh_cookie, removed_cookies, ACCESS_COOKIE,
+        LOGIN_SESSION_DAYS, REFRESH_COOKIE,
+    },
+    middleware::{
+        ApiTokenAuth, CurrentSession, CHANGE_PASSWORD_PATH, DEACTIVATED, FROM_PROTECTED_KEY,
+    },
+    models::NewLoginSession,
+    state::AppState,
 };
 
-pub async fn login(session: Session) -> impl IntoResponse {
+/// Session key of the login waiting for the second factor.
+const TWO_FACTOR_LOGIN_KEY: &str = "two_factor_login";
+
+/// Seconds the user has to enter the code after the password.
+const TWO_FACTOR_LOGIN_SECONDS: i64 = 5 * 60;
+
+/// Wrong codes before the password has to be entered again.
+const TWO_FACTOR_LOGIN_ATTEMPTS: u32 = 5;
+
+/// Login with the right password, waiting for the code of the authenticator app.
+#[derive(Debug, Serialize, Deserialize)]
+struct PendingTwoFactor {
+    user_id: i64,
+    started_at: i64,
+    attempts: u32,
+    /// Secret to set up first, when the account has to use two-factor authentication but
+    /// doesn't yet.
+    setup_secret: Option<String>,
+}
+
+pub async fn login(State(state): State<AppState>, session: Session) -> impl IntoResponse {
     let from_protected = get_protected(session).await;
 
     HtmlTemplate(LoginPageTemplate {
         from_protected,
-        is_admin: false,
+        permissions: Permissions::default(),
         username: "".to_string(),
         password: "".to_string(),
         error: None,
         logged_user: None,
+        sso: state.oidc.is_some(),
     })
 }
 
 pub async fn login_post(
     State(state): State<AppState>,
+    session: Session,
+    client: ClientInfo,
     Form(form_data): Form<LoginUserSchema>,
 ) -> Result<Response, Response> {
-    let result = check_email_password(
-        form_data.username.clone(),
-        form_data.password.clone(),
-        &state.db,
-    )
-    .await;
-
     if form_data.username.is_empty() || form_data.password.is_empty() {
         Err(HtmlTemplate(LoginSectionTemplate {
             username: form_data.username.clone(),
-            password: form_data.password,
+            password: form_data.password.clone(),
             error: Some("Username or password cannot be empty".to_string()),
         })
         .into_response())?
     }
 
+    let now = chrono::Utc::now()
+        .with_timezone(&state.timezone)
+        .fixed_offset();
+    let keys = login_keys(&form_data.username, client.ip.as_deref());
+    let attempt = start_throttled_attempt(&state, &keys, now).await?;
+
+    let result = check_email_password(
+        form_data.username.clone(),
+        form_data.password.clone(),
+        &state.db,
+    )
+    .await;
+
     if let Err(err) = result {
-        let err = format!("Something went wrong: {}", err);
-        error!("{}", err);
+        // Wrong passwords stay counted
+        if !matches!(err, CheckUserPasswordError::NotValid) {
+            error!("Something went wrong: {}", err);
+        }
         return Err(HtmlTemplate(LoginSectionTemplate {
             username: form_data.username,
             password: "".to_string(),
@@ -72,74 +130,562 @@ pub async fn login_post(
         .into_response())?;
     }
 
-    let user_id = result.unwrap().id;
+    let user = result.unwrap();
+    refund_throttled_attempt(&state, attempt).await;
 
-    let now = chrono::Utc::now();
-    let iat = now.timestamp() as usize;
-    let exp = (now + chrono::Duration::days(7)).timestamp() as usize;
-    let claims = TokenClaims {
-        sub: user_id.to_string(),
-        exp,
-        iat,
-    };
+    // Only told after the password matched, so it doesn't give away which accounts exist
+    if user.deactivated {
+        return Err(login_section_error(DEACTIVATED));
+    }
 
-    let token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(state.jwt_secret.as_ref()),
-    )
-    .unwrap();
+    match start_two_factor_login(&state, &session, &user).await {
+        Ok(Some(two_factor)) => return Ok(HtmlTemplate(two_factor).into_response()),
+        Ok(None) => {}
+        Err(err) => {
+            error!("Failed to start the second login step: {}", err);
+            return Err(login_section_error("Something went wrong, try again"));
+        }
+    }
 
-    let cookie = Cookie::build(("token", token.to_owned()))
-        .path("/")
-        .max_age(Duration::days(5))
-        .same_site(SameSite::Lax)
-        .http_only(true);
+    if let Err(err) = record_success(&state.db, &user.name).await {
+        error!("Failed to reset failed logins: {}", err);
+    }
+
+    let [access, refresh] = match start_login_session(&state, user.id, client).await {
+        Ok(cookies) => cookies,
+        Err(err) => {
+            error!("Failed to create session: {}", err);
+            return Err(HtmlTemplate(LoginSectionTemplate {
+                username: form_data.username,
+                password: "".to_string(),
+                error: Some("Something went wrong, try again".to_string()),
+            })
+            .into_response())?;
+        }
+    };
 
     let headers = AppendHeaders([
-        (SET_COOKIE, cookie.to_string()),
-        (HeaderName::from_static("hx-redirect"), "/".to_string()),
+        (SET_COOKIE, access.to_string()),
+        (SET_COOKIE, refresh.to_string()),
+        (HeaderName::from_static("hx-redirect"), landing_page(&user)),
     ]);
 
     Ok((headers, ()).into_response())
 }
 
-pub async fn logout_post(session: Session) -> impl IntoResponse {
+/// Keeps the login waiting for the second factor when the user has it or has to set it up.
+///
+/// Returns the form asking for the code, `None` when the login can go on right away.
+async fn start_two_factor_login(
+    state: &AppState,
+    session: &Session,
+    user: &User,
+) -> Result<Option<LoginTwoFactorTemplate>, TwoFactorError> {
+    let settings = read_settings(&state.db).await?;
+
+    let needs_setup = !user.two_factor && settings.require_admin_two_factor && user.is_admin();
+    if !user.two_factor && !needs_setup {
+        return Ok(None);
+    }
+
+    let pending = PendingTwoFactor {
+        user_id: user.id,
+        started_at: chrono::Utc::now().timestamp(),
+        attempts: 0,
+        setup_secret: needs_setup.then(totp::generate_secret),
+    };
+    let enrollment = pending
+        .setup_secret
+        .clone()
+        .map(|secret| Enrollment::new(&user.name, secret));
+
+    session.insert(TWO_FACTOR_LOGIN_KEY, pending).await?;
+
+    Ok(Some(LoginTwoFactorTemplate {
+        enrollment,
+        error: None,
+    }))
+}
+
+/// Where the browser goes after logging in.
+fn landing_page(user: &User) -> String {
+    if user.must_change_password {
+        CHANGE_PASSWORD_PATH.to_string()
+    } else {
+        "/".to_string()
+    }
+}
+
+/// Second login step, checks the code and starts the session.
+pub async fn login_two_factor_post(
+    State(state): State<AppState>,
+    session: Session,
+    client: ClientInfo,
+    Form(form): Form<TwoFactorCodeSchema>,
+) -> Result<Response, Response> {
+    let pending: Option<PendingTwoFactor> = session.get(TWO_FACTOR_LOGIN_KEY).await.ok().flatten();
+    let now = chrono::Utc::now()
+        .with_timezone(&state.timezone)
+        .fixed_offset();
+
+    let Some(mut pending) =
+        pending.filter(|pending| now.timestamp() - pending.started_at <= TWO_FACTOR_LOGIN_SECONDS)
+    else {
+        let _ = session
+            .remove::<PendingTwoFactor>(TWO_FACTOR_LOGIN_KEY)
+            .await;
+        return Err(login_section_error("The login expired, log in again"));
+    };
+
+    let user = match read_user(&state.db, pending.user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return Err(login_section_error("The login expired, log in again")),
+        Err(err) => {
+            error!("Failed to read user: {}", err);
+            return Err(login_section_error("Something went wrong, try again"));
+        }
+    };
+
+    // Wrong codes count like wrong passwords, the password alone mustn't allow guessing codes
+    let keys = login_keys(&user.name, client.ip.as_deref());
+    let attempt = start_throttled_attempt(&state, &keys, now).await?;
+
+    let result = match &pending.setup_secret {
+        Some(secret) => confirm_two_factor(&state.db, &user, secret, &form.code, now)
+            .await
+            .map(Some),
+        None => match check_second_factor(&state.db, user.id, &form.code, now).await {
+            Ok(true) => Ok(None),
+            Ok(false) => Err(TwoFactorError::InvalidCode),
+            Err(err) => Err(err.into()),
+        },
+    };
+
+    let recovery_codes = match result {
+        Ok(recovery_codes) => recovery_codes,
+        Err(TwoFactorError::InvalidCode) => {
+            pending.attempts += 1;
+            if pending.attempts >= TWO_FACTOR_LOGIN_ATTEMPTS {
+                let _ = session
+                    .remove::<PendingTwoFactor>(TWO_FACTOR_LOGIN_KEY)
+                    .await;
+                return Err(login_section_error("Too many wrong codes, log in again"));
+            }
+
+            let enrollment = pending
+                .setup_secret
+                .clone()
+                .map(|secret| Enrollment::new(&user.name, secret));
+            if let Err(err) = session.insert(TWO_FACTOR_LOGIN_KEY, pending).await {
+                error!("Failed to keep the login in the session: {}", err);
+                return Err(login_section_error("Something went wrong, try again"));
+            }
+
+            return Err(HtmlTemplate(LoginTwoFactorTemplate {
+                enrollment,
+                error: Some(TwoFactorError::InvalidCode.to_string()),
+            })
+            .into_response());
+        }
+        Err(err) => {
+            error!("Failed to check the second factor: {}", err);
+            return Err(login_section_error("Something went wrong, try again"));
+        }
+    };
+
+    let _ = session
+        .remove::<PendingTwoFactor>(TWO_FACTOR_LOGIN_KEY)
+        .await;
+    refund_throttled_attempt(&state, attempt).await;
+    if let Err(err) = record_success(&state.db, &user.name).await {
+        error!("Failed to reset failed logins: {}", err);
+    }
+
+    let [access, refresh] = match start_login_session(&state, user.id, client.clone()).await {
+        Ok(cookies) => cookies,
+        Err(err) => {
+            error!("Failed to create session: {}", err);
+            return Err(login_section_error("Something went wrong, try again"));
+        }
+    };
+    let cookies = [
+        (SET_COOKIE, access.to_string()),
+        (SET_COOKIE, refresh.to_string()),
+    ];
+
+    match recovery_codes {
+        // Set up during the login, the codes have to be shown before going on
+        Some(recovery_codes) => {
+            let entry = AuditEntry::new(AuditAction::TwoFactorEnable).target(&user.name);
+            audit::record(&state, Some(&user), &client, entry).await;
+
+            Ok((
+                AppendHeaders(cookies),
+                HtmlTemplate(LoginRecoveryCodesTemplate { recovery_codes }),
+            )
+                .into_response())
+        }
+        None => {
+            let redirect = [(HeaderName::from_static("hx-redirect"), landing_page(&user))];
+
+            Ok((AppendHeaders(cookies), AppendHeaders(redirect), ()).into_response())
+        }
+    }
+}
+
+/// Counts the login attempt as failed until it turns out right, refuses it while the username or
+/// address has to wait after failed ones.
+async fn start_throttled_attempt(
+    state: &AppState,
+    keys: &[(String, Limits)],
+    now: DateTime<FixedOffset>,
+) -> Result<Attempt, Response> {
+    match start_attempt(&state.db, keys, now).await {
+        Ok(Ok(attempt)) => Ok(attempt),
+        Ok(Err(until)) => Err(login_section_error(&format!(
+            "Too many failed logins, try again in {}",
+            describe_wait(now, until)
+        ))),
+        Err(err) => {
+            error!("Failed to count the login attempt: {}", err);
+            Err(login_section_error("Something went wrong, try again"))
+        }
+    }
+}
+
+/// Takes back the failure counted for an attempt with the right password or code.
+async fn refund_throttled_attempt(state: &AppState, attempt: Attempt) {
+    if let Err(err) = refund_attempt(&state.db, attempt).await {
+        error!("Failed to take back the counted login attempt: {}", err);
+    }
+}
+
+/// Login form showing the error, for starting over with the password.
+fn login_section_error(error: &str) -> Response {
+    HtmlTemplate(LoginSectionTemplate {
+        username: "".to_string(),
+        password: "".to_string(),
+        error: Some(error.to_string()),
+    })
+    .into_response()
+}
+
+/// Starts a login session for the user, returning the cookies with its tokens.
+pub async fn start_login_session(
+    state: &AppState,
+    user_id: i64,
+    client: ClientInfo,
+) -> sqlx::Result<[Cookie<'static>; 2]> {
+    let now = chrono::Utc::now()
+        .with_timezone(&state.timezone)
+        .fixed_offset();
+    let expires_at = now + chrono::Duration::days(LOGIN_SESSION_DAYS);
+    let session_id = generate_session_id();
+    let refresh_token = generate_refresh_token();
+
+    let login_session = NewLoginSession {
+        id: &session_id,
+        user_id,
+        created_at: now,
+        expires_at,
+        ip: client.ip,
+        user_agent: client.user_agent,
+        refresh_hash: hash_refresh_token(&refresh_token),
+    };
+    create_login_session(&state.db, &login_session).await?;
+
+    let access_token = encode_access_token(&state.jwt_secret, user_id, &session_id);
+
+    Ok([access_cookie(access_token), refresh_cookie(refresh_token)])
+}
+
+/// Sends the user to the identity provider to log in.
+pub async fn oidc_login(
+    State(state): State<AppState>,
+    session: Session,
+) -> Result<Response, Response> {
+    let result = start_oidc_login(&state, &session).await;
+
+    match result {
+        Ok(url) => Ok(Redirect::to(url.as_str()).into_response()),
+        Err(err) => {
+            error!("Failed to start single sign-on: {}", err);
+            Err(login_failed(&state, err))
+        }
+    }
+}
+
+async fn start_oidc_login(state: &AppState, session: &Session) -> Result<Url, OidcLoginError> {
+    let oidc = state.oidc.as_ref().ok_or(OidcLoginError::NotConfigured)?;
+
+    let metadata = oidc.discover().await?;
+    let login = PendingLogin::new();
+    let url = oidc.authorization_url(&metadata, &login)?;
+
+    session.insert(OIDC_LOGIN_KEY, login).await?;
+
+    Ok(url)
+}
+
+/// Logs in the user the identity provider sent back, creating the account on the first visit.
+pub async fn oidc_callback(
+    State(state): State<AppState>,
+    session: Session,
+    client: ClientInfo,
+    Query(query): Query<OidcCallbackSchema>,
+) -> Result<Response, Response> {
+    let user = finish_oidc_login(&state, &session, &client, query)
+        .await
+        .map_err(|err| {
+            error!("Single sign-on failed: {}", err);
+            login_failed(&state, err)
+        })?;
+
+    // The identity provider only stands in for the password
+    match start_two_factor_login(&state, &session, &user).await {
+        Ok(Some(two_factor)) => {
+            return Ok(HtmlTemplate(LoginTwoFactorPageTemplate {
+                from_protected: false,
+                permissions: Permissions::default(),
+                logged_user: None,
+                enrollment: two_factor.enrollment,
+                error: None,
+            })
+            .into_response())
+        }
+        Ok(None) => {}
+        Err(err) => {
+            error!("Failed to start the second login step: {}", err);
+            return Err(login_page_error(&state, "Something went wrong, try again"));
+        }
+    }
+
+    let [access, refresh] = start_login_session(&state, user.id, client)
+        .await
+        .map_err(|err| {
+            error!("Failed to create session: {}", err);
+            login_failed(&state, OidcLoginError::DbError(err))
+        })?;
+
+    let headers = AppendHeaders([
+        (SET_COOKIE, access.to_string()),
+        (SET_COOKIE, refresh.to_string()),
+    ]);
+
+    Ok((headers, Redirect::to("/")).into_response())
+}
+
+async fn finish_oidc_login(
+    state: &AppState,
+    session: &Session,
+    client: &ClientInfo,
+    query: OidcCallbackSchema,
+) -> Result<User, OidcLoginError> {
+    let oidc = state.oidc.as_ref().ok_or(OidcLoginError::NotConfigured)?;
+
+    // Taken out right away, every login attempt can only come back once
+    let login: Option<PendingLogin> = session.remove(OIDC_LOGIN_KEY).await?;
+
+    if let Some(error) = query.error {
+        return Err(OidcLoginError::Refused(
+            query.error_description.unwrap_or(error),
+        ));
+    }
+
+    let login = login
+        .filter(|login| query.state.as_ref() == Some(&login.state))
+        .ok_or(OidcLoginError::StateMismatch)?;
+    let code = query
+        .code
+        .ok_or_else(|| OidcLoginError::Refused("no authorization code".to_string()))?;
+
+    let metadata = oidc.discover().await?;
+    let claims = oidc.exchange_code(&metadata, &login, &code).await?;
+    let roles = oidc.roles(&claims);
+
+    // The identity provider decides the roles, they are updated on every login
+    let user = match read_user_by_oidc_subject(&state.db, &claims.sub).await? {
+        Some(previous) if previous.deactivated => return Err(OidcLoginError::Deactivated),
+        Some(previous) => {
+            if previous.roles != roles {
+                let synced = set_user_roles(&state.db, previous.id, &roles).await?;
+                if !synced.iter().all(|role| roles.contains(role)) {
+                    warn!(
+                        "Kept the super admin role of '{}', they are the last super admin",
+                        previous.name
+                    );
+                }
+
+                let mut user = previous.clone();
+                user.permissions = permissions_of(&synced);
+                user.roles = synced;
+
+                // Only the kept role differed, nothing changed
+                let changed = user.roles.len() != previous.roles.len()
+                    || !user.roles.iter().all(|role| previous.roles.contains(role));
+                if changed {
+                    let entry = AuditEntry::new(AuditAction::UserRolesSync)
+                        .target(&user.name)
+                        .before(&previous)
+                        .after(&user);
+                    audit::record(state, Some(&user), client, entry).await;
+                }
+
+                user
+            } else {
+                previous
+            }
+        }
+        // No account for people who couldn't use it anyway
+        None if roles.is_empty() => return Err(OidcLoginError::NoRoles),
+        None => {
+            let user = create_oidc_user(&state.db, claims.username(), &claims.sub, &roles).await?;
+
+            let entry = AuditEntry::new(AuditAction::UserCreate)
+                .target(&user.name)
+                .after(&user);
+            audit::record(state, Some(&user), client, entry).await;
+
+            user
+        }
+    };
+
+    if user.roles.is_empty() {
+        return Err(OidcLoginError::NoRoles);
+    }
+
+    Ok(user)
+}
+
+/// Login page showing why single sign-on failed.
+fn login_failed(state: &AppState, err: OidcLoginError) -> Response {
+    let error = match err {
+        OidcLoginError::DbError(_) | OidcLoginError::Session(_) => {
+            "Something went wrong, try again".to_string()
+        }
+        err => err.to_string(),
+    };
+
+    login_page_error(state, &error)
+}
+
+/// Login page showing the error, for starting over.
+fn login_page_error(state: &AppState, error: &str) -> Response {
+    HtmlTemplate(LoginPageTemplate {
+        from_protected: false,
+        permissions: Permissions::default(),
+        username: "".to_string(),
+        password: "".to_string(),
+        error: Some(error.to_string()),
+        logged_user: None,
+        sso: state.oidc.is_some(),
+    })
+    .into_response()
+}
+
+pub async fn logout_post(
+    State(state): State<AppState>,
+    session: Session,
+    cookie_jar: CookieJar,
+) -> impl IntoResponse {
     session.insert(FROM_PROTECTED_KEY, false).await.unwrap();
 
-    let cookie = Cookie::build(("token", ""))
-        .path("/")
-        .max_age(Duration::hours(-1))
-        .same_site(SameSite::Lax)
-        .http_only(true);
+    // End the session on the server too, the cookies alone could have been copied
+    let claims = cookie_jar
+        .get(ACCESS_COOKIE)
+        .and_then(|cookie| decode_access_token(&state.jwt_secret, cookie.value(), true));
+    let result = match (claims, cookie_jar.get(REFRESH_COOKIE)) {
+        (Some(claims), _) => {
+            let user_id = claims.sub.parse().unwrap_or_default();
+            delete_login_session(&state.db, user_id, &claims.jti)
+                .await
+                .map(|_| ())
+        }
+        (None, Some(cookie)) => {
+            delete_login_session_by_refresh_token(&state.db, &hash_refresh_token(cookie.value()))
+                .await
+        }
+        (None, None) => Ok(()),
+    };
+    if let Err(err) = result {
+        error!("Failed to end session: {}", err);
+    }
 
-    let headers = AppendHeaders([(SET_COOKIE, cookie.to_string())]);
+    let headers = removed_cookies().map(|cookie| (SET_COOKIE, cookie.to_string()));
 
-    (headers, Redirect::to("/login"))
+    (AppendHeaders(headers), Redirect::to("/login"))
 }
 
 pub async fn change_password(
     session: Session,
+    State(state): State<AppState>,
     Extension(user): Extension<User>,
-) -> impl IntoResponse {
+    token_auth: Option<Extension<ApiTokenAuth>>,
+) -> Result<Response, Response> {
+    if token_auth.is_some() {
+        let reason = ChangePasswordError::ChangedWithToken.to_string();
+        return Err((StatusCode::FORBIDDEN, reason).into_response());
+    }
+
     let from_protected = get_protected(session).await;
 
-    HtmlTemplate(ChangePasswordPageTemplate {
+    let two_factor = two_factor_state(&state.db, &user).await.map_err(|e| {
+        HtmlTemplate(Error500Template {
+            from_protected,
+            permissions: user.permissions,
+            reason: format!("Failed to read two-factor authentication: {}", e),
+            logged_user: Some(user.name.clone()),
+        })
+        .into_response()
+    })?;
+
+    Ok(HtmlTemplate(ChangePasswordPageTemplate {
         logged_user: Some(user.name.clone()),
         from_protected,
-        is_admin: user.is_admin,
+        permissions: user.permissions,
         error: None,
+        must_change_password: user.must_change_password,
+        two_factor,
+        two_factor_error: None,
     })
+    .into_response())
+}
+
+/// Checks a new password against the retyped one and the password policy.
+pub fn validate_new_password(
+    state: &AppState,
+    username: &str,
+    new_password: &str,
+    retype_password: &str,
+) -> Result<(), ChangePasswordError> {
+    if new_password != retype_password {
+        return Err(ChangePasswordError::PasswordMismatch);
+    }
+
+    state.password_policy.check(new_password, &[username])?;
+
+    Ok(())
 }
 
 pub async fn change_password_post(
     session: Session,
     State(state): State<AppState>,
     Extension(user): Extension<User>,
+    current_session: Option<Extension<CurrentSession>>,
+    token_auth: Option<Extension<ApiTokenAuth>>,
+    client: ClientInfo,
     Form(form): Form<ChangePasswordSchema>,
 ) -> Result<Response, Response> {
     let from_protected = get_protected(session).await;
 
+    // A leaked token must not be enough to lock the owner out
+    if token_auth.is_some() {
+        Err(HtmlTemplate(ChangePasswordSectionTemplate {
+            error: Some(ChangePasswordError::ChangedWithToken.to_string()),
+        })
+        .into_response())?
+    }
+
     // Check if the old password is correct
     let is_valid = check_email_password(user.name.clone(), form.old_password.clone(), &state.db)
         .await
@@ -162,10 +708,14 @@ pub async fn change_password_post(
         .into_response())?
     }
 
-    // Check if the new password and retype password are the same
-    if form.new_password != form.retype_password {
+    if let Err(e) = validate_new_password(
+        &state,
+        &user.name,
+        &form.new_password,
+        &form.retype_password,
+    ) {
         Err(HtmlTemplate(ChangePasswordSectionTemplate {
-            error: Some("New password and retype password do not match".to_string()),
+            error: Some(e.to_string()),
         })
         .into_response())?
     }
@@ -180,7 +730,7 @@ pub async fn change_password_post(
         let err = format!("Something went wrong: {}", err);
         error!("{}", err);
         Err(HtmlTemplate(Error500Template {
-            is_admin: user.is_admin,
+            permissions: user.permissions,
             from_protected,
             reason: "Failed to change password".to_string(),
             logged_user: Some(user.name.clone()),
@@ -188,5 +738,17 @@ pub async fn change_password_post(
         .into_response())?
     }
 
+    // Whoever knew the old password must not stay logged in elsewhere
+    let current_session = current_session.map(|Extension(CurrentSession(id))| id);
+    let revoked = delete_login_sessions(&state.db, user.id, current_session.as_deref()).await;
+    if let Err(err) = &revoked {
+        error!("Failed to end other sessions: {}", err);
+    }
+
+    let entry = AuditEntry::new(AuditAction::PasswordChange)
+        .target(&user.name)
+        .after(&serde_json::json!({ "revoked_sessions": revoked.unwrap_or_default() }));
+    audit::record(&state, Some(&user), &client, entry).await;
+
     Ok(HtmlTemplate(ChangePasswordSuccessTemplate {}).into_response())
 }
@@ -1,58 +1,275 @@
 use crate::{
-    errors::add_number::AddNumberError,
-    models::User,
+    audit::{self, AuditAction, AuditEntry, ClientInfo},
+    code_format::CodeScheme,
+    db::{read_code_series, read_last_ten, read_series},
+    errors::{add_number::AddNumberError, reset_codes::ResetCodesError},
+    forms::{ReserveBatchSchema, ResetCodesSchema, SelectSeriesSchema, ValidateCodeSchema},
+    models::{CodeValidation, ResetScope, User, DEFAULT_SERIES_ID},
     templates::{
         codes::{CodeItemTemplate, IndexSectionTemplate},
         HtmlTemplate,
     },
 };
 use axum::{
-    extract::State,
+    extract::{Query, State},
     http::StatusCode,
     response::{IntoResponse, Response},
-    Extension,
+    Extension, Form, Json,
 };
-use chrono::{DateTime, Local};
 
-use crate::{db::create_code, state::AppState};
+use serde_json::json;
+use tracing::info;
+
+use crate::{
+    db::{create_code, create_codes},
+    state::AppState,
+};
+
+pub async fn list_codes(
+    State(state): State<AppState>,
+    Query(query): Query<SelectSeriesSchema>,
+) -> Result<Response, Response> {
+    list_section(&state, query.series_id).await
+}
 
 pub async fn add_code(
     State(state): State<AppState>,
     Extension(user): Extension<User>,
+    client: ClientInfo,
+    form: Option<Form<SelectSeriesSchema>>,
 ) -> Result<Response, Response> {
-    let current_local: DateTime<Local> = Local::now();
-    let code = current_local.format("V%Y%m%d").to_string();
+    let series_id = form
+        .and_then(|Form(form)| form.series_id)
+        .unwrap_or(DEFAULT_SERIES_ID);
 
     // Create the new number
-    let created_code = create_code(&state.db, &code, &user.id.to_string()).await;
+    let created_code = match resolve_scheme(&state, series_id).await {
+        Ok(scheme) => create_code(&state.db, &scheme, scheme.now(), &user.id.to_string()).await,
+        Err(e) => Err(e),
+    };
 
     match created_code {
-        Err(AddNumberError::DuplicateCode(e)) => {
-            Err((StatusCode::CONFLICT, format!("Duplicate code: {}", e)).into_response())
+        Err(e) => Err(add_number_error_response(e)),
+        Ok(code) => {
+            let entry = AuditEntry::new(AuditAction::CodeReserve)
+                .target(&code.code)
+                .after(&code);
+            audit::record(&state, Some(&user), &client, entry).await;
+
+            Ok(HtmlTemplate(CodeItemTemplate { codes: vec![code] }).into_response())
         }
-        Err(AddNumberError::DbError(e)) => Err((
+    }
+}
+
+pub async fn add_codes(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    client: ClientInfo,
+    Form(form): Form<ReserveBatchSchema>,
+) -> Result<Response, Response> {
+    let series_id = form.series_id.unwrap_or(DEFAULT_SERIES_ID);
+
+    if form.count == 0 || form.count > state.max_batch_size {
+        return Err(add_number_error_response(AddNumberError::InvalidBatchSize(
+            state.max_batch_size,
+        )));
+    }
+
+    // Create the whole block at once
+    let created_codes = match resolve_scheme(&state, series_id).await {
+        Ok(scheme) => {
+            create_codes(
+                &state.db,
+                &scheme,
+                scheme.now(),
+                &user.id.to_string(),
+                form.count,
+            )
+            .await
+        }
+        Err(e) => Err(e),
+    };
+
+    match created_codes {
+        Err(e) => Err(add_number_error_response(e)),
+        Ok(codes) => {
+            let range = match (codes.first(), codes.last()) {
+                (Some(first), Some(last)) => format!("{} - {}", first.code, last.code),
+                _ => String::new(),
+            };
+            let entry = AuditEntry::new(AuditAction::CodeReserveBatch)
+                .target(range)
+                .after(&codes);
+            audit::record(&state, Some(&user), &client, entry).await;
+
+            Ok(HtmlTemplate(CodeItemTemplate { codes }).into_response())
+        }
+    }
+}
+
+fn add_number_error_response(e: AddNumberError) -> Response {
+    match e {
+        AddNumberError::DuplicateCode(e) => {
+            (StatusCode::CONFLICT, format!("Duplicate code: {}", e)).into_response()
+        }
+        e @ AddNumberError::AllocationConflict(_) => {
+            (StatusCode::SERVICE_UNAVAILABLE, e.to_string()).into_response()
+        }
+        e @ AddNumberError::SeriesNotFound(_) => {
+            (StatusCode::NOT_FOUND, e.to_string()).into_response()
+        }
+        e @ (AddNumberError::SeriesInactive(_) | AddNumberError::InvalidBatchSize(_)) => {
+            (StatusCode::BAD_REQUEST, e.to_string()).into_response()
+        }
+        AddNumberError::InvalidFormat(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Series has an invalid format: {}", e),
+        )
+            .into_response(),
+        AddNumberError::DbError(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             format!("Failed to create number: {}", e),
+        )
+            .into_response(),
+    }
+}
+
+/// Loads the series a reservation is made in and checks it can be used.
+async fn resolve_scheme(state: &AppState, series_id: i64) -> Result<CodeScheme, AddNumberError> {
+    let series = read_series(&state.db, series_id)
+        .await?
+        .ok_or(AddNumberError::SeriesNotFound(series_id))?;
+
+    if !series.is_active {
+        return Err(AddNumberError::SeriesInactive(series.name));
+    }
+
+    Ok(series.scheme(&state.code_prefix, &state.code_format, state.timezone)?)
+}
+
+/// Verifies the check digit of a code and looks it up in the database.
+///
+/// The series is the one the code was reserved in, the requested one for unknown codes.
+pub async fn validate_code(
+    State(state): State<AppState>,
+    Query(query): Query<ValidateCodeSchema>,
+) -> Result<Response, Response> {
+    let code = query.code.trim();
+
+    let stored_series = read_code_series(&state.db, code).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to read code: {}", e),
+        )
+            .into_response()
+    })?;
+
+    let series_id = stored_series
+        .or(query.series_id)
+        .unwrap_or(DEFAULT_SERIES_ID);
+    let series = match read_series(&state.db, series_id).await {
+        Ok(Some(series)) => series,
+        Ok(None) => Err(add_number_error_response(AddNumberError::SeriesNotFound(
+            series_id,
+        )))?,
+        Err(e) => Err(add_number_error_response(e.into()))?,
+    };
+    let scheme = series
+        .scheme(&state.code_prefix, &state.code_format, state.timezone)
+        .map_err(|e| add_number_error_response(e.into()))?;
+
+    Ok(Json(CodeValidation {
+        code: code.to_string(),
+        exists: stored_series.is_some(),
+        series: series.name,
+        check_digit: scheme.check_digit.map(|c| c.to_string()),
+        check_digit_valid: scheme.check_digit.map(|c| c.verify(code)),
+    })
+    .into_response())
+}
+
+pub async fn reset_codes(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    client: ClientInfo,
+    form: Option<Form<ResetCodesSchema>>,
+) -> Result<Response, Response> {
+    let scope = form
+        .map(|Form(form)| ResetScope {
+            series_id: form.series_id,
+            from: form.from,
+            to: form.to,
+        })
+        .unwrap_or_default();
+
+    let reset = crate::db::reset_codes(&state.db, user.id, &scope).await;
+
+    match reset {
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to reset codes: {}", e),
         )
             .into_response()),
-        Err(AddNumberError::ParseSuffixError(e)) => Err((
+        Ok(archived) => {
+            info!("User {} archived {} codes", user.id, archived);
+
+            let target = match scope.series_id {
+                Some(series_id) => format!("series {}", series_id),
+                None => "all series".to_string(),
+            };
+            let entry = AuditEntry::new(AuditAction::CodeReset)
+                .target(target)
+                .after(&json!({ "scope": scope, "archived": archived }));
+            audit::record(&state, Some(&user), &client, entry).await;
+
+            list_section(&state, scope.series_id).await
+        }
+    }
+}
+
+pub async fn undo_reset(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    client: ClientInfo,
+    form: Option<Form<SelectSeriesSchema>>,
+) -> Result<Response, Response> {
+    let series_id = form.and_then(|Form(form)| form.series_id);
+
+    match crate::db::undo_last_reset(&state.db).await {
+        Err(e @ (ResetCodesError::NothingToUndo | ResetCodesError::UndoConflict)) => {
+            Err((StatusCode::CONFLICT, e.to_string()).into_response())
+        }
+        Err(e) => Err((
             StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to parse the suffix: {}", e),
+            format!("Failed to undo the reset: {}", e),
         )
             .into_response()),
-        Ok(code) => Ok(HtmlTemplate(CodeItemTemplate { code }).into_response()),
+        Ok(restored) => {
+            info!("User {} restored {} codes", user.id, restored);
+
+            let entry =
+                AuditEntry::new(AuditAction::CodeResetUndo).after(&json!({ "restored": restored }));
+            audit::record(&state, Some(&user), &client, entry).await;
+
+            list_section(&state, series_id).await
+        }
     }
 }
 
-pub async fn reset_codes(State(state): State<AppState>) -> Result<Response, Response> {
-    let created_code = crate::db::reset_codes(&state.db).await;
+/// Renders the codes list of the series shown on the dashboard.
+async fn list_section(state: &AppState, series_id: Option<i64>) -> Result<Response, Response> {
+    let series_id = series_id.unwrap_or(DEFAULT_SERIES_ID);
+    let timezone = match read_series(&state.db, series_id).await {
+        Ok(Some(series)) => series.display_timezone(state.timezone),
+        _ => state.timezone,
+    };
 
-    match created_code {
+    match read_last_ten(&state.db, series_id, timezone).await {
         Err(e) => Err((
             StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to reset codes: {}", e),
+            format!("Failed to read codes: {}", e),
         )
             .into_response()),
-        Ok(_) => Ok(HtmlTemplate(IndexSectionTemplate { codes: vec![] }).into_response()),
+        Ok(codes) => Ok(HtmlTemplate(IndexSectionTemplate { codes }).into_response()),
     }
 }
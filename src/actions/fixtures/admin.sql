@@ -0,0 +1,13 @@
+INSERT INTO
+    users (id, name, password)
+VALUES (
+        1,
+        'Admin',
+        '$argon2id$v=19$m=19456,t=2,p=1$FPLq4LNUILUFJssUNFtk5Q$MYcXhclig7w+iXKDj30/eyX0T+iK6LLYJVLGLrO9s0Q'
+    );
+
+INSERT INTO
+    user_roles (user_id, role_id)
+SELECT 1, id
+FROM roles
+WHERE name = 'super_admin';
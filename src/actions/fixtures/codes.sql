@@ -1,25 +1,25 @@
-INSERT INTO codes (code) VALUES ('V20240101.1');
+INSERT INTO codes (code, period_key, seq) VALUES ('V20240101.1', '2024-01-01', 1);
 
-INSERT INTO codes (code) VALUES ('V20240101.2');
+INSERT INTO codes (code, period_key, seq) VALUES ('V20240101.2', '2024-01-01', 2);
 
-INSERT INTO codes (code) VALUES ('V20240101.3');
+INSERT INTO codes (code, period_key, seq) VALUES ('V20240101.3', '2024-01-01', 3);
 
-INSERT INTO codes (code) VALUES ('V20240102.1');
+INSERT INTO codes (code, period_key, seq) VALUES ('V20240102.1', '2024-01-02', 1);
 
-INSERT INTO codes (code) VALUES ('V20240102.2');
+INSERT INTO codes (code, period_key, seq) VALUES ('V20240102.2', '2024-01-02', 2);
 
-INSERT INTO codes (code) VALUES ('V20240103.3');
+INSERT INTO codes (code, period_key, seq) VALUES ('V20240103.3', '2024-01-03', 3);
 
-INSERT INTO codes (code) VALUES ('V20240104.1');
+INSERT INTO codes (code, period_key, seq) VALUES ('V20240104.1', '2024-01-04', 1);
 
-INSERT INTO codes (code) VALUES ('V20240104.2');
+INSERT INTO codes (code, period_key, seq) VALUES ('V20240104.2', '2024-01-04', 2);
 
-INSERT INTO codes (code) VALUES ('V20240105.1');
+INSERT INTO codes (code, period_key, seq) VALUES ('V20240105.1', '2024-01-05', 1);
 
-INSERT INTO codes (code) VALUES ('V20240105.2');
+INSERT INTO codes (code, period_key, seq) VALUES ('V20240105.2', '2024-01-05', 2);
 
-INSERT INTO codes (code) VALUES ('V20240106.1');
+INSERT INTO codes (code, period_key, seq) VALUES ('V20240106.1', '2024-01-06', 1);
 
-INSERT INTO codes (code) VALUES ('V20240106.3');
+INSERT INTO codes (code, period_key, seq) VALUES ('V20240106.3', '2024-01-06', 3);
 
-INSERT INTO codes (code) VALUES ('V20240106.7');
\ No newline at end of file
+INSERT INTO codes (code, period_key, seq) VALUES ('V20240106.7', '2024-01-06', 7);
\ No newline at end of file
@@ -1,3 +1,9 @@
 INSERT INTO
-    users (id, name, password, is_admin)
-VALUES (2, 'Alice', 'pass', 0);
\ No newline at end of file
+    users (id, name, password)
+VALUES (2, 'Alice', 'pass');
+
+INSERT INTO
+    user_roles (user_id, role_id)
+SELECT 2, id
+FROM roles
+WHERE name = 'reserver';
\ No newline at end of file
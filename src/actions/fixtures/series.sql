@@ -0,0 +1,11 @@
+INSERT INTO
+    series (id, name, prefix, format, reset_period, description)
+VALUES (2, 'Scripts', 'S', '{prefix}-{seq:03}', 'never', 'Script numbers');
+
+INSERT INTO
+    series (id, name, description, is_active)
+VALUES (3, 'Archive', 'Old numbers', 0);
+
+INSERT INTO codes (code, series_id, period_key, seq) VALUES ('S-001', 2, '', 1);
+
+INSERT INTO codes (code, series_id, period_key, seq) VALUES ('S-002', 2, '', 2);
@@ -0,0 +1,290 @@
+use crate::{
+    actions::auth::{start_login_session, validate_new_password},
+    audit::{self, AuditAction, AuditEntry, ClientInfo},
+    db::{
+        accept_invite, create_invite, delete_invite, read_invite, read_invite_by_hash,
+        read_invites, user_name_exists,
+    },
+    errors::{
+        create_user::CreateUserError, invite::InviteError, password_change::ChangePasswordError,
+    },
+    forms::{AcceptInviteSchema, CreateInviteSchema},
+    jwt::{generate_refresh_token, hash_refresh_token},
+    models::{Invite, NewInvite, User},
+    permissions::{Permissions, Role},
+    templates::{
+        admin::InvitesSectionTemplate,
+        auth::{InvitePageTemplate, InviteSectionTemplate},
+        HtmlTemplate,
+    },
+    utils::BaseUrl,
+};
+use axum::{
+    extract::{Path, State},
+    http::{
+        header::{REFERRER_POLICY, SET_COOKIE},
+        HeaderName, HeaderValue, StatusCode,
+    },
+    response::{AppendHeaders, IntoResponse, Response},
+    Extension,
+};
+use axum_extra::extract::Form;
+use chrono::{Duration, Utc};
+use chrono_tz::Tz;
+use sqlx::SqlitePool;
+use tracing::error;
+
+use crate::state::AppState;
+
+/// Days an invite can be accepted after it was created.
+pub const INVITE_DAYS: i64 = 7;
+
+pub async fn create_user_invite(
+    State(state): State<AppState>,
+    Extension(admin): Extension<User>,
+    base_url: BaseUrl,
+    client: ClientInfo,
+    Form(form): Form<CreateInviteSchema>,
+) -> Result<Response, Response> {
+    // User admins could otherwise hand out more than they have themselves
+    if form.roles.contains(&Role::SuperAdmin) && !admin.has_role(&Role::SuperAdmin) {
+        Err((
+            StatusCode::FORBIDDEN,
+            "Only super admins can invite super admins",
+        )
+            .into_response())?
+    }
+
+    match validate_and_create_invite(&state, &admin, &form).await {
+        Ok((invite, token)) => {
+            let entry = AuditEntry::new(AuditAction::InviteCreate)
+                .target(&invite.name)
+                .after(&invite);
+            audit::record(&state, Some(&admin), &client, entry).await;
+
+            let invites = read_invites(&state.db, state.timezone).await.map_err(|e| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Failed to read invites: {}", e),
+                )
+                    .into_response()
+            })?;
+
+            Ok(HtmlTemplate(InvitesSectionTemplate {
+                invites,
+                created: Some(base_url.join(&format!("/invite/{}", token))),
+            })
+            .into_response())
+        }
+        Err(InviteError::DbError(e)) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to create invite: {}", e),
+        )
+            .into_response()),
+        Err(e) => {
+            // Show validation errors next to the form instead of replacing the list
+            let headers = AppendHeaders([
+                (HeaderName::from_static("hx-retarget"), "#invite-error"),
+                (HeaderName::from_static("hx-reswap"), "innerHTML"),
+            ]);
+            Err((headers, e.to_string()).into_response())
+        }
+    }
+}
+
+/// Creates the invite, returning it together with the token of its link.
+async fn validate_and_create_invite(
+    state: &AppState,
+    admin: &User,
+    form: &CreateInviteSchema,
+) -> Result<(Invite, String), InviteError> {
+    let name = form.name.trim();
+    if name.is_empty() {
+        return Err(InviteError::EmptyName);
+    }
+
+    if user_name_exists(&state.db, name, None).await? {
+        return Err(InviteError::NameTaken(name.to_string()));
+    }
+
+    if has_pending_invite(&state.db, name).await? {
+        return Err(InviteError::AlreadyInvited(name.to_string()));
+    }
+
+    let now = Utc::now().with_timezone(&state.timezone).fixed_offset();
+    let token = generate_refresh_token();
+    let invite = NewInvite {
+        name,
+        roles: &form.roles,
+        token_hash: hash_refresh_token(&token),
+        created_by: admin.id,
+        created_at: now,
+        expires_at: now + Duration::days(INVITE_DAYS),
+    };
+
+    let invite = create_invite(&state.db, &invite, state.timezone).await?;
+
+    Ok((invite, token))
+}
+
+pub async fn revoke_user_invite(
+    Path(id): Path<u64>,
+    State(state): State<AppState>,
+    Extension(admin): Extension<User>,
+    client: ClientInfo,
+) -> Result<Response, Response> {
+    let invite = read_invite(&state.db, id as i64, state.timezone)
+        .await
+        .ok()
+        .flatten();
+
+    let result = delete_invite(&state.db, id as i64).await;
+
+    match (result, invite) {
+        (Ok(true), Some(invite)) => {
+            let entry = AuditEntry::new(AuditAction::InviteRevoke)
+                .target(&invite.name)
+                .before(&invite);
+            audit::record(&state, Some(&admin), &client, entry).await;
+
+            Ok(().into_response())
+        }
+        (Ok(_), _) => Err((StatusCode::NOT_FOUND, "Invite not found").into_response()),
+        (Err(e), _) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to revoke invite: {}", e),
+        )
+            .into_response()),
+    }
+}
+
+/// Page for picking the password of the invited account.
+pub async fn invite(
+    Path(token): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Response, Response> {
+    let page = |username: String, error: Option<String>| {
+        let valid = error.is_none();
+        let mut response = HtmlTemplate(InvitePageTemplate {
+            from_protected: false,
+            permissions: Permissions::default(),
+            logged_user: None,
+            token: token.clone(),
+            username,
+            error,
+            valid,
+        })
+        .into_response();
+        // Keeps the token in the address out of requests to other sites
+        response
+            .headers_mut()
+            .insert(REFERRER_POLICY, HeaderValue::from_static("no-referrer"));
+        response
+    };
+
+    match read_pending_invite(&state, &token).await {
+        Ok(invite) => Ok(page(invite.name, None)),
+        Err(e @ InviteError::InvalidLink) => Err((
+            StatusCode::NOT_FOUND,
+            page("".to_string(), Some(e.to_string())),
+        )
+            .into_response()),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to open the invite: {}", e),
+        )
+            .into_response()),
+    }
+}
+
+/// Creates the invited user with the picked password and logs them in.
+pub async fn accept_invite_post(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Form(form): Form<AcceptInviteSchema>,
+) -> Result<Response, Response> {
+    let user = validate_and_accept(&state, &form)
+        .await
+        .map_err(|(username, e)| {
+            if matches!(
+                e,
+                InviteError::DbError(_)
+                    | InviteError::CreateUser(
+                        CreateUserError::CantRead | CreateUserError::DbError(_)
+                    )
+                    | InviteError::NewPassword(ChangePasswordError::DbError(_))
+            ) {
+                error!("Accepting invite failed: {}", e);
+            }
+
+            HtmlTemplate(InviteSectionTemplate {
+                token: form.token.clone(),
+                username,
+                error: Some(e.to_string()),
+            })
+            .into_response()
+        })?;
+
+    let entry = AuditEntry::new(AuditAction::UserCreate)
+        .target(&user.name)
+        .after(&user);
+    audit::record(&state, Some(&user), &client, entry).await;
+
+    let [access, refresh] = start_login_session(&state, user.id, client)
+        .await
+        .map_err(|e| {
+            error!("Failed to create session: {}", e);
+            // The account exists now, logging in works the usual way
+            (
+                AppendHeaders([(HeaderName::from_static("hx-redirect"), "/login")]),
+                (),
+            )
+                .into_response()
+        })?;
+
+    let headers = AppendHeaders([
+        (SET_COOKIE, access.to_string()),
+        (SET_COOKIE, refresh.to_string()),
+        (HeaderName::from_static("hx-redirect"), "/".to_string()),
+    ]);
+
+    Ok((headers, ()).into_response())
+}
+
+/// Creates the user, returns the username with the error for showing the form again.
+async fn validate_and_accept(
+    state: &AppState,
+    form: &AcceptInviteSchema,
+) -> Result<User, (String, InviteError)> {
+    let invite = read_pending_invite(state, &form.token)
+        .await
+        .map_err(|e| ("".to_string(), e))?;
+    let failed = |e: InviteError| (invite.name.clone(), e);
+
+    validate_new_password(state, &invite.name, &form.password, &form.retype_password)
+        .map_err(|e| failed(e.into()))?;
+
+    accept_invite(&state.db, &hash_refresh_token(&form.token), &form.password)
+        .await
+        .map_err(|e| failed(e.into()))?
+        .ok_or_else(|| failed(InviteError::InvalidLink))
+}
+
+/// Whether an invite that can still be accepted is for the name, in any case.
+pub async fn has_pending_invite(db: &SqlitePool, name: &str) -> sqlx::Result<bool> {
+    let name = name.to_lowercase();
+
+    // Only the expiry is looked at, which doesn't depend on the timezone
+    Ok(read_invites(db, Tz::UTC)
+        .await?
+        .iter()
+        .any(|invite| !invite.expired && invite.name.to_lowercase() == name))
+}
+
+/// Invite of the token that can still be accepted.
+async fn read_pending_invite(state: &AppState, token: &str) -> Result<Invite, InviteError> {
+    read_invite_by_hash(&state.db, &hash_refresh_token(token), state.timezone)
+        .await?
+        .filter(|invite| !invite.expired)
+        .ok_or(InviteError::InvalidLink)
+}
@@ -1,6 +1,13 @@
 pub mod admin;
 pub mod auth;
 pub mod codes;
+pub mod invites;
 pub mod pages;
+pub mod password_reset;
+pub mod series;
+pub mod sessions;
+pub mod setup;
 #[cfg(test)]
 mod test;
+pub mod tokens;
+pub mod two_factor;
@@ -1,16 +1,26 @@
 use crate::{
-    models::User,
+    forms::SelectSeriesSchema,
+    models::{User, DEFAULT_SERIES_ID},
     templates::{codes::IndexPageTemplate, HtmlTemplate},
 };
-use axum::{extract::State, response::IntoResponse, Extension};
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Extension,
+};
 use tower_sessions::Session;
 
-use crate::{db::read_last_ten, middleware::FROM_PROTECTED_KEY, state::AppState};
+use crate::{
+    db::{read_all_series, read_last_ten},
+    middleware::FROM_PROTECTED_KEY,
+    state::AppState,
+};
 
 pub async fn index(
     session: Session,
     State(state): State<AppState>,
     Extension(user): Extension<User>,
+    Query(query): Query<SelectSeriesSchema>,
 ) -> impl IntoResponse {
     let from_protected: bool = session
         .get(FROM_PROTECTED_KEY)
@@ -18,14 +28,32 @@ pub async fn index(
         .unwrap()
         .unwrap_or_default();
 
-    let last_ten = read_last_ten(&state.db).await;
+    let Ok(series) = read_all_series(&state.db).await else {
+        return Err("Failed to read series");
+    };
+    let series: Vec<_> = series.into_iter().filter(|s| s.is_active).collect();
+
+    let selected_series = query
+        .series_id
+        .filter(|id| series.iter().any(|s| s.id == *id))
+        .or_else(|| series.first().map(|s| s.id))
+        .unwrap_or(DEFAULT_SERIES_ID);
+
+    let timezone = series
+        .iter()
+        .find(|s| s.id == selected_series)
+        .map_or(state.timezone, |s| s.display_timezone(state.timezone));
+    let last_ten = read_last_ten(&state.db, selected_series, timezone).await;
 
     if let Ok(last_ten) = last_ten {
         Ok(HtmlTemplate(IndexPageTemplate {
             codes: last_ten,
+            series,
+            selected_series,
             from_protected,
-            is_admin: user.is_admin,
+            permissions: user.permissions,
             logged_user: Some(user.name.clone()),
+            max_batch_size: state.max_batch_size,
         }))
     } else {
         Err("Failed to read last ten numbers")
@@ -0,0 +1,171 @@
+use crate::{
+    actions::auth::validate_new_password,
+    audit::{self, AuditAction, AuditEntry, ClientInfo},
+    db::{
+        change_password, delete_login_sessions, read_password_reset, read_user, take_password_reset,
+    },
+    errors::{password_change::ChangePasswordError, password_reset::PasswordResetError},
+    forms::ResetPasswordSchema,
+    jwt::{hash_password, hash_refresh_token},
+    login_throttle::record_success,
+    models::{PasswordResetEntity, User},
+    permissions::Permissions,
+    templates::{
+        auth::{
+            ResetPasswordPageTemplate, ResetPasswordSectionTemplate, ResetPasswordSuccessTemplate,
+        },
+        HtmlTemplate,
+    },
+};
+use axum::{
+    extract::{Path, State},
+    http::{header::REFERRER_POLICY, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Form,
+};
+use chrono::{DateTime, FixedOffset, Utc};
+use tracing::error;
+
+use crate::state::AppState;
+
+/// Hours a reset link works after the admin created it.
+pub const PASSWORD_RESET_HOURS: i64 = 24;
+
+/// Page for setting a new password without the old one, opened from the link of an admin.
+pub async fn reset_password(
+    Path(token): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Response, Response> {
+    let page = |username: String, error: Option<String>| {
+        let valid = error.is_none();
+        let mut response = HtmlTemplate(ResetPasswordPageTemplate {
+            from_protected: false,
+            permissions: Permissions::default(),
+            logged_user: None,
+            token: token.clone(),
+            username,
+            error,
+            valid,
+        })
+        .into_response();
+        // Keeps the token in the address out of requests to other sites
+        response
+            .headers_mut()
+            .insert(REFERRER_POLICY, HeaderValue::from_static("no-referrer"));
+        response
+    };
+
+    match read_reset_user(&state, &token).await {
+        Ok((user, _)) => Ok(page(user.name, None)),
+        Err(e @ PasswordResetError::InvalidLink) => Err((
+            StatusCode::NOT_FOUND,
+            page("".to_string(), Some(e.to_string())),
+        )
+            .into_response()),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to open the reset link: {}", e),
+        )
+            .into_response()),
+    }
+}
+
+/// Sets the new password, the link stops working and the user is logged out everywhere.
+pub async fn reset_password_post(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Form(form): Form<ResetPasswordSchema>,
+) -> Result<Response, Response> {
+    let user = validate_and_reset(&state, &form)
+        .await
+        .map_err(|(username, e)| {
+            if matches!(
+                e,
+                PasswordResetError::DbError(_)
+                    | PasswordResetError::ReadUser(_)
+                    | PasswordResetError::NewPassword(ChangePasswordError::DbError(_))
+            ) {
+                error!("Password reset failed: {}", e);
+            }
+
+            HtmlTemplate(ResetPasswordSectionTemplate {
+                token: form.token.clone(),
+                username,
+                error: Some(e.to_string()),
+            })
+            .into_response()
+        })?;
+
+    let revoked = delete_login_sessions(&state.db, user.id, None).await;
+    if let Err(err) = &revoked {
+        error!("Failed to end sessions: {}", err);
+    }
+    // Whoever asked for the link could have locked themselves out guessing
+    if let Err(err) = record_success(&state.db, &user.name).await {
+        error!("Failed to clear failed logins: {}", err);
+    }
+
+    let entry = AuditEntry::new(AuditAction::PasswordReset)
+        .target(&user.name)
+        .after(&serde_json::json!({ "revoked_sessions": revoked.unwrap_or_default() }));
+    audit::record(&state, Some(&user), &client, entry).await;
+
+    Ok(HtmlTemplate(ResetPasswordSuccessTemplate {}).into_response())
+}
+
+/// Changes the password, returns the username with the error for showing the form again.
+async fn validate_and_reset(
+    state: &AppState,
+    form: &ResetPasswordSchema,
+) -> Result<User, (String, PasswordResetError)> {
+    let (user, _) = read_reset_user(state, &form.token)
+        .await
+        .map_err(|e| ("".to_string(), e))?;
+    let failed = |e: PasswordResetError| (user.name.clone(), e);
+
+    validate_new_password(state, &user.name, &form.new_password, &form.retype_password)
+        .map_err(|e| failed(e.into()))?;
+
+    // Only one of two requests racing with the same link gets it
+    take_valid_reset(state, &form.token)
+        .await
+        .map_err(failed)?
+        .filter(|reset| reset.user_id == user.id)
+        .ok_or_else(|| failed(PasswordResetError::InvalidLink))?;
+
+    change_password(&state.db, user.id, &hash_password(&form.new_password))
+        .await
+        .map_err(|e| failed(e.into()))?;
+
+    Ok(user)
+}
+
+/// User the unexpired reset link is for.
+async fn read_reset_user(
+    state: &AppState,
+    token: &str,
+) -> Result<(User, PasswordResetEntity), PasswordResetError> {
+    let reset = read_password_reset(&state.db, &hash_refresh_token(token))
+        .await?
+        .filter(|reset| reset.expires_at > now(state))
+        .ok_or(PasswordResetError::InvalidLink)?;
+
+    let user = read_user(&state.db, reset.user_id)
+        .await?
+        .ok_or(PasswordResetError::InvalidLink)?;
+
+    Ok((user, reset))
+}
+
+async fn take_valid_reset(
+    state: &AppState,
+    token: &str,
+) -> Result<Option<PasswordResetEntity>, PasswordResetError> {
+    let reset = take_password_reset(&state.db, &hash_refresh_token(token)).await?;
+
+    Ok(reset.filter(|reset| reset.expires_at > now(state)))
+}
+
+fn now(state: &AppState) -> DateTime<FixedOffset> {
+    Utc::now().with_timezone(&state.timezone).fixed_offset()
+}
@@ -0,0 +1,179 @@
+use crate::{
+    audit::{self, AuditAction, AuditEntry, ClientInfo},
+    check_digit::CheckDigit,
+    code_format::{parse_timezone, CodeFormat, ResetPeriod},
+    db::read_all_series,
+    errors::create_series::CreateSeriesError,
+    forms::CreateSeriesSchema,
+    models::{NewSeries, User},
+    templates::{
+        errors::Error500Template,
+        series::{SeriesManagementTemplate, SeriesTemplate},
+        HtmlTemplate,
+    },
+    utils::get_protected,
+};
+use axum::{
+    extract::{Path, State},
+    http::{HeaderName, StatusCode},
+    response::{AppendHeaders, IntoResponse, Response},
+    Extension, Form,
+};
+use tower_sessions::Session;
+
+use crate::state::AppState;
+
+pub async fn get_series(
+    session: Session,
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+) -> Result<Response, Response> {
+    let from_protected = get_protected(session).await;
+
+    let series = read_all_series(&state.db).await.map_err(|e| {
+        HtmlTemplate(Error500Template {
+            from_protected,
+            permissions: user.permissions,
+            reason: format!("Failed to read series: {}", e),
+            logged_user: Some(user.name.clone()),
+        })
+        .into_response()
+    })?;
+
+    Ok(HtmlTemplate(SeriesManagementTemplate {
+        from_protected,
+        permissions: user.permissions,
+        logged_user: Some(user.name.clone()),
+        series,
+        default_prefix: state.code_prefix.clone(),
+        default_format: state.code_format.to_string(),
+        default_timezone: state.timezone.to_string(),
+    })
+    .into_response())
+}
+
+pub async fn create_series(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    client: ClientInfo,
+    Form(form): Form<CreateSeriesSchema>,
+) -> Result<Response, Response> {
+    let result = validate_and_create_series(&state, form).await;
+
+    match result {
+        Ok(series) => {
+            let entry = AuditEntry::new(AuditAction::SeriesCreate)
+                .target(&series.name)
+                .after(&series);
+            audit::record(&state, Some(&user), &client, entry).await;
+
+            Ok(HtmlTemplate(SeriesTemplate {
+                series,
+                default_prefix: state.code_prefix.clone(),
+                default_format: state.code_format.to_string(),
+                default_timezone: state.timezone.to_string(),
+            })
+            .into_response())
+        }
+        Err(CreateSeriesError::DbError(e)) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to create series: {}", e),
+        )
+            .into_response()),
+        Err(e) => {
+            // Show validation errors next to the form instead of adding a row
+            let headers = AppendHeaders([
+                (HeaderName::from_static("hx-retarget"), "#series-error"),
+                (HeaderName::from_static("hx-reswap"), "innerHTML"),
+            ]);
+            Err((headers, e.to_string()).into_response())
+        }
+    }
+}
+
+async fn validate_and_create_series(
+    state: &AppState,
+    form: CreateSeriesSchema,
+) -> Result<crate::models::Series, CreateSeriesError> {
+    let name = form.name.trim();
+    if name.is_empty() {
+        return Err(CreateSeriesError::EmptyName);
+    }
+
+    let prefix = Some(form.prefix.trim()).filter(|p| !p.is_empty());
+    let format = Some(form.format.trim()).filter(|f| !f.is_empty());
+
+    let code_format = match format {
+        Some(format) => format.parse::<CodeFormat>()?,
+        None => state.code_format.clone(),
+    };
+
+    let reset = match form.reset_period.trim() {
+        "" => ResetPeriod::Daily,
+        reset_period => reset_period.parse()?,
+    };
+    code_format.check_reset(&reset)?;
+
+    let check_digit = match form.check_digit.trim() {
+        "" => None,
+        check_digit => Some(check_digit.parse::<CheckDigit>()?),
+    };
+
+    let timezone = match form.timezone.trim() {
+        "" => None,
+        timezone => Some(parse_timezone(timezone)?),
+    };
+
+    let series = NewSeries {
+        name,
+        prefix,
+        format,
+        reset,
+        check_digit,
+        timezone,
+        description: form.description.trim(),
+    };
+
+    crate::db::create_series(&state.db, &series).await
+}
+
+pub async fn toggle_series(
+    Path(id): Path<u64>,
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    client: ClientInfo,
+) -> Result<Response, Response> {
+    let series = crate::db::read_series(&state.db, id as i64).await;
+
+    let result = match series {
+        Ok(Some(before)) => crate::db::set_series_active(&state.db, before.id, !before.is_active)
+            .await
+            .map(|series| series.map(|series| (before, series))),
+        Ok(None) => Ok(None),
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(Some((before, series))) => {
+            let entry = AuditEntry::new(AuditAction::SeriesToggle)
+                .target(&series.name)
+                .before(&before)
+                .after(&series);
+            audit::record(&state, Some(&user), &client, entry).await;
+
+            Ok(HtmlTemplate(SeriesTemplate {
+                series,
+                default_prefix: state.code_prefix.clone(),
+                default_format: state.code_format.to_string(),
+                default_timezone: state.timezone.to_string(),
+            })
+            .into_response())
+        }
+        Ok(None) => Err((StatusCode::NOT_FOUND, "Series not found").into_response()),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to update series: {}", e),
+        )
+            .into_response()),
+    }
+}
@@ -0,0 +1,139 @@
+use crate::{
+    audit::{self, AuditAction, AuditEntry, ClientInfo},
+    db::{delete_login_session, delete_login_sessions, read_login_sessions},
+    jwt::removed_cookies,
+    middleware::{ApiTokenAuth, CurrentSession},
+    models::User,
+    templates::{errors::Error500Template, sessions::SessionsTemplate, HtmlTemplate},
+    utils::get_protected,
+};
+use axum::{
+    extract::{Path, State},
+    http::{header::SET_COOKIE, HeaderName, StatusCode},
+    response::{AppendHeaders, IntoResponse, Response},
+    Extension,
+};
+use tower_sessions::Session;
+
+use crate::state::AppState;
+
+pub async fn get_sessions(
+    session: Session,
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    current_session: Option<Extension<CurrentSession>>,
+    token_auth: Option<Extension<ApiTokenAuth>>,
+) -> Result<Response, Response> {
+    if token_auth.is_some() {
+        return Err(refused_token());
+    }
+
+    let from_protected = get_protected(session).await;
+
+    let sessions = read_login_sessions(&state.db, user.id, state.timezone)
+        .await
+        .map_err(|e| {
+            HtmlTemplate(Error500Template {
+                from_protected,
+                permissions: user.permissions,
+                reason: format!("Failed to read sessions: {}", e),
+                logged_user: Some(user.name.clone()),
+            })
+            .into_response()
+        })?;
+
+    Ok(HtmlTemplate(SessionsTemplate {
+        from_protected,
+        permissions: user.permissions,
+        logged_user: Some(user.name.clone()),
+        sessions,
+        current: current_session.map(|Extension(CurrentSession(id))| id),
+    })
+    .into_response())
+}
+
+pub async fn revoke_session(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    current_session: Option<Extension<CurrentSession>>,
+    token_auth: Option<Extension<ApiTokenAuth>>,
+    client: ClientInfo,
+) -> Result<Response, Response> {
+    if token_auth.is_some() {
+        return Err(refused_token());
+    }
+
+    let result = delete_login_session(&state.db, user.id, &id).await;
+
+    match result {
+        Ok(true) => {
+            let entry = AuditEntry::new(AuditAction::SessionRevoke).target(&user.name);
+            audit::record(&state, Some(&user), &client, entry).await;
+
+            let is_current =
+                current_session.is_some_and(|Extension(CurrentSession(current))| current == id);
+            if is_current {
+                Ok(logged_out())
+            } else {
+                Ok(().into_response())
+            }
+        }
+        Ok(false) => Err((StatusCode::NOT_FOUND, "Session not found").into_response()),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to revoke session: {}", e),
+        )
+            .into_response()),
+    }
+}
+
+pub async fn revoke_all_sessions(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    token_auth: Option<Extension<ApiTokenAuth>>,
+    client: ClientInfo,
+) -> Result<Response, Response> {
+    if token_auth.is_some() {
+        return Err(refused_token());
+    }
+
+    let result = delete_login_sessions(&state.db, user.id, None).await;
+
+    match result {
+        Ok(revoked) => {
+            let entry = AuditEntry::new(AuditAction::SessionRevokeAll)
+                .target(&user.name)
+                .after(&serde_json::json!({ "revoked": revoked }));
+            audit::record(&state, Some(&user), &client, entry).await;
+
+            Ok(logged_out())
+        }
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to revoke sessions: {}", e),
+        )
+            .into_response()),
+    }
+}
+
+/// Sessions are managed in the browser only, a leaked token must not be able to end them.
+fn refused_token() -> Response {
+    (
+        StatusCode::FORBIDDEN,
+        "Sessions can't be managed with a token",
+    )
+        .into_response()
+}
+
+/// Sends the browser to the login page after its own session was revoked.
+fn logged_out() -> Response {
+    let [access, refresh] = removed_cookies();
+    let headers = AppendHeaders([
+        (SET_COOKIE, access.to_string()),
+        (SET_COOKIE, refresh.to_string()),
+        (HeaderName::from_static("hx-redirect"), "/login".to_string()),
+    ]);
+
+    (headers, ()).into_response()
+}
@@ -0,0 +1,145 @@
+use crate::{
+    actions::auth::start_login_session,
+    audit::{self, AuditAction, AuditEntry, ClientInfo},
+    db::{create_first_admin, has_super_admin},
+    errors::{create_user::CreateUserError, setup::SetupError},
+    forms::{SetupSchema, SetupTokenSchema},
+    jwt::hash_refresh_token,
+    models::User,
+    permissions::Permissions,
+    templates::{
+        auth::{SetupPageTemplate, SetupSectionTemplate},
+        errors::Error403Template,
+        HtmlTemplate,
+    },
+};
+use axum::{
+    extract::{Query, State},
+    http::{header::SET_COOKIE, HeaderName, StatusCode},
+    response::{AppendHeaders, IntoResponse, Redirect, Response},
+    Form,
+};
+use tracing::error;
+
+use crate::state::AppState;
+
+pub async fn setup(
+    State(state): State<AppState>,
+    Query(query): Query<SetupTokenSchema>,
+) -> Result<Response, Response> {
+    match check_setup(&state, &query.token).await {
+        Ok(()) => Ok(HtmlTemplate(SetupPageTemplate {
+            from_protected: false,
+            permissions: Permissions::default(),
+            logged_user: None,
+            token: query.token,
+            username: "".to_string(),
+            error: None,
+        })
+        .into_response()),
+        Err(SetupError::AlreadyDone) => Err(Redirect::to("/login").into_response()),
+        Err(SetupError::InvalidToken) => Err((
+            StatusCode::FORBIDDEN,
+            HtmlTemplate(Error403Template {
+                reason: SetupError::InvalidToken.to_string(),
+                from_protected: false,
+                permissions: Permissions::default(),
+                logged_user: None,
+            }),
+        )
+            .into_response()),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to open setup: {}", e),
+        )
+            .into_response()),
+    }
+}
+
+/// Creates the first admin and logs them in.
+pub async fn setup_post(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Form(form): Form<SetupSchema>,
+) -> Result<Response, Response> {
+    let failed = |error: SetupError| {
+        HtmlTemplate(SetupSectionTemplate {
+            token: form.token.clone(),
+            username: form.username.clone(),
+            error: Some(error.to_string()),
+        })
+        .into_response()
+    };
+
+    let user = validate_and_create(&state, &form).await.map_err(|e| {
+        if matches!(
+            e,
+            SetupError::DbError(_)
+                | SetupError::CreateUser(CreateUserError::CantRead | CreateUserError::DbError(_))
+        ) {
+            error!("Setup failed: {}", e);
+        }
+        failed(e)
+    })?;
+
+    let entry = AuditEntry::new(AuditAction::UserCreate)
+        .target(&user.name)
+        .after(&user);
+    audit::record(&state, Some(&user), &client, entry).await;
+
+    let [access, refresh] = start_login_session(&state, user.id, client)
+        .await
+        .map_err(|e| {
+            error!("Failed to create session: {}", e);
+            // The admin exists now, logging in works the usual way
+            (
+                AppendHeaders([(HeaderName::from_static("hx-redirect"), "/login")]),
+                (),
+            )
+                .into_response()
+        })?;
+
+    let headers = AppendHeaders([
+        (SET_COOKIE, access.to_string()),
+        (SET_COOKIE, refresh.to_string()),
+        (HeaderName::from_static("hx-redirect"), "/".to_string()),
+    ]);
+
+    Ok((headers, ()).into_response())
+}
+
+async fn validate_and_create(state: &AppState, form: &SetupSchema) -> Result<User, SetupError> {
+    check_setup(state, &form.token).await?;
+
+    if form.username.trim().is_empty() || form.password.is_empty() {
+        return Err(SetupError::EmptyFields);
+    }
+    if form.password != form.retype_password {
+        return Err(SetupError::PasswordMismatch);
+    }
+    state
+        .password_policy
+        .check(&form.password, &[form.username.trim()])?;
+
+    create_first_admin(&state.db, form.username.trim(), &form.password)
+        .await?
+        .ok_or(SetupError::AlreadyDone)
+}
+
+/// Lets through only the token printed to the log, and only until the first admin exists.
+async fn check_setup(state: &AppState, token: &str) -> Result<(), SetupError> {
+    let Some(setup_token) = &state.setup_token else {
+        return Err(SetupError::AlreadyDone);
+    };
+
+    if has_super_admin(&state.db).await? {
+        return Err(SetupError::AlreadyDone);
+    }
+
+    // Comparing hashes keeps the time from telling how much of the token was right
+    if hash_refresh_token(token) != hash_refresh_token(setup_token) {
+        return Err(SetupError::InvalidToken);
+    }
+
+    Ok(())
+}
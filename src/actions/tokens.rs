@@ -0,0 +1,196 @@
+use crate::{
+    api_token::{display_prefix, generate_api_token, hash_api_token},
+    audit::{self, AuditAction, AuditEntry, ClientInfo},
+    db::read_api_tokens,
+    errors::create_api_token::CreateApiTokenError,
+    forms::CreateApiTokenSchema,
+    middleware::ApiTokenAuth,
+    models::{ApiToken, NewApiToken, User},
+    permissions::{Permission, Permissions},
+    templates::{
+        api_tokens::{ApiTokensSectionTemplate, ApiTokensTemplate},
+        errors::Error500Template,
+        HtmlTemplate,
+    },
+    utils::get_protected,
+};
+use axum::{
+    extract::{Path, State},
+    http::{HeaderName, StatusCode},
+    response::{AppendHeaders, IntoResponse, Response},
+    Extension,
+};
+use axum_extra::extract::Form;
+use chrono::{Duration, Utc};
+use tower_sessions::Session;
+
+use crate::state::AppState;
+
+pub async fn get_tokens(
+    session: Session,
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+) -> Result<Response, Response> {
+    let from_protected = get_protected(session).await;
+
+    let tokens = read_api_tokens(&state.db, user.id, state.timezone)
+        .await
+        .map_err(|e| {
+            HtmlTemplate(Error500Template {
+                from_protected,
+                permissions: user.permissions,
+                reason: format!("Failed to read API tokens: {}", e),
+                logged_user: Some(user.name.clone()),
+            })
+            .into_response()
+        })?;
+
+    Ok(HtmlTemplate(ApiTokensTemplate {
+        from_protected,
+        permissions: user.permissions,
+        logged_user: Some(user.name.clone()),
+        tokens,
+        created: None,
+    })
+    .into_response())
+}
+
+pub async fn create_token(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    token_auth: Option<Extension<ApiTokenAuth>>,
+    client: ClientInfo,
+    Form(form): Form<CreateApiTokenSchema>,
+) -> Result<Response, Response> {
+    // A leaked token must not be able to outlive its own expiry or revocation
+    let result = match token_auth {
+        Some(_) => Err(CreateApiTokenError::CreatedWithToken),
+        None => validate_and_create_token(&state, &user, form).await,
+    };
+
+    match result {
+        Ok((token, secret)) => {
+            let entry = AuditEntry::new(AuditAction::TokenCreate)
+                .target(&token.name)
+                .after(&token);
+            audit::record(&state, Some(&user), &client, entry).await;
+
+            let tokens = read_api_tokens(&state.db, user.id, state.timezone)
+                .await
+                .map_err(|e| {
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("Failed to read API tokens: {}", e),
+                    )
+                        .into_response()
+                })?;
+
+            Ok(HtmlTemplate(ApiTokensSectionTemplate {
+                tokens,
+                created: Some(secret),
+            })
+            .into_response())
+        }
+        Err(CreateApiTokenError::DbError(e)) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to create API token: {}", e),
+        )
+            .into_response()),
+        Err(e) => {
+            // Show validation errors next to the form instead of replacing the list
+            let headers = AppendHeaders([
+                (HeaderName::from_static("hx-retarget"), "#token-error"),
+                (HeaderName::from_static("hx-reswap"), "innerHTML"),
+            ]);
+            Err((headers, e.to_string()).into_response())
+        }
+    }
+}
+
+/// Creates the token, returning it together with its secret.
+async fn validate_and_create_token(
+    state: &AppState,
+    user: &User,
+    form: CreateApiTokenSchema,
+) -> Result<(ApiToken, String), CreateApiTokenError> {
+    let name = form.name.trim();
+    if name.is_empty() {
+        return Err(CreateApiTokenError::EmptyName);
+    }
+
+    let scopes = form
+        .scopes
+        .iter()
+        .map(|scope| {
+            let permission = scope
+                .parse::<Permission>()
+                .map_err(CreateApiTokenError::UnknownScope)?;
+
+            if user.can(permission) {
+                Ok(permission)
+            } else {
+                Err(CreateApiTokenError::ScopeNotGranted(scope.clone()))
+            }
+        })
+        .collect::<Result<Permissions, _>>()?;
+
+    if scopes.iter().next().is_none() {
+        return Err(CreateApiTokenError::NoScopes);
+    }
+
+    let now = Utc::now().with_timezone(&state.timezone).fixed_offset();
+    let expires_at = match form.expires_in_days.trim() {
+        "" => None,
+        days => match days.parse::<u32>() {
+            Ok(days) if days > 0 => Some(now + Duration::days(days.into())),
+            _ => return Err(CreateApiTokenError::InvalidExpiry(days.to_string())),
+        },
+    };
+
+    let secret = generate_api_token();
+    let token = NewApiToken {
+        user_id: user.id,
+        name,
+        token_hash: hash_api_token(&secret),
+        token_prefix: display_prefix(&secret),
+        scopes,
+        created_at: now,
+        expires_at,
+    };
+
+    let token = crate::db::create_api_token(&state.db, &token, state.timezone).await?;
+
+    Ok((token, secret))
+}
+
+pub async fn revoke_token(
+    Path(id): Path<u64>,
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    client: ClientInfo,
+) -> Result<Response, Response> {
+    let token = crate::db::read_api_token(&state.db, id as i64, state.timezone)
+        .await
+        .ok()
+        .flatten()
+        .filter(|token| token.user_id == user.id);
+
+    let result = crate::db::delete_api_token(&state.db, user.id, id as i64).await;
+
+    match (result, token) {
+        (Ok(true), Some(token)) => {
+            let entry = AuditEntry::new(AuditAction::TokenRevoke)
+                .target(&token.name)
+                .before(&token);
+            audit::record(&state, Some(&user), &client, entry).await;
+
+            Ok(().into_response())
+        }
+        (Ok(_), _) => Err((StatusCode::NOT_FOUND, "API token not found").into_response()),
+        (Err(e), _) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to revoke API token: {}", e),
+        )
+            .into_response()),
+    }
+}
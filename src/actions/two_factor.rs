@@ -0,0 +1,278 @@
+use crate::{
+    audit::{self, AuditAction, AuditEntry, ClientInfo},
+    db::{
+        count_recovery_codes, read_settings, read_user_totp, replace_recovery_codes,
+        use_recovery_code, use_totp_step,
+    },
+    errors::two_factor::TwoFactorError,
+    forms::TwoFactorCodeSchema,
+    middleware::ApiTokenAuth,
+    models::User,
+    templates::{
+        auth::{TwoFactorSectionTemplate, TwoFactorState},
+        HtmlTemplate,
+    },
+    totp::{self, Enrollment},
+};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Form,
+};
+use chrono::{DateTime, FixedOffset, Utc};
+use sqlx::SqlitePool;
+use tower_sessions::Session;
+
+use crate::state::AppState;
+
+/// Session key of the secret being set up on the profile page.
+const TWO_FACTOR_SETUP_KEY: &str = "two_factor_setup";
+
+pub async fn setup_two_factor(
+    session: Session,
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    token_auth: Option<Extension<ApiTokenAuth>>,
+) -> Result<Response, Response> {
+    // A leaked token must not be able to take over or drop the second factor
+    if token_auth.is_some() {
+        return Err(failed(&state, &user, TwoFactorError::ChangedWithToken).await);
+    }
+
+    if user.two_factor {
+        return Err(failed(&state, &user, TwoFactorError::AlreadyEnabled).await);
+    }
+
+    // Kept out of the database until a code confirms the app has it
+    let secret = totp::generate_secret();
+    if let Err(e) = session.insert(TWO_FACTOR_SETUP_KEY, &secret).await {
+        return Err(failed(&state, &user, e.into()).await);
+    }
+
+    Ok(section(
+        TwoFactorState::Setup(Enrollment::new(&user.name, secret)),
+        None,
+    ))
+}
+
+pub async fn enable_two_factor(
+    session: Session,
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    token_auth: Option<Extension<ApiTokenAuth>>,
+    client: ClientInfo,
+    Form(form): Form<TwoFactorCodeSchema>,
+) -> Result<Response, Response> {
+    if token_auth.is_some() {
+        return Err(failed(&state, &user, TwoFactorError::ChangedWithToken).await);
+    }
+
+    let secret: Option<String> = session.get(TWO_FACTOR_SETUP_KEY).await.ok().flatten();
+
+    let result = match &secret {
+        Some(secret) => confirm_two_factor(&state.db, &user, secret, &form.code, now(&state)).await,
+        None => Err(TwoFactorError::SetupExpired),
+    };
+
+    match (result, secret) {
+        (Ok(recovery_codes), _) => {
+            let _ = session.remove::<String>(TWO_FACTOR_SETUP_KEY).await;
+
+            let entry = AuditEntry::new(AuditAction::TwoFactorEnable).target(&user.name);
+            audit::record(&state, Some(&user), &client, entry).await;
+
+            Ok(section(TwoFactorState::RecoveryCodes(recovery_codes), None))
+        }
+        // Let the user try again with the QR code they already scanned
+        (Err(TwoFactorError::InvalidCode), Some(secret)) => Err(section(
+            TwoFactorState::Setup(Enrollment::new(&user.name, secret)),
+            Some(TwoFactorError::InvalidCode.to_string()),
+        )),
+        (Err(e), _) => Err(failed(&state, &user, e).await),
+    }
+}
+
+pub async fn disable_two_factor(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    token_auth: Option<Extension<ApiTokenAuth>>,
+    client: ClientInfo,
+    Form(form): Form<TwoFactorCodeSchema>,
+) -> Result<Response, Response> {
+    if token_auth.is_some() {
+        return Err(failed(&state, &user, TwoFactorError::ChangedWithToken).await);
+    }
+
+    let result = validate_and_disable(&state, &user, &form.code).await;
+
+    match result {
+        Ok(()) => {
+            let entry = AuditEntry::new(AuditAction::TwoFactorDisable).target(&user.name);
+            audit::record(&state, Some(&user), &client, entry).await;
+
+            Ok(section(TwoFactorState::Disabled, None))
+        }
+        Err(e) => Err(failed(&state, &user, e).await),
+    }
+}
+
+async fn validate_and_disable(
+    state: &AppState,
+    user: &User,
+    code: &str,
+) -> Result<(), TwoFactorError> {
+    if !user.two_factor {
+        return Err(TwoFactorError::NotEnabled);
+    }
+
+    if user.is_admin() && read_settings(&state.db).await?.require_admin_two_factor {
+        return Err(TwoFactorError::Required);
+    }
+
+    if !check_second_factor(&state.db, user.id, code, now(state)).await? {
+        return Err(TwoFactorError::InvalidCode);
+    }
+
+    crate::db::disable_two_factor(&state.db, user.id).await?;
+
+    Ok(())
+}
+
+pub async fn regenerate_recovery_codes(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    token_auth: Option<Extension<ApiTokenAuth>>,
+    client: ClientInfo,
+    Form(form): Form<TwoFactorCodeSchema>,
+) -> Result<Response, Response> {
+    if token_auth.is_some() {
+        return Err(failed(&state, &user, TwoFactorError::ChangedWithToken).await);
+    }
+
+    let result = validate_and_regenerate(&state, &user, &form.code).await;
+
+    match result {
+        Ok(recovery_codes) => {
+            let entry = AuditEntry::new(AuditAction::RecoveryCodesRegenerate).target(&user.name);
+            audit::record(&state, Some(&user), &client, entry).await;
+
+            Ok(section(TwoFactorState::RecoveryCodes(recovery_codes), None))
+        }
+        Err(e) => Err(failed(&state, &user, e).await),
+    }
+}
+
+async fn validate_and_regenerate(
+    state: &AppState,
+    user: &User,
+    code: &str,
+) -> Result<Vec<String>, TwoFactorError> {
+    if !user.two_factor {
+        return Err(TwoFactorError::NotEnabled);
+    }
+
+    if !check_second_factor(&state.db, user.id, code, now(state)).await? {
+        return Err(TwoFactorError::InvalidCode);
+    }
+
+    let (recovery_codes, hashes) = new_recovery_codes();
+    replace_recovery_codes(&state.db, user.id, &hashes).await?;
+
+    Ok(recovery_codes)
+}
+
+/// Turns on two-factor authentication once the code shows the app has the secret, returns the
+/// new recovery codes.
+pub async fn confirm_two_factor(
+    db: &SqlitePool,
+    user: &User,
+    secret: &str,
+    code: &str,
+    now: DateTime<FixedOffset>,
+) -> Result<Vec<String>, TwoFactorError> {
+    if user.two_factor {
+        return Err(TwoFactorError::AlreadyEnabled);
+    }
+
+    let step = totp::verify(secret, code, now.timestamp()).ok_or(TwoFactorError::InvalidCode)?;
+
+    let (recovery_codes, hashes) = new_recovery_codes();
+    crate::db::enable_two_factor(db, user.id, secret, step, now, &hashes).await?;
+
+    Ok(recovery_codes)
+}
+
+/// Checks a code of the authenticator app or an unused recovery code, either works only once.
+pub async fn check_second_factor(
+    db: &SqlitePool,
+    user_id: i64,
+    code: &str,
+    now: DateTime<FixedOffset>,
+) -> sqlx::Result<bool> {
+    if !totp::is_totp_code(code) {
+        return use_recovery_code(db, user_id, &totp::hash_recovery_code(code), now).await;
+    }
+
+    let Some(user_totp) = read_user_totp(db, user_id).await? else {
+        return Ok(false);
+    };
+
+    match totp::verify(&user_totp.secret, code, now.timestamp()) {
+        Some(step) if step > user_totp.last_step => use_totp_step(db, user_id, step).await,
+        _ => Ok(false),
+    }
+}
+
+/// What the profile page shows about the two-factor authentication of the user.
+pub async fn two_factor_state(db: &SqlitePool, user: &User) -> sqlx::Result<TwoFactorState> {
+    if !user.two_factor {
+        return Ok(TwoFactorState::Disabled);
+    }
+
+    Ok(TwoFactorState::Enabled {
+        recovery_codes_left: count_recovery_codes(db, user.id).await?,
+    })
+}
+
+/// Creates recovery codes, returns them together with their hashes for storage.
+fn new_recovery_codes() -> (Vec<String>, Vec<String>) {
+    let recovery_codes = totp::generate_recovery_codes();
+    let hashes = recovery_codes
+        .iter()
+        .map(|code| totp::hash_recovery_code(code))
+        .collect();
+
+    (recovery_codes, hashes)
+}
+
+fn now(state: &AppState) -> DateTime<FixedOffset> {
+    Utc::now().with_timezone(&state.timezone).fixed_offset()
+}
+
+fn section(two_factor: TwoFactorState, two_factor_error: Option<String>) -> Response {
+    HtmlTemplate(TwoFactorSectionTemplate {
+        two_factor,
+        two_factor_error,
+    })
+    .into_response()
+}
+
+/// Section showing the error next to the unchanged state.
+async fn failed(state: &AppState, user: &User, err: TwoFactorError) -> Response {
+    let two_factor = match err {
+        TwoFactorError::DbError(_) | TwoFactorError::Session(_) => Err(err.to_string()),
+        _ => two_factor_state(&state.db, user)
+            .await
+            .map_err(|e| e.to_string()),
+    };
+
+    match two_factor {
+        Ok(two_factor) => section(two_factor, Some(err.to_string())),
+        Err(reason) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to update two-factor authentication: {}", reason),
+        )
+            .into_response(),
+    }
+}
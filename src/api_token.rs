@@ -0,0 +1,48 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+
+/// Start of every personal access token, tells them apart from login JWTs.
+pub const API_TOKEN_PREFIX: &str = "sgp_";
+
+/// Characters of a token kept in clear to recognize it in the UI.
+const DISPLAY_PREFIX_LEN: usize = API_TOKEN_PREFIX.len() + 8;
+
+/// Creates a new random token secret.
+pub fn generate_api_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+
+    format!("{}{}", API_TOKEN_PREFIX, hex::encode(bytes))
+}
+
+/// Hashes the token for storage.
+///
+/// Tokens are long and random, so a fast hash is enough and keeps every API request cheap.
+pub fn hash_api_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+pub fn display_prefix(token: &str) -> String {
+    token.chars().take(DISPLAY_PREFIX_LEN).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::api_token::{display_prefix, generate_api_token, hash_api_token, API_TOKEN_PREFIX};
+
+    #[test]
+    fn generated_tokens() {
+        let token = generate_api_token();
+
+        assert!(token.starts_with(API_TOKEN_PREFIX));
+        assert_eq!(token.len(), API_TOKEN_PREFIX.len() + 64);
+        assert_ne!(token, generate_api_token());
+        assert_eq!(display_prefix(&token).len(), 12);
+    }
+
+    #[test]
+    fn hash_is_stable() {
+        assert_eq!(hash_api_token("sgp_abc"), hash_api_token("sgp_abc"));
+        assert_ne!(hash_api_token("sgp_abc"), hash_api_token("sgp_abd"));
+    }
+}
@@ -0,0 +1,281 @@
+use std::{
+    fmt::Display,
+    net::{IpAddr, SocketAddr},
+};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRef, FromRequestParts},
+    http::{header::USER_AGENT, request::Parts},
+};
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::Value;
+use tracing::error;
+
+use crate::{
+    db::create_audit_event,
+    models::{NewAuditEvent, User},
+    state::AppState,
+};
+
+/// Kind of change recorded in the audit log.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuditAction {
+    CodeReserve,
+    CodeReserveBatch,
+    CodeReset,
+    CodeResetUndo,
+    UserCreate,
+    UserDelete,
+    UserUpdate,
+    UserDeactivate,
+    UserReactivate,
+    InviteCreate,
+    InviteRevoke,
+    UserRolesSync,
+    PasswordChange,
+    PasswordResetCreate,
+    PasswordReset,
+    TwoFactorEnable,
+    TwoFactorDisable,
+    RecoveryCodesRegenerate,
+    SeriesCreate,
+    SeriesToggle,
+    TokenCreate,
+    TokenRevoke,
+    SessionRevoke,
+    SessionRevokeAll,
+    SettingsUpdate,
+}
+
+impl AuditAction {
+    pub const ALL: [AuditAction; 25] = [
+        AuditAction::CodeReserve,
+        AuditAction::CodeReserveBatch,
+        AuditAction::CodeReset,
+        AuditAction::CodeResetUndo,
+        AuditAction::UserCreate,
+        AuditAction::UserDelete,
+        AuditAction::UserUpdate,
+        AuditAction::UserDeactivate,
+        AuditAction::UserReactivate,
+        AuditAction::InviteCreate,
+        AuditAction::InviteRevoke,
+        AuditAction::UserRolesSync,
+        AuditAction::PasswordChange,
+        AuditAction::PasswordResetCreate,
+        AuditAction::PasswordReset,
+        AuditAction::TwoFactorEnable,
+        AuditAction::TwoFactorDisable,
+        AuditAction::RecoveryCodesRegenerate,
+        AuditAction::SeriesCreate,
+        AuditAction::SeriesToggle,
+        AuditAction::TokenCreate,
+        AuditAction::TokenRevoke,
+        AuditAction::SessionRevoke,
+        AuditAction::SessionRevokeAll,
+        AuditAction::SettingsUpdate,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::CodeReserve => "code.reserve",
+            AuditAction::CodeReserveBatch => "code.reserve_batch",
+            AuditAction::CodeReset => "code.reset",
+            AuditAction::CodeResetUndo => "code.reset_undo",
+            AuditAction::UserCreate => "user.create",
+            AuditAction::UserDelete => "user.delete",
+            AuditAction::UserUpdate => "user.update",
+            AuditAction::UserDeactivate => "user.deactivate",
+            AuditAction::UserReactivate => "user.reactivate",
+            AuditAction::InviteCreate => "invite.create",
+            AuditAction::InviteRevoke => "invite.revoke",
+            AuditAction::UserRolesSync => "user.roles_sync",
+            AuditAction::PasswordChange => "user.password_change",
+            AuditAction::PasswordResetCreate => "user.password_reset_create",
+            AuditAction::PasswordReset => "user.password_reset",
+            AuditAction::TwoFactorEnable => "user.two_factor_enable",
+            AuditAction::TwoFactorDisable => "user.two_factor_disable",
+            AuditAction::RecoveryCodesRegenerate => "user.recovery_codes_regenerate",
+            AuditAction::SeriesCreate => "series.create",
+            AuditAction::SeriesToggle => "series.toggle",
+            AuditAction::TokenCreate => "token.create",
+            AuditAction::TokenRevoke => "token.revoke",
+            AuditAction::SessionRevoke => "session.revoke",
+            AuditAction::SessionRevokeAll => "session.revoke_all",
+            AuditAction::SettingsUpdate => "settings.update",
+        }
+    }
+}
+
+impl Display for AuditAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Address and user agent of the client making the request.
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientInfo
+where
+    AppState: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let header = |name: &str| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string())
+        };
+
+        let state = AppState::from_ref(state);
+        let ip = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(address)| {
+                client_address(
+                    address.ip(),
+                    header("x-forwarded-for").as_deref(),
+                    &state.trusted_proxies,
+                )
+                .to_string()
+            });
+
+        Ok(ClientInfo {
+            ip,
+            user_agent: header(USER_AGENT.as_str()),
+        })
+    }
+}
+
+/// Address of the client, the connection comes from the proxy itself behind a reverse proxy.
+///
+/// Anyone can send `X-Forwarded-For`, so it's only read when the peer is a trusted proxy. The
+/// last address not belonging to a trusted proxy is the one the proxies saw.
+fn client_address(peer: IpAddr, forwarded_for: Option<&str>, trusted_proxies: &[IpAddr]) -> IpAddr {
+    if !trusted_proxies.contains(&peer) {
+        return peer;
+    }
+
+    let mut address = peer;
+    for entry in forwarded_for.unwrap_or_default().rsplit(',') {
+        let Ok(ip) = entry.trim().parse::<IpAddr>() else {
+            break;
+        };
+        address = ip;
+        if !trusted_proxies.contains(&ip) {
+            break;
+        }
+    }
+
+    address
+}
+
+/// A change about to be written to the audit log.
+pub struct AuditEntry {
+    pub action: AuditAction,
+    pub target: Option<String>,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+impl AuditEntry {
+    pub fn new(action: AuditAction) -> Self {
+        AuditEntry {
+            action,
+            target: None,
+            before: None,
+            after: None,
+        }
+    }
+
+    pub fn target(mut self, target: impl Display) -> Self {
+        self.target = Some(target.to_string());
+        self
+    }
+
+    pub fn before(mut self, before: &impl Serialize) -> Self {
+        self.before = serde_json::to_value(before).ok();
+        self
+    }
+
+    pub fn after(mut self, after: &impl Serialize) -> Self {
+        self.after = serde_json::to_value(after).ok();
+        self
+    }
+}
+
+/// Writes the entry to the audit log.
+///
+/// The change itself already happened, so a failure is only logged.
+pub async fn record(
+    state: &AppState,
+    actor: Option<&User>,
+    client: &ClientInfo,
+    entry: AuditEntry,
+) {
+    let event = NewAuditEvent {
+        created_at: Utc::now().with_timezone(&state.timezone).fixed_offset(),
+        actor_id: actor.map(|user| user.id),
+        actor_name: actor.map(|user| user.name.clone()),
+        action: entry.action.to_string(),
+        target: entry.target,
+        before_json: entry.before.map(|value| value.to_string()),
+        after_json: entry.after.map(|value| value.to_string()),
+        ip: client.ip.clone(),
+        user_agent: client.user_agent.clone(),
+    };
+
+    if let Err(e) = create_audit_event(&state.db, &event).await {
+        error!("Failed to write audit event {}: {}", event.action, e);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn client_address_ignores_untrusted_header() {
+        let address = client_address(ip("203.0.113.7"), Some("10.0.0.1"), &[]);
+        assert_eq!(address, ip("203.0.113.7"));
+
+        let proxies = [ip("127.0.0.1")];
+        let address = client_address(ip("203.0.113.7"), Some("10.0.0.1"), &proxies);
+        assert_eq!(address, ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn client_address_behind_trusted_proxy() {
+        let proxies = [ip("127.0.0.1"), ip("10.0.0.2")];
+
+        let address = client_address(ip("127.0.0.1"), Some("198.51.100.4"), &proxies);
+        assert_eq!(address, ip("198.51.100.4"));
+
+        // Only the entries added by trusted proxies count, the client can prepend anything
+        let forwarded = "1.2.3.4, 198.51.100.4, 10.0.0.2";
+        let address = client_address(ip("127.0.0.1"), Some(forwarded), &proxies);
+        assert_eq!(address, ip("198.51.100.4"));
+
+        let address = client_address(ip("127.0.0.1"), None, &proxies);
+        assert_eq!(address, ip("127.0.0.1"));
+
+        let address = client_address(ip("127.0.0.1"), Some("garbage"), &proxies);
+        assert_eq!(address, ip("127.0.0.1"));
+    }
+}
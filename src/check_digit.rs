@@ -0,0 +1,163 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::errors::code_format::CodeFormatError;
+
+/// Damm algorithm operation table, a totally anti-symmetric quasigroup of order 10.
+const DAMM_TABLE: [[u8; 10]; 10] = [
+    [0, 3, 1, 7, 5, 9, 8, 6, 4, 2],
+    [7, 0, 9, 2, 1, 5, 4, 8, 6, 3],
+    [4, 2, 0, 6, 8, 7, 1, 3, 5, 9],
+    [1, 7, 5, 0, 9, 8, 3, 4, 2, 6],
+    [6, 1, 2, 3, 0, 4, 5, 9, 7, 8],
+    [3, 6, 7, 4, 2, 0, 9, 5, 8, 1],
+    [5, 8, 6, 9, 7, 2, 0, 1, 3, 4],
+    [8, 9, 4, 5, 3, 6, 2, 0, 1, 7],
+    [9, 4, 3, 8, 6, 1, 7, 2, 0, 5],
+    [2, 5, 8, 1, 4, 3, 6, 7, 9, 0],
+];
+
+/// Algorithm computing the check characters appended to generated codes.
+///
+/// Luhn and Damm only look at the digits of the code. ISO 7064 MOD 97-10 also covers letters,
+/// which count as `A = 10` up to `Z = 35` like in IBANs. Separators are ignored by all of them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CheckDigit {
+    Luhn,
+    Mod97,
+    Damm,
+}
+
+impl CheckDigit {
+    /// Appends the check characters to the code.
+    pub fn append(&self, code: &str) -> String {
+        format!("{}{}", code, self.compute(code))
+    }
+
+    /// Checks that the code ends with valid check characters.
+    pub fn verify(&self, code: &str) -> bool {
+        let len = self.len();
+        if code.len() <= len || !code.is_char_boundary(code.len() - len) {
+            return false;
+        }
+
+        let (payload, check) = code.split_at(code.len() - len);
+        self.compute(payload) == check
+    }
+
+    /// Removes valid check characters from the end of the code.
+    pub fn strip<'a>(&self, code: &'a str) -> Option<&'a str> {
+        self.verify(code).then(|| &code[..code.len() - self.len()])
+    }
+
+    /// Computes the check characters of the code.
+    pub fn compute(&self, code: &str) -> String {
+        match self {
+            CheckDigit::Luhn => luhn(&digits(code)).to_string(),
+            CheckDigit::Mod97 => format!("{:02}", mod97(code)),
+            CheckDigit::Damm => damm(&digits(code)).to_string(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            CheckDigit::Luhn | CheckDigit::Damm => 1,
+            CheckDigit::Mod97 => 2,
+        }
+    }
+}
+
+fn digits(code: &str) -> Vec<u32> {
+    code.chars().filter_map(|c| c.to_digit(10)).collect()
+}
+
+fn luhn(digits: &[u32]) -> u32 {
+    // The check digit ends up rightmost, so doubling starts with the last digit of the payload
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, d)| match (i % 2 == 0, d * 2) {
+            (true, doubled) if doubled > 9 => doubled - 9,
+            (true, doubled) => doubled,
+            (false, _) => *d,
+        })
+        .sum();
+
+    (10 - sum % 10) % 10
+}
+
+fn damm(digits: &[u32]) -> u8 {
+    digits
+        .iter()
+        .fold(0, |interim, d| DAMM_TABLE[interim as usize][*d as usize])
+}
+
+fn mod97(code: &str) -> u32 {
+    let remainder = code
+        .chars()
+        .filter_map(|c| c.to_digit(36))
+        .chain([0, 0])
+        .fold(0, |remainder, value| {
+            let shift = if value > 9 { 100 } else { 10 };
+            (remainder * shift + value) % 97
+        });
+
+    98 - remainder
+}
+
+impl FromStr for CheckDigit {
+    type Err = CodeFormatError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "luhn" => Ok(CheckDigit::Luhn),
+            "mod97-10" => Ok(CheckDigit::Mod97),
+            "damm" => Ok(CheckDigit::Damm),
+            other => Err(CodeFormatError::UnknownCheckDigit(other.to_string())),
+        }
+    }
+}
+
+impl Display for CheckDigit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckDigit::Luhn => f.write_str("luhn"),
+            CheckDigit::Mod97 => f.write_str("mod97-10"),
+            CheckDigit::Damm => f.write_str("damm"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::check_digit::CheckDigit;
+
+    #[test]
+    fn luhn() {
+        assert_eq!(CheckDigit::Luhn.compute("7992739871"), "3");
+        assert_eq!(CheckDigit::Luhn.append("V20240106.08"), "V20240106.086");
+        assert!(CheckDigit::Luhn.verify("79927398713"));
+        assert!(!CheckDigit::Luhn.verify("79927398731"));
+    }
+
+    #[test]
+    fn damm() {
+        assert_eq!(CheckDigit::Damm.compute("572"), "4");
+        assert!(CheckDigit::Damm.verify("5724"));
+        assert!(!CheckDigit::Damm.verify("5274"));
+    }
+
+    #[test]
+    fn mod97() {
+        assert_eq!(CheckDigit::Mod97.compute("794"), "44");
+        assert_eq!(CheckDigit::Mod97.compute("V20240106.08"), "78");
+        assert!(CheckDigit::Mod97.verify("V20240106.0878"));
+        assert!(!CheckDigit::Mod97.verify("V20240106.0978"));
+    }
+
+    #[test]
+    fn verify_too_short() {
+        assert!(!CheckDigit::Luhn.verify("0"));
+        assert!(!CheckDigit::Mod97.verify("44"));
+    }
+}
@@ -0,0 +1,509 @@
+use std::{collections::HashMap, fmt::Display, str::FromStr};
+
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, Datelike, Duration, FixedOffset, TimeZone, Utc,
+};
+use chrono_tz::Tz;
+
+use crate::{check_digit::CheckDigit, errors::code_format::CodeFormatError};
+
+/// Format used when no other format is configured. Produces codes like `V20240106.07`.
+pub const DEFAULT_CODE_FORMAT: &str = "{prefix}{date:%Y%m%d}.{seq:02}";
+
+/// Date format used by a bare `{date}` placeholder.
+const DEFAULT_DATE_FORMAT: &str = "%Y%m%d";
+
+/// Days rendered when checking a format against a reset period, three years from a leap year.
+const RESET_CHECK_DAYS: i64 = 3 * 365 + 1;
+
+/// Everything needed to generate codes for one series.
+#[derive(Debug, Clone)]
+pub struct CodeScheme {
+    pub series_id: i64,
+    pub prefix: String,
+    pub format: CodeFormat,
+    pub reset: ResetPeriod,
+    pub check_digit: Option<CheckDigit>,
+    pub timezone: Tz,
+}
+
+impl CodeScheme {
+    /// Current time in the series' timezone, which decides the date in the code.
+    pub fn now(&self) -> DateTime<Tz> {
+        Utc::now().with_timezone(&self.timezone)
+    }
+
+    /// Reads the sequence number back out of a code reserved before numbers were stored with codes.
+    ///
+    /// Those codes were dated in the server's local time, so the days around the reservation are tried too.
+    pub fn legacy_seq(&self, code: &str, created_at: &DateTime<FixedOffset>) -> Option<i64> {
+        let created_at = created_at.with_timezone(&self.timezone);
+        let payloads = self
+            .check_digit
+            .and_then(|check_digit| check_digit.strip(code))
+            .into_iter()
+            .chain([code]);
+
+        payloads
+            .flat_map(|payload| [0, -1, 1].map(|days| (payload, created_at + Duration::days(days))))
+            .find_map(|(payload, timestamp)| {
+                self.format
+                    .parse_seq(&self.prefix, &timestamp, payload)
+                    // Rendering the number again rules out digits that belong to the text around it
+                    .filter(|seq| self.format.render(&self.prefix, &timestamp, *seq) == payload)
+            })
+    }
+}
+
+/// How often the sequence of a series starts again from 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResetPeriod {
+    Daily,
+    /// ISO 8601 week, starting on Monday.
+    Weekly,
+    Monthly,
+    Yearly,
+    /// Fiscal year starting in the given month (1-12).
+    FiscalYear(u32),
+    Never,
+}
+
+impl ResetPeriod {
+    /// Identifies the period the timestamp falls into. Codes sharing the key belong to the same sequence.
+    pub fn period_key<Tz: TimeZone>(&self, timestamp: &DateTime<Tz>) -> String
+    where
+        Tz::Offset: Display,
+    {
+        match self {
+            ResetPeriod::Daily => timestamp.format("%Y-%m-%d").to_string(),
+            ResetPeriod::Weekly => timestamp.format("%G-W%V").to_string(),
+            ResetPeriod::Monthly => timestamp.format("%Y-%m").to_string(),
+            ResetPeriod::Yearly => timestamp.format("%Y").to_string(),
+            ResetPeriod::FiscalYear(start_month) => {
+                format!("FY{}", fiscal_year(timestamp, *start_month))
+            }
+            ResetPeriod::Never => String::new(),
+        }
+    }
+}
+
+impl FromStr for ResetPeriod {
+    type Err = CodeFormatError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "daily" => Ok(ResetPeriod::Daily),
+            "weekly" => Ok(ResetPeriod::Weekly),
+            "monthly" => Ok(ResetPeriod::Monthly),
+            "yearly" => Ok(ResetPeriod::Yearly),
+            "fiscal" => Ok(ResetPeriod::FiscalYear(1)),
+            "never" => Ok(ResetPeriod::Never),
+            other => other
+                .strip_prefix("fiscal:")
+                .map(parse_start_month)
+                .ok_or_else(|| CodeFormatError::InvalidResetPeriod(other.to_string()))?
+                .map(ResetPeriod::FiscalYear),
+        }
+    }
+}
+
+impl Display for ResetPeriod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResetPeriod::Daily => f.write_str("daily"),
+            ResetPeriod::Weekly => f.write_str("weekly"),
+            ResetPeriod::Monthly => f.write_str("monthly"),
+            ResetPeriod::Yearly => f.write_str("yearly"),
+            ResetPeriod::FiscalYear(1) => f.write_str("fiscal"),
+            ResetPeriod::FiscalYear(start_month) => write!(f, "fiscal:{}", start_month),
+            ResetPeriod::Never => f.write_str("never"),
+        }
+    }
+}
+
+/// Parses an IANA timezone name like `Europe/Prague`.
+pub fn parse_timezone(name: &str) -> Result<Tz, CodeFormatError> {
+    name.parse()
+        .map_err(|_| CodeFormatError::UnknownTimezone(name.to_string()))
+}
+
+/// Fiscal years are named after the calendar year they end in.
+fn fiscal_year<Tz: TimeZone>(timestamp: &DateTime<Tz>, start_month: u32) -> i32 {
+    if start_month > 1 && timestamp.month() >= start_month {
+        timestamp.year() + 1
+    } else {
+        timestamp.year()
+    }
+}
+
+fn parse_start_month(value: &str) -> Result<u32, CodeFormatError> {
+    value
+        .parse::<u32>()
+        .ok()
+        .filter(|month| (1..=12).contains(month))
+        .ok_or_else(|| CodeFormatError::InvalidFiscalStart(value.to_string()))
+}
+
+/// One piece of a parsed code format.
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Prefix,
+    Date(String),
+    FiscalYear(u32),
+    Seq { width: usize },
+}
+
+/// Parsed and validated code format template.
+///
+/// Supported placeholders:
+/// - `{prefix}` - the configured code prefix
+/// - `{date}` / `{date:<strftime>}` - reservation date, `%Y%m%d` when no format is given
+/// - `{isoweek}` - ISO week number of the reservation date, e.g. `01`
+/// - `{month}` - month of the reservation date, e.g. `01`
+/// - `{fy}` / `{fy:<month>}` - fiscal year starting in the given month, January when not given
+/// - `{seq}` / `{seq:0N}` - sequence number, optionally zero-padded to `N` digits
+///
+/// Literal braces are written as `{{` and `}}`. Exactly one `{seq}` is required.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodeFormat {
+    template: String,
+    segments: Vec<Segment>,
+}
+
+impl CodeFormat {
+    /// Renders a complete code.
+    pub fn render<Tz: TimeZone>(&self, prefix: &str, timestamp: &DateTime<Tz>, seq: i64) -> String
+    where
+        Tz::Offset: Display,
+    {
+        let mut code = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Seq { width } => code.push_str(&format!("{:0>width$}", seq)),
+                other => code.push_str(&render_fixed(other, prefix, timestamp)),
+            }
+        }
+        code
+    }
+
+    /// Renders the parts of the code surrounding the sequence number.
+    ///
+    /// Codes sharing the same stem belong to the same sequence.
+    pub fn stem<Tz: TimeZone>(&self, prefix: &str, timestamp: &DateTime<Tz>) -> (String, String)
+    where
+        Tz::Offset: Display,
+    {
+        let seq_index = self.seq_index();
+        let render = |segments: &[Segment]| {
+            segments
+                .iter()
+                .map(|segment| render_fixed(segment, prefix, timestamp))
+                .collect::<String>()
+        };
+
+        (
+            render(&self.segments[..seq_index]),
+            render(&self.segments[seq_index + 1..]),
+        )
+    }
+
+    /// Parses the sequence number back out of an existing code.
+    ///
+    /// Returns `None` when the code wasn't produced by this format for the given prefix and date.
+    pub fn parse_seq<Tz: TimeZone>(
+        &self,
+        prefix: &str,
+        timestamp: &DateTime<Tz>,
+        code: &str,
+    ) -> Option<i64>
+    where
+        Tz::Offset: Display,
+    {
+        let (before, after) = self.stem(prefix, timestamp);
+        let digits = code.strip_prefix(&before)?.strip_suffix(&after)?;
+
+        if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+
+        digits.parse().ok()
+    }
+
+    /// Checks that codes of different reset periods can't render alike.
+    ///
+    /// Sequences start over every period, so the text around the number has to tell periods apart.
+    /// Every day of a few years is rendered, enough for formats repeating yearly or weekly.
+    pub fn check_reset(&self, reset: &ResetPeriod) -> Result<(), CodeFormatError> {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let mut periods = HashMap::new();
+        for day in 0..RESET_CHECK_DAYS {
+            let timestamp = start + Duration::days(day);
+            let period = reset.period_key(&timestamp);
+            let seen = periods
+                .entry(self.stem("", &timestamp))
+                .or_insert_with(|| period.clone());
+            if *seen != period {
+                return Err(CodeFormatError::PeriodsNotDistinct(reset.to_string()));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn seq_index(&self) -> usize {
+        self.segments
+            .iter()
+            .position(|segment| matches!(segment, Segment::Seq { .. }))
+            .expect("validated format always contains {seq}")
+    }
+}
+
+fn render_fixed<Tz: TimeZone>(segment: &Segment, prefix: &str, timestamp: &DateTime<Tz>) -> String
+where
+    Tz::Offset: Display,
+{
+    match segment {
+        Segment::Literal(text) => text.clone(),
+        Segment::Prefix => prefix.to_string(),
+        Segment::Date(format) => timestamp.format(format).to_string(),
+        Segment::FiscalYear(start_month) => fiscal_year(timestamp, *start_month).to_string(),
+        Segment::Seq { .. } => String::new(),
+    }
+}
+
+fn parse_placeholder(placeholder: &str) -> Result<Segment, CodeFormatError> {
+    let (name, spec) = match placeholder.split_once(':') {
+        Some((name, spec)) => (name, Some(spec)),
+        None => (placeholder, None),
+    };
+
+    match (name, spec) {
+        ("prefix", None) => Ok(Segment::Prefix),
+        ("date", None) => Ok(Segment::Date(DEFAULT_DATE_FORMAT.to_string())),
+        ("date", Some(format)) => {
+            let invalid = format.is_empty()
+                || StrftimeItems::new(format).any(|item| matches!(item, Item::Error));
+            if invalid {
+                return Err(CodeFormatError::InvalidDateFormat(format.to_string()));
+            }
+            Ok(Segment::Date(format.to_string()))
+        }
+        ("isoweek", None) => Ok(Segment::Date("%V".to_string())),
+        ("month", None) => Ok(Segment::Date("%m".to_string())),
+        ("fy", None) => Ok(Segment::FiscalYear(1)),
+        ("fy", Some(start_month)) => parse_start_month(start_month).map(Segment::FiscalYear),
+        ("seq", None) => Ok(Segment::Seq { width: 0 }),
+        ("seq", Some(spec)) => spec
+            .strip_prefix('0')
+            .and_then(|width| width.parse::<usize>().ok())
+            .filter(|width| (1..=18).contains(width))
+            .map(|width| Segment::Seq { width })
+            .ok_or_else(|| CodeFormatError::InvalidSeqWidth(spec.to_string())),
+        _ => Err(CodeFormatError::UnknownPlaceholder(placeholder.to_string())),
+    }
+}
+
+impl FromStr for CodeFormat {
+    type Err = CodeFormatError;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.char_indices().peekable();
+
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '{' if chars.peek().map(|(_, c)| *c) == Some('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek().map(|(_, c)| *c) == Some('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let end = template[index..]
+                        .find('}')
+                        .ok_or(CodeFormatError::UnterminatedPlaceholder(index))?;
+                    let placeholder = &template[index + 1..index + end];
+
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(parse_placeholder(placeholder)?);
+
+                    while chars.peek().is_some_and(|(i, _)| *i <= index + end) {
+                        chars.next();
+                    }
+                }
+                '}' => return Err(CodeFormatError::UnmatchedBrace(index)),
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        match segments
+            .iter()
+            .filter(|segment| matches!(segment, Segment::Seq { .. }))
+            .count()
+        {
+            0 => Err(CodeFormatError::MissingSeq),
+            1 => Ok(CodeFormat {
+                template: template.to_string(),
+                segments,
+            }),
+            _ => Err(CodeFormatError::DuplicateSeq),
+        }
+    }
+}
+
+impl Display for CodeFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.template)
+    }
+}
+
+impl Default for CodeFormat {
+    fn default() -> Self {
+        DEFAULT_CODE_FORMAT
+            .parse()
+            .expect("default code format is valid")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Local, TimeZone};
+
+    use crate::{
+        code_format::{CodeFormat, ResetPeriod},
+        errors::code_format::CodeFormatError,
+    };
+
+    #[test]
+    fn check_reset() {
+        let cases = [
+            ("{prefix}{date}.{seq}", ResetPeriod::Daily, true),
+            ("{prefix}-{seq:04}", ResetPeriod::Never, true),
+            ("{prefix}-{seq:04}", ResetPeriod::Daily, false),
+            // The first ISO week can start in December
+            ("{date:%Y}-W{isoweek}-{seq}", ResetPeriod::Weekly, false),
+            ("{date:%G}-W{isoweek}-{seq}", ResetPeriod::Weekly, true),
+            ("{month}-{seq}", ResetPeriod::Monthly, false),
+            ("{date:%Y}{month}-{seq}", ResetPeriod::Monthly, true),
+            ("{date:%Y}-{seq}", ResetPeriod::FiscalYear(4), false),
+            ("FY{fy:4}-{seq}", ResetPeriod::FiscalYear(4), true),
+            ("{date:%Y%m%d}-{seq}", ResetPeriod::Yearly, true),
+        ];
+
+        for (template, reset, valid) in cases {
+            let format = template.parse::<CodeFormat>().unwrap();
+            let result = format.check_reset(&reset);
+            assert_eq!(result.is_ok(), valid, "{} with {}", template, reset);
+        }
+    }
+
+    #[test]
+    fn render_default_format() {
+        let format = CodeFormat::default();
+        let timestamp = Local.with_ymd_and_hms(2024, 1, 6, 12, 0, 0).unwrap();
+
+        assert_eq!(format.render("V", &timestamp, 7), "V20240106.07");
+        assert_eq!(format.render("V", &timestamp, 123), "V20240106.123");
+    }
+
+    #[test]
+    fn render_custom_format() {
+        let format: CodeFormat = "{prefix}-{date:%y%m}-{seq:04}{{x}}".parse().unwrap();
+        let timestamp = Local.with_ymd_and_hms(2024, 1, 6, 12, 0, 0).unwrap();
+
+        assert_eq!(format.render("DOC", &timestamp, 5), "DOC-2401-0005{x}");
+    }
+
+    #[test]
+    fn parse_seq() {
+        let format: CodeFormat = "{prefix}{seq:03}/{date}".parse().unwrap();
+        let timestamp = Local.with_ymd_and_hms(2024, 1, 6, 12, 0, 0).unwrap();
+
+        assert_eq!(format.parse_seq("S", &timestamp, "S012/20240106"), Some(12));
+        assert_eq!(format.parse_seq("S", &timestamp, "S7/20240106"), Some(7));
+        assert_eq!(format.parse_seq("S", &timestamp, "S012/20240107"), None);
+        assert_eq!(format.parse_seq("S", &timestamp, "S0x2/20240106"), None);
+    }
+
+    #[test]
+    fn render_period_placeholders() {
+        let format: CodeFormat = "{fy:4}/{fy}/W{isoweek}/{month}/{seq}".parse().unwrap();
+        let timestamp = Local.with_ymd_and_hms(2024, 12, 30, 12, 0, 0).unwrap();
+
+        assert_eq!(format.render("", &timestamp, 1), "2025/2024/W01/12/1");
+    }
+
+    #[test]
+    fn period_keys() {
+        let timestamp = Local.with_ymd_and_hms(2024, 12, 30, 12, 0, 0).unwrap();
+        let cases = [
+            ("daily", "2024-12-30"),
+            ("weekly", "2025-W01"),
+            ("monthly", "2024-12"),
+            ("yearly", "2024"),
+            ("fiscal", "FY2024"),
+            ("fiscal:4", "FY2025"),
+            ("never", ""),
+        ];
+
+        for (reset, expected) in cases {
+            let reset: ResetPeriod = reset.parse().unwrap();
+            assert_eq!(reset.period_key(&timestamp), expected, "{}", reset);
+        }
+    }
+
+    #[test]
+    fn invalid_reset_periods() {
+        assert_eq!(
+            "hourly".parse::<ResetPeriod>(),
+            Err(CodeFormatError::InvalidResetPeriod("hourly".to_string()))
+        );
+        assert_eq!(
+            "fiscal:13".parse::<ResetPeriod>(),
+            Err(CodeFormatError::InvalidFiscalStart("13".to_string()))
+        );
+    }
+
+    #[test]
+    fn invalid_formats() {
+        let cases = [
+            ("{prefix}{date}", CodeFormatError::MissingSeq),
+            ("{seq}{seq}", CodeFormatError::DuplicateSeq),
+            ("{seq", CodeFormatError::UnterminatedPlaceholder(0)),
+            ("{seq}}", CodeFormatError::UnmatchedBrace(5)),
+            (
+                "{name}{seq}",
+                CodeFormatError::UnknownPlaceholder("name".to_string()),
+            ),
+            ("{seq:3}", CodeFormatError::InvalidSeqWidth("3".to_string())),
+            (
+                "{fy:0}{seq}",
+                CodeFormatError::InvalidFiscalStart("0".to_string()),
+            ),
+            (
+                "{date:%Q}{seq}",
+                CodeFormatError::InvalidDateFormat("%Q".to_string()),
+            ),
+        ];
+
+        for (template, expected) in cases {
+            assert_eq!(
+                template.parse::<CodeFormat>(),
+                Err(expected),
+                "{}",
+                template
+            );
+        }
+    }
+}
@@ -1,22 +1,35 @@
+use std::time::Duration;
+
+use chrono::{DateTime, FixedOffset, Utc};
+use chrono_tz::Tz;
 use sqlx::{
     error::ErrorKind,
     sqlite::{SqliteConnectOptions, SqlitePoolOptions},
-    SqlitePool,
+    SqliteConnection, SqliteExecutor, SqlitePool,
 };
 use tracing::info;
 
 use crate::{
+    code_format::CodeScheme,
     errors::{
         add_number::AddNumberError, check_user_password::CheckUserPasswordError,
-        create_user::CreateUserError, delete_user::DeleteUserError,
+        create_series::CreateSeriesError, create_user::CreateUserError,
+        delete_user::DeleteUserError, oidc_login::OidcLoginError,
         password_change::ChangePasswordError, read_user::ReadUserError, read_users::ReadUsersError,
-        reset_codes::ResetCodesError,
+        reset_codes::ResetCodesError, update_user::UpdateUserError, ApplicationError,
+    },
+    jwt::{dummy_password_hash, hash_password, verify_password},
+    models::{
+        parse_roles, ApiToken, ApiTokenEntity, AuditEvent, AuditEventEntity, AuditFilter, Code,
+        CodeEntity, Invite, InviteEntity, LoginFailureEntity, LoginSession, LoginSessionEntity,
+        NewApiToken, NewAuditEvent, NewInvite, NewLoginSession, NewSeries, PasswordResetEntity,
+        RefreshOutcome, ResetScope, SequenceCheck, Series, SeriesEntity, Settings, User,
+        UserEntity, UserTotpEntity,
     },
-    jwt::{hash_password, verify_password},
-    models::{Code, CodeEntity, CodeValue, CodeValueEntity, User, UserEntity},
+    permissions::Role,
 };
 
-pub async fn create_db_pool(path: &str) -> Result<SqlitePool, sqlx::Error> {
+pub async fn create_db_pool(path: &str) -> Result<SqlitePool, ApplicationError> {
     info!("Setting up database at {}", path);
     let opts = SqliteConnectOptions::new()
         .filename(path)
@@ -24,55 +37,142 @@ pub async fn create_db_pool(path: &str) -> Result<SqlitePool, sqlx::Error> {
 
     let db = SqlitePoolOptions::new().connect_with(opts).await?;
     info!("Connected to database");
-    sqlx::migrate!().run(&db).await?;
+    if let Err(err) = sqlx::migrate!().run(&db).await {
+        // Usernames can only be made unique once nobody shares one
+        let duplicates = read_duplicate_user_names(&db).await.unwrap_or_default();
+        if !duplicates.is_empty() {
+            return Err(ApplicationError::DuplicateUserNames(duplicates.join("; ")));
+        }
+        return Err(sqlx::Error::from(err).into());
+    }
     info!("Migrated database");
     Ok(db)
 }
 
-pub async fn read_last_ten(db: &SqlitePool) -> sqlx::Result<Vec<Code>> {
+/// Names shared by more than one user ignoring case, each listed with all its spellings.
+pub async fn read_duplicate_user_names(db: &SqlitePool) -> sqlx::Result<Vec<String>> {
+    sqlx::query_scalar!(
+        r#"
+				SELECT GROUP_CONCAT(name, ', ') as "names!: String"
+				FROM users
+				WHERE deleted_at IS NULL
+				GROUP BY name COLLATE NOCASE
+				HAVING COUNT(*) > 1
+			"#
+    )
+    .fetch_all(db)
+    .await
+}
+
+pub async fn read_last_ten(
+    db: &SqlitePool,
+    series_id: i64,
+    timezone: Tz,
+) -> sqlx::Result<Vec<Code>> {
     let users = sqlx::query_as!(
         CodeEntity,
         r#"
-				SELECT codes.id, code, created_at, users.id as user_id, users.name as user_name
+				SELECT codes.id, code, created_at as "created_at: DateTime<FixedOffset>", users.id as "user_id!", users.name as user_name
 				FROM codes
 				JOIN users ON codes.user_id = users.id
-				ORDER BY created_at DESC
+				WHERE codes.series_id = ? AND codes.reset_id IS NULL
+				ORDER BY JULIANDAY(created_at) DESC
 				LIMIT 10
 			"#,
+        series_id
     )
     .fetch_all(db)
     .await?;
 
-    Ok(users.into_iter().map(|x| x.into()).collect())
+    Ok(users.into_iter().map(|x| Code::new(x, timezone)).collect())
 }
 
-pub async fn read_latest_today(
-    db: &SqlitePool,
-    code_prefix: &str,
-) -> sqlx::Result<Option<CodeValue>> {
-    let pattern = format!("{}%", code_prefix);
-    let code = sqlx::query_as!(
-        CodeValueEntity,
+pub async fn read_max_seq(
+    db: impl SqliteExecutor<'_>,
+    series_id: i64,
+    period_key: &str,
+) -> sqlx::Result<Option<i64>> {
+    let seq = sqlx::query_scalar!(
         r#"
-	SELECT code
+	SELECT MAX(seq) as "seq: i64"
 	FROM codes
-	WHERE code LIKE ?
-	ORDER By created_at DESC
-	LIMIT 1
+	WHERE series_id = ? AND period_key = ? AND reset_id IS NULL
 "#,
-        pattern
+        series_id,
+        period_key
     )
-    .fetch_optional(db)
+    .fetch_one(db)
+    .await?;
+
+    Ok(seq)
+}
+
+/// Codes of the series still waiting for their sequence number to be checked.
+pub async fn read_sequence_checks(
+    db: &SqlitePool,
+    series_id: i64,
+) -> sqlx::Result<Vec<SequenceCheck>> {
+    sqlx::query_as!(
+        SequenceCheck,
+        r#"
+				SELECT codes.id, code, created_at as "created_at: DateTime<FixedOffset>", period_key, seq
+				FROM sequence_checks
+				JOIN codes ON codes.id = sequence_checks.code_id
+				WHERE series_id = ?
+				ORDER BY codes.id
+		"#,
+        series_id
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Stores the checked sequence numbers and raises the counters of the series past them.
+pub async fn save_sequence_checks(
+    db: &SqlitePool,
+    series_id: i64,
+    checks: &[SequenceCheck],
+) -> sqlx::Result<()> {
+    let mut tx = db.begin().await?;
+
+    for check in checks {
+        sqlx::query!(
+            "UPDATE codes SET period_key = ?, seq = ? WHERE id = ?",
+            check.period_key,
+            check.seq,
+            check.id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!("DELETE FROM sequence_checks WHERE code_id = ?", check.id)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    // Counters created before the check may have started below numbers that were already taken
+    sqlx::query!(
+        r#"
+		INSERT INTO counters (series_id, period_key, value)
+		SELECT series_id, period_key, MAX(seq)
+		FROM codes
+		WHERE series_id = ? AND period_key IS NOT NULL AND seq IS NOT NULL AND reset_id IS NULL
+		GROUP BY period_key
+		ON CONFLICT (series_id, period_key) DO UPDATE SET value = MAX(value, excluded.value)
+	"#,
+        series_id
+    )
+    .execute(&mut *tx)
     .await?;
 
-    Ok(code.map(|c| c.into()))
+    tx.commit().await
 }
 
-pub async fn read_code(db: &SqlitePool, id: i64) -> sqlx::Result<Option<Code>> {
+pub async fn read_code(db: &SqlitePool, id: i64, timezone: Tz) -> sqlx::Result<Option<Code>> {
     let code = sqlx::query_as!(
         CodeEntity,
         r#"
-				SELECT codes.id, code, created_at, users.id as user_id, users.name as user_name
+				SELECT codes.id, code, created_at as "created_at: DateTime<FixedOffset>", users.id as user_id, users.name as user_name
 				FROM codes
 				JOIN users ON codes.user_id = users.id
 				WHERE codes.id = ?
@@ -82,71 +182,344 @@ pub async fn read_code(db: &SqlitePool, id: i64) -> sqlx::Result<Option<Code>> {
     .fetch_optional(db)
     .await?;
 
-    Ok(code.map(|c| c.into()))
+    Ok(code.map(|c| Code::new(c, timezone)))
 }
 
-pub async fn reset_codes(db: &SqlitePool) -> sqlx::Result<(), ResetCodesError> {
-    sqlx::query!(
+/// Finds the series an existing code was reserved in, preferring active codes over archived ones.
+pub async fn read_code_series(db: &SqlitePool, code: &str) -> sqlx::Result<Option<i64>> {
+    let series_id = sqlx::query_scalar!(
         r#"
-		DELETE FROM codes
-	"#
+				SELECT series_id
+				FROM codes
+				WHERE code = ?
+				ORDER BY reset_id IS NOT NULL, reset_id DESC
+				LIMIT 1
+		"#,
+        code
     )
-    .execute(db)
+    .fetch_optional(db)
     .await?;
 
-    Ok(())
+    Ok(series_id)
 }
 
-pub async fn create_code(
+/// Archives the active codes in the scope, so their numbering starts over.
+///
+/// Returns the number of archived codes.
+pub async fn reset_codes(
     db: &SqlitePool,
-    code: &str,
-    user_id: &str,
-) -> sqlx::Result<Code, AddNumberError> {
-    let latest_code = read_latest_today(db, code).await?;
-    let suffix = match latest_code {
-        Some(code) => code
-            .code
-            .split('.')
-            .last()
-            .ok_or_else(|| AddNumberError::ParseSuffixError(code.code.clone()))?
-            .parse::<i64>()
-            .map_err(|_| AddNumberError::ParseSuffixError(code.code.clone()))?,
-        None => 0, // No existing code, start at 0
-    };
+    user_id: i64,
+    scope: &ResetScope,
+) -> sqlx::Result<u64, ResetCodesError> {
+    let ResetScope {
+        series_id,
+        from,
+        to,
+    } = scope;
+
+    let mut tx = db.begin().await?;
 
-    // Generate the new code
-    let new_code = format!("{}.{:0>2}", code, suffix + 1);
-    let users = sqlx::query_scalar!(
+    let reset = sqlx::query!(
         r#"
-		INSERT INTO codes (code, user_id)
-		VALUES (?, ?)
+		INSERT INTO resets (user_id, series_id, from_date, to_date)
+		VALUES (?, ?, ?, ?)
 	"#,
-        new_code,
-        user_id
+        user_id,
+        series_id,
+        from,
+        to
     )
-    .execute(db)
+    .execute(&mut *tx)
+    .await?;
+    let reset_id = reset.last_insert_rowid();
+
+    // Allocation continues after the codes left active once the counter is gone
+    sqlx::query!(
+        r#"
+		DELETE FROM counters
+		WHERE EXISTS (
+			SELECT 1
+			FROM codes
+			WHERE codes.series_id = counters.series_id
+				AND codes.period_key = counters.period_key
+				AND codes.reset_id IS NULL
+				AND (?1 IS NULL OR codes.series_id = ?1)
+				AND (?2 IS NULL OR SUBSTR(codes.created_at, 1, 10) >= ?2)
+				AND (?3 IS NULL OR SUBSTR(codes.created_at, 1, 10) <= ?3)
+		)
+	"#,
+        series_id,
+        from,
+        to
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let archived = sqlx::query!(
+        r#"
+		UPDATE codes
+		SET reset_id = ?1
+		WHERE reset_id IS NULL
+			AND (?2 IS NULL OR series_id = ?2)
+			AND (?3 IS NULL OR SUBSTR(created_at, 1, 10) >= ?3)
+			AND (?4 IS NULL OR SUBSTR(created_at, 1, 10) <= ?4)
+	"#,
+        reset_id,
+        series_id,
+        from,
+        to
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(archived.rows_affected())
+}
+
+/// Restores the codes archived by the most recent reset which wasn't undone yet.
+///
+/// Returns the number of restored codes.
+pub async fn undo_last_reset(db: &SqlitePool) -> sqlx::Result<u64, ResetCodesError> {
+    let mut tx = db.begin().await?;
+
+    let reset_id = sqlx::query_scalar!(
+        r#"
+		SELECT id
+		FROM resets
+		WHERE undone_at IS NULL
+		ORDER BY id DESC
+		LIMIT 1
+	"#
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(ResetCodesError::NothingToUndo)?;
+
+    sqlx::query!(
+        r#"
+		DELETE FROM counters
+		WHERE EXISTS (
+			SELECT 1
+			FROM codes
+			WHERE codes.series_id = counters.series_id
+				AND codes.period_key = counters.period_key
+				AND codes.reset_id = ?
+		)
+	"#,
+        reset_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let restored = sqlx::query!(
+        r#"
+		UPDATE codes
+		SET reset_id = NULL
+		WHERE reset_id = ?
+	"#,
+        reset_id
+    )
+    .execute(&mut *tx)
     .await
     .map_err(|e: sqlx::Error| {
         if let Some(db_error) = e.as_database_error() {
             if db_error.kind() == ErrorKind::UniqueViolation {
-                return AddNumberError::DuplicateCode(new_code.to_string());
+                return ResetCodesError::UndoConflict;
             }
         }
-        AddNumberError::DbError(e)
+        ResetCodesError::DbError(e)
     })?;
 
-    let code = read_code(db, users.last_insert_rowid()).await?.unwrap();
+    sqlx::query!(
+        r#"
+		UPDATE resets
+		SET undone_at = STRFTIME('%Y-%m-%dT%H:%M:%f', 'NOW') || '+00:00'
+		WHERE id = ?
+	"#,
+        reset_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(restored.rows_affected())
+}
+
+/// How many times a reservation is attempted when the database is locked by another writer.
+const MAX_ALLOCATION_ATTEMPTS: u32 = 5;
+
+pub async fn create_code(
+    db: &SqlitePool,
+    scheme: &CodeScheme,
+    timestamp: DateTime<Tz>,
+    user_id: &str,
+) -> sqlx::Result<Code, AddNumberError> {
+    let mut codes = create_codes(db, scheme, timestamp, user_id, 1).await?;
+
+    Ok(codes.remove(0))
+}
+
+/// Reserves a block of `count` consecutive numbers, ordered from the lowest one.
+pub async fn create_codes(
+    db: &SqlitePool,
+    scheme: &CodeScheme,
+    timestamp: DateTime<Tz>,
+    user_id: &str,
+    count: u32,
+) -> sqlx::Result<Vec<Code>, AddNumberError> {
+    let mut attempt = 1;
+
+    loop {
+        match allocate_codes(db, scheme, &timestamp, user_id, count).await {
+            Err(AddNumberError::DbError(e)) if is_busy(&e) => {
+                if attempt == MAX_ALLOCATION_ATTEMPTS {
+                    return Err(AddNumberError::AllocationConflict(attempt));
+                }
+
+                info!("Database busy while allocating a code, attempt {}", attempt);
+                tokio::time::sleep(Duration::from_millis(20 * attempt as u64)).await;
+                attempt += 1;
+            }
+            Ok(ids) => {
+                let mut codes = Vec::with_capacity(ids.len());
+                for id in ids {
+                    codes.push(read_code(db, id, scheme.timezone).await?.unwrap());
+                }
+                return Ok(codes);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Allocates the next `count` numbers of the sequence and inserts the codes in a single write transaction.
+async fn allocate_codes(
+    db: &SqlitePool,
+    scheme: &CodeScheme,
+    timestamp: &DateTime<Tz>,
+    user_id: &str,
+    count: u32,
+) -> sqlx::Result<Vec<i64>, AddNumberError> {
+    let CodeScheme {
+        series_id,
+        prefix,
+        format,
+        reset,
+        check_digit,
+        ..
+    } = scheme;
+
+    let period_key = reset.period_key(timestamp);
+    // Stored with the offset, so the time matches the date in the code
+    let created_at = timestamp.fixed_offset();
+
+    let mut tx = db.begin().await?;
+
+    // Writing first takes the database write lock, so nobody else can allocate until we commit
+    let created = sqlx::query!(
+        r#"
+		INSERT INTO counters (series_id, period_key, value)
+		VALUES (?, ?, 0)
+		ON CONFLICT (series_id, period_key) DO NOTHING
+	"#,
+        series_id,
+        period_key
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let last_seq = if created.rows_affected() == 1 {
+        // New counter, continue after codes issued before counters existed
+        read_max_seq(&mut *tx, *series_id, &period_key)
+            .await?
+            .unwrap_or(0) // No existing code, start at 0
+    } else {
+        sqlx::query_scalar!(
+            r#"
+			SELECT value
+			FROM counters
+			WHERE series_id = ? AND period_key = ?
+		"#,
+            series_id,
+            period_key
+        )
+        .fetch_one(&mut *tx)
+        .await?
+    };
+
+    // Generate the new codes
+    let mut ids = Vec::with_capacity(count as usize);
+    let mut seq = last_seq;
+    for _ in 0..count {
+        seq += 1;
+        let mut new_code = format.render(prefix, timestamp, seq);
+        if let Some(check_digit) = check_digit {
+            new_code = check_digit.append(&new_code);
+        }
+        let inserted = sqlx::query!(
+            r#"
+			INSERT INTO codes (code, user_id, series_id, period_key, seq, created_at)
+			VALUES (?, ?, ?, ?, ?, ?)
+		"#,
+            new_code,
+            user_id,
+            series_id,
+            period_key,
+            seq,
+            created_at
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|e: sqlx::Error| {
+            if let Some(db_error) = e.as_database_error() {
+                if db_error.kind() == ErrorKind::UniqueViolation {
+                    return AddNumberError::DuplicateCode(new_code.to_string());
+                }
+            }
+            AddNumberError::DbError(e)
+        })?;
+
+        ids.push(inserted.last_insert_rowid());
+    }
+
+    sqlx::query!(
+        r#"
+		UPDATE counters
+		SET value = ?
+		WHERE series_id = ? AND period_key = ?
+	"#,
+        seq,
+        series_id,
+        period_key
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(ids)
+}
 
-    Ok(code)
+/// Checks for `SQLITE_BUSY` and `SQLITE_LOCKED`, including their extended codes.
+fn is_busy(e: &sqlx::Error) -> bool {
+    e.as_database_error()
+        .and_then(|db_error| db_error.code())
+        .and_then(|code| code.parse::<i32>().ok())
+        .is_some_and(|code| matches!(code & 0xff, 5 | 6))
 }
 
 pub async fn read_user_by_id(db: &SqlitePool, user_id: &str) -> sqlx::Result<User, ReadUserError> {
     let user = sqlx::query_as!(
         UserEntity,
         r#"
-					SELECT id, name, password, is_admin
+					SELECT users.id as "id!", users.name as "name!", users.password as "password!", GROUP_CONCAT(roles.name) as "roles: String", EXISTS(SELECT 1 FROM user_totp WHERE user_totp.user_id = users.id) as "two_factor!: bool", COALESCE((SELECT failures FROM login_failures WHERE key = 'user:' || LOWER(users.name)), 0) as "failed_logins!: i64", users.must_change_password as "must_change_password!: bool", users.deactivated as "deactivated!: bool"
 					FROM users
-					WHERE id = ?
+					LEFT JOIN user_roles ON user_roles.user_id = users.id
+					LEFT JOIN roles ON roles.id = user_roles.role_id
+					WHERE users.id = ?
+					GROUP BY users.id
 				"#,
         user_id
     )
@@ -168,20 +541,23 @@ pub async fn check_email_password(
     let user = sqlx::query_as!(
         UserEntity,
         r#"
-					SELECT id, name, password, is_admin
+					SELECT users.id as "id!", users.name as "name!", users.password as "password!", GROUP_CONCAT(roles.name) as "roles: String", EXISTS(SELECT 1 FROM user_totp WHERE user_totp.user_id = users.id) as "two_factor!: bool", COALESCE((SELECT failures FROM login_failures WHERE key = 'user:' || LOWER(users.name)), 0) as "failed_logins!: i64", users.must_change_password as "must_change_password!: bool", users.deactivated as "deactivated!: bool"
 					FROM users
-					WHERE name = ?
+					LEFT JOIN user_roles ON user_roles.user_id = users.id
+					LEFT JOIN roles ON roles.id = user_roles.role_id
+					WHERE users.name = ? COLLATE NOCASE AND users.deleted_at IS NULL
+					GROUP BY users.id
 				"#,
         email
     )
     .fetch_optional(db)
     .await?;
 
-    if user.is_none() {
+    // Hashing anyway takes as long as for a real user, so the time doesn't tell names apart
+    let Some(user) = user else {
+        verify_password(&password, dummy_password_hash());
         return Err(CheckUserPasswordError::NotValid);
-    }
-
-    let user = user.unwrap();
+    };
 
     if verify_password(&password, &user.password) {
         Ok(user.into())
@@ -198,7 +574,7 @@ pub async fn change_password(
     sqlx::query!(
         r#"
 				UPDATE users
-				SET password = ?
+				SET password = ?, must_change_password = 0
 				WHERE id = ?
 			"#,
         hashed_password,
@@ -215,8 +591,13 @@ pub async fn read_all_users(db: &SqlitePool) -> sqlx::Result<Vec<User>, ReadUser
     let user = sqlx::query_as!(
         UserEntity,
         r#"
-				SELECT id, name, password, is_admin
+				SELECT users.id as "id!", users.name as "name!", users.password as "password!", GROUP_CONCAT(roles.name) as "roles: String", EXISTS(SELECT 1 FROM user_totp WHERE user_totp.user_id = users.id) as "two_factor!: bool", COALESCE((SELECT failures FROM login_failures WHERE key = 'user:' || LOWER(users.name)), 0) as "failed_logins!: i64", users.must_change_password as "must_change_password!: bool", users.deactivated as "deactivated!: bool"
 				FROM users
+				LEFT JOIN user_roles ON user_roles.user_id = users.id
+				LEFT JOIN roles ON roles.id = user_roles.role_id
+				WHERE users.deleted_at IS NULL
+				GROUP BY users.id
+				ORDER BY users.id
 			"#
     )
     .fetch_all(db)
@@ -229,9 +610,12 @@ pub async fn read_user(db: &SqlitePool, id: i64) -> sqlx::Result<Option<User>, R
     let user = sqlx::query_as!(
         UserEntity,
         r#"
-				SELECT id, name, password, is_admin
+				SELECT users.id as "id!", users.name as "name!", users.password as "password!", GROUP_CONCAT(roles.name) as "roles: String", EXISTS(SELECT 1 FROM user_totp WHERE user_totp.user_id = users.id) as "two_factor!: bool", COALESCE((SELECT failures FROM login_failures WHERE key = 'user:' || LOWER(users.name)), 0) as "failed_logins!: i64", users.must_change_password as "must_change_password!: bool", users.deactivated as "deactivated!: bool"
 				FROM users
-				WHERE id = ?
+				LEFT JOIN user_roles ON user_roles.user_id = users.id
+				LEFT JOIN roles ON roles.id = user_roles.role_id
+				WHERE users.id = ? AND users.deleted_at IS NULL
+				GROUP BY users.id
 			"#,
         id
     )
@@ -241,60 +625,1548 @@ pub async fn read_user(db: &SqlitePool, id: i64) -> sqlx::Result<Option<User>, R
     Ok(user.map(|x| x.into()))
 }
 
-pub async fn delete_user(db: &SqlitePool, id: i64) -> sqlx::Result<(), DeleteUserError> {
-    let count = sqlx::query_scalar!(
+/// Deletes the user, their codes go to the other user when given.
+///
+/// Users who still own codes or made resets keep their row under a tombstone name, so the history
+/// stays complete. They lose everything that would let them log in again.
+pub async fn delete_user(
+    db: &SqlitePool,
+    id: i64,
+    reassign_to: Option<i64>,
+    now: DateTime<FixedOffset>,
+) -> sqlx::Result<(), DeleteUserError> {
+    let mut tx = db.begin().await?;
+
+    if is_last_super_admin(&mut *tx, id).await? {
+        Err(DeleteUserError::CantDeleteLastAdmin)?;
+    }
+
+    if let Some(reassign_to) = reassign_to {
+        let exists = sqlx::query_scalar!(
+            r#"
+					SELECT EXISTS(
+						SELECT 1 FROM users WHERE id = ? AND deleted_at IS NULL
+					) as "exists!: bool"
+				"#,
+            reassign_to
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if reassign_to == id || !exists {
+            Err(DeleteUserError::InvalidReassignment)?;
+        }
+
+        sqlx::query!(
+            r#"
+		UPDATE codes
+		SET user_id = ?
+		WHERE user_id = ?
+	"#,
+            reassign_to,
+            id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    let referenced = sqlx::query_scalar!(
         r#"
-					SELECT COUNT(*) FROM users
-					WHERE id <> ? and is_admin = 1
+					SELECT (
+						EXISTS(SELECT 1 FROM codes WHERE user_id = ?1)
+						OR EXISTS(SELECT 1 FROM resets WHERE user_id = ?1)
+					) as "referenced!: bool"
 				"#,
         id
     )
-    .fetch_one(db)
+    .fetch_one(&mut *tx)
     .await?;
 
-    if count == 0 {
-        Err(DeleteUserError::CantDeleteLastAdmin)?;
+    if !referenced {
+        sqlx::query!(
+            r#"
+					DELETE FROM users
+					WHERE id = ?
+				"#,
+            id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        return Ok(());
     }
 
-    sqlx::query_as!(
-        UserEntity,
+    sqlx::query!(
         r#"
-					DELETE FROM users
-					WHERE id = ?
+		UPDATE users
+		SET name = 'Deleted user #' || id, password = '', oidc_subject = NULL,
+			must_change_password = 0, deactivated = 1, deleted_at = ?
+		WHERE id = ?
+	"#,
+        now,
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    // What deleting the row would have cascaded to
+    sqlx::query!(
+        r#"
+		DELETE FROM user_roles WHERE user_id = ?;
+		DELETE FROM api_tokens WHERE user_id = ?;
+		DELETE FROM login_sessions WHERE user_id = ?;
+		DELETE FROM user_totp WHERE user_id = ?;
+		DELETE FROM recovery_codes WHERE user_id = ?;
+		DELETE FROM password_resets WHERE user_id = ?;
+	"#,
+        id,
+        id,
+        id,
+        id,
+        id,
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Whether the user is the only super admin who can still log in, the application can't do
+/// without them.
+async fn is_last_super_admin(db: impl SqliteExecutor<'_>, id: i64) -> sqlx::Result<bool> {
+    sqlx::query_scalar!(
+        r#"
+					SELECT EXISTS(
+						SELECT 1 FROM user_roles
+						JOIN roles ON roles.id = user_roles.role_id
+						JOIN users ON users.id = user_roles.user_id
+						WHERE user_id = ?1 AND roles.name = 'super_admin' AND users.deactivated = 0
+					) AND NOT EXISTS(
+						SELECT 1 FROM user_roles
+						JOIN roles ON roles.id = user_roles.role_id
+						JOIN users ON users.id = user_roles.user_id
+						WHERE user_id <> ?1 AND roles.name = 'super_admin' AND users.deactivated = 0
+					) as "last!: bool"
 				"#,
         id
     )
-    .execute(db)
+    .fetch_one(db)
+    .await
+}
+
+/// Renames the user and replaces the roles, the last super admin keeps the role.
+pub async fn update_user(
+    db: &SqlitePool,
+    id: i64,
+    name: &str,
+    roles: &[Role],
+) -> sqlx::Result<(), UpdateUserError> {
+    let mut tx = db.begin().await?;
+
+    if !roles.contains(&Role::SuperAdmin) && is_last_super_admin(&mut *tx, id).await? {
+        Err(UpdateUserError::CantDemoteLastAdmin)?;
+    }
+
+    sqlx::query!(
+        r#"
+		UPDATE users
+		SET name = ?
+		WHERE id = ?
+	"#,
+        name,
+        id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e: sqlx::Error| {
+        if let Some(db_error) = e.as_database_error() {
+            if db_error.kind() == ErrorKind::UniqueViolation {
+                return UpdateUserError::NameTaken(name.to_string());
+            }
+        }
+        UpdateUserError::DbError(e)
+    })?;
+
+    sqlx::query!(
+        r#"
+		DELETE FROM user_roles
+		WHERE user_id = ?
+	"#,
+        id
+    )
+    .execute(&mut *tx)
     .await?;
 
+    insert_user_roles(&mut tx, id, roles).await?;
+
+    tx.commit().await?;
+
     Ok(())
 }
 
-pub async fn create_user(
+/// Blocks or allows logging in as the user, deactivating ends the sessions of the user.
+pub async fn set_user_deactivated(
     db: &SqlitePool,
-    username: String,
-    password: String,
-    is_admin: bool,
-) -> sqlx::Result<User, CreateUserError> {
-    let hashed_password = hash_password(&password);
+    id: i64,
+    deactivated: bool,
+) -> sqlx::Result<(), UpdateUserError> {
+    let mut tx = db.begin().await?;
 
-    let user = sqlx::query_as!(
-        UserEntity,
+    if deactivated && is_last_super_admin(&mut *tx, id).await? {
+        Err(UpdateUserError::CantDeactivateLastAdmin)?;
+    }
+
+    sqlx::query!(
         r#"
-		INSERT INTO users (name, password, is_admin)
-		VALUES (?, ?, ?)
+		UPDATE users
+		SET deactivated = ?
+		WHERE id = ?
+	"#,
+        deactivated,
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    if deactivated {
+        sqlx::query!(
+            r#"
+		DELETE FROM login_sessions
+		WHERE user_id = ?
+	"#,
+            id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Whether any super admin exists, otherwise the application still needs its first one.
+pub async fn has_super_admin(db: &SqlitePool) -> sqlx::Result<bool> {
+    sqlx::query_scalar!(
+        r#"
+					SELECT EXISTS(
+						SELECT 1 FROM user_roles
+						JOIN roles ON roles.id = user_roles.role_id
+						WHERE roles.name = 'super_admin'
+					) as "exists!: bool"
+				"#
+    )
+    .fetch_one(db)
+    .await
+}
+
+/// Creates the first super admin, returns `None` when there already is one.
+pub async fn create_first_admin(
+    db: &SqlitePool,
+    username: &str,
+    password: &str,
+) -> sqlx::Result<Option<User>, CreateUserError> {
+    let hashed_password = hash_password(password);
+
+    let mut tx = db.begin().await?;
+
+    // Checked in the same statement, two setups at once can't both succeed
+    let user_id = sqlx::query_scalar!(
+        r#"
+		INSERT INTO users (name, password)
+		SELECT ?, ?
+		WHERE NOT EXISTS (
+			SELECT 1 FROM user_roles
+			JOIN roles ON roles.id = user_roles.role_id
+			WHERE roles.name = 'super_admin'
+		)
+		RETURNING id
 	"#,
         username,
         hashed_password,
-        is_admin
     )
-    .execute(db)
-    .await?;
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e: sqlx::Error| {
+        if let Some(db_error) = e.as_database_error() {
+            if db_error.kind() == ErrorKind::UniqueViolation {
+                return CreateUserError::DuplicateName(username.to_string());
+            }
+        }
+        CreateUserError::DbError(e)
+    })?;
+
+    let Some(user_id) = user_id else {
+        return Ok(None);
+    };
+
+    insert_user_roles(&mut tx, user_id, &[Role::SuperAdmin]).await?;
+
+    tx.commit().await?;
+
+    match read_user(db, user_id).await {
+        Ok(Some(user)) => Ok(Some(user)),
+        _ => Err(CreateUserError::CantRead),
+    }
+}
+
+async fn insert_user_roles(
+    db: &mut SqliteConnection,
+    user_id: i64,
+    roles: &[Role],
+) -> sqlx::Result<()> {
+    for role in roles {
+        let role = role.as_str();
+        sqlx::query!(
+            r#"
+			INSERT INTO user_roles (user_id, role_id)
+			SELECT ?, id FROM roles WHERE name = ?
+		"#,
+            user_id,
+            role
+        )
+        .execute(&mut *db)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Replaces all roles of the user, the last super admin keeps the role.
+///
+/// Returns the roles the user ends up with.
+pub async fn set_user_roles(
+    db: &SqlitePool,
+    user_id: i64,
+    roles: &[Role],
+) -> sqlx::Result<Vec<Role>> {
+    let mut tx = db.begin().await?;
+
+    let mut roles = roles.to_vec();
+    if !roles.contains(&Role::SuperAdmin) && is_last_super_admin(&mut *tx, user_id).await? {
+        roles.push(Role::SuperAdmin);
+    }
+
+    sqlx::query!(
+        r#"
+		DELETE FROM user_roles
+		WHERE user_id = ?
+	"#,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    insert_user_roles(&mut tx, user_id, &roles).await?;
+
+    tx.commit().await?;
+
+    Ok(roles)
+}
+
+/// Reads the user linked to the account at the identity provider.
+pub async fn read_user_by_oidc_subject(
+    db: &SqlitePool,
+    subject: &str,
+) -> sqlx::Result<Option<User>> {
+    let user = sqlx::query_as!(
+        UserEntity,
+        r#"
+				SELECT users.id as "id!", users.name as "name!", users.password as "password!", GROUP_CONCAT(roles.name) as "roles: String", EXISTS(SELECT 1 FROM user_totp WHERE user_totp.user_id = users.id) as "two_factor!: bool", COALESCE((SELECT failures FROM login_failures WHERE key = 'user:' || LOWER(users.name)), 0) as "failed_logins!: i64", users.must_change_password as "must_change_password!: bool", users.deactivated as "deactivated!: bool"
+				FROM users
+				LEFT JOIN user_roles ON user_roles.user_id = users.id
+				LEFT JOIN roles ON roles.id = user_roles.role_id
+				WHERE users.oidc_subject = ?
+				GROUP BY users.id
+			"#,
+        subject
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(user.map(|x| x.into()))
+}
+
+/// Creates a user logging in through the identity provider.
+///
+/// Its password stays empty, which never verifies, so it can't log in locally.
+pub async fn create_oidc_user(
+    db: &SqlitePool,
+    name: &str,
+    subject: &str,
+    roles: &[Role],
+) -> Result<User, OidcLoginError> {
+    let mut tx = db.begin().await?;
+
+    // Taking over a local account would hand its history to whoever got the name at the provider
+    let user_id = sqlx::query!(
+        r#"
+		INSERT INTO users (name, password, oidc_subject)
+		VALUES (?, '', ?)
+	"#,
+        name,
+        subject,
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e: sqlx::Error| {
+        if let Some(db_error) = e.as_database_error() {
+            if db_error.kind() == ErrorKind::UniqueViolation {
+                return OidcLoginError::NameTaken(name.to_string());
+            }
+        }
+        OidcLoginError::DbError(e)
+    })?
+    .last_insert_rowid();
+
+    insert_user_roles(&mut tx, user_id, roles).await?;
+
+    tx.commit().await?;
+
+    read_user_by_oidc_subject(db, subject)
+        .await?
+        .ok_or(OidcLoginError::DbError(sqlx::Error::RowNotFound))
+}
+
+pub async fn read_all_series(db: &SqlitePool) -> sqlx::Result<Vec<Series>> {
+    let series = sqlx::query_as!(
+        SeriesEntity,
+        r#"
+				SELECT id, name, prefix, format, reset_period, check_digit, timezone, description, is_active
+				FROM series
+				ORDER BY id
+			"#
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(series.into_iter().map(|x| x.into()).collect())
+}
+
+pub async fn read_series(db: &SqlitePool, id: i64) -> sqlx::Result<Option<Series>> {
+    let series = sqlx::query_as!(
+        SeriesEntity,
+        r#"
+				SELECT id, name, prefix, format, reset_period, check_digit, timezone, description, is_active
+				FROM series
+				WHERE id = ?
+			"#,
+        id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(series.map(|x| x.into()))
+}
+
+pub async fn create_series(
+    db: &SqlitePool,
+    series: &NewSeries<'_>,
+) -> sqlx::Result<Series, CreateSeriesError> {
+    let NewSeries {
+        name,
+        prefix,
+        format,
+        reset,
+        check_digit,
+        timezone,
+        description,
+    } = series;
+
+    let reset_period = reset.to_string();
+    let check_digit = check_digit.map(|c| c.to_string());
+    let timezone = timezone.map(|tz| tz.name());
+    let series = sqlx::query!(
+        r#"
+		INSERT INTO series (name, prefix, format, reset_period, check_digit, timezone, description)
+		VALUES (?, ?, ?, ?, ?, ?, ?)
+	"#,
+        name,
+        prefix,
+        format,
+        reset_period,
+        check_digit,
+        timezone,
+        description
+    )
+    .execute(db)
+    .await
+    .map_err(|e: sqlx::Error| {
+        if let Some(db_error) = e.as_database_error() {
+            if db_error.kind() == ErrorKind::UniqueViolation {
+                return CreateSeriesError::DuplicateName(name.to_string());
+            }
+        }
+        CreateSeriesError::DbError(e)
+    })?;
+
+    read_series(db, series.last_insert_rowid())
+        .await?
+        .ok_or(CreateSeriesError::CantRead)
+}
+
+pub async fn set_series_active(
+    db: &SqlitePool,
+    id: i64,
+    is_active: bool,
+) -> sqlx::Result<Option<Series>> {
+    sqlx::query!(
+        r#"
+				UPDATE series
+				SET is_active = ?
+				WHERE id = ?
+			"#,
+        is_active,
+        id
+    )
+    .execute(db)
+    .await?;
+
+    read_series(db, id).await
+}
+
+pub async fn create_audit_event(db: &SqlitePool, event: &NewAuditEvent) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+		INSERT INTO audit_events (created_at, actor_id, actor_name, action, target, before_json, after_json, ip, user_agent)
+		VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+	"#,
+        event.created_at,
+        event.actor_id,
+        event.actor_name,
+        event.action,
+        event.target,
+        event.before_json,
+        event.after_json,
+        event.ip,
+        event.user_agent
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Reads one page of the audit log, newest first, together with the number of all matching events.
+pub async fn read_audit_events(
+    db: &SqlitePool,
+    filter: &AuditFilter,
+    limit: i64,
+    offset: i64,
+) -> sqlx::Result<(Vec<AuditEvent>, i64)> {
+    let AuditFilter {
+        actor,
+        action,
+        from,
+        to,
+    } = filter;
+
+    let events = sqlx::query_as!(
+        AuditEventEntity,
+        r#"
+				SELECT id, created_at as "created_at: DateTime<FixedOffset>", actor_name, action, target, before_json, after_json, ip, user_agent
+				FROM audit_events
+				WHERE (?1 IS NULL OR actor_name = ?1)
+					AND (?2 IS NULL OR action = ?2)
+					AND (?3 IS NULL OR SUBSTR(created_at, 1, 10) >= ?3)
+					AND (?4 IS NULL OR SUBSTR(created_at, 1, 10) <= ?4)
+				ORDER BY id DESC
+				LIMIT ?5 OFFSET ?6
+			"#,
+        actor,
+        action,
+        from,
+        to,
+        limit,
+        offset
+    )
+    .fetch_all(db)
+    .await?;
+
+    let total = sqlx::query_scalar!(
+        r#"
+				SELECT COUNT(*)
+				FROM audit_events
+				WHERE (?1 IS NULL OR actor_name = ?1)
+					AND (?2 IS NULL OR action = ?2)
+					AND (?3 IS NULL OR SUBSTR(created_at, 1, 10) >= ?3)
+					AND (?4 IS NULL OR SUBSTR(created_at, 1, 10) <= ?4)
+			"#,
+        actor,
+        action,
+        from,
+        to
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok((events.into_iter().map(|x| x.into()).collect(), total))
+}
+
+pub async fn create_api_token(
+    db: &SqlitePool,
+    token: &NewApiToken<'_>,
+    timezone: Tz,
+) -> sqlx::Result<ApiToken> {
+    let scopes = token
+        .scopes
+        .iter()
+        .map(|permission| permission.as_str())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let id = sqlx::query!(
+        r#"
+		INSERT INTO api_tokens (user_id, name, token_hash, token_prefix, scopes, created_at, expires_at)
+		VALUES (?, ?, ?, ?, ?, ?, ?)
+	"#,
+        token.user_id,
+        token.name,
+        token.token_hash,
+        token.token_prefix,
+        scopes,
+        token.created_at,
+        token.expires_at
+    )
+    .execute(db)
+    .await?
+    .last_insert_rowid();
+
+    read_api_token(db, id, timezone)
+        .await?
+        .ok_or(sqlx::Error::RowNotFound)
+}
+
+pub async fn read_api_token(
+    db: &SqlitePool,
+    id: i64,
+    timezone: Tz,
+) -> sqlx::Result<Option<ApiToken>> {
+    let token = sqlx::query_as!(
+        ApiTokenEntity,
+        r#"
+				SELECT id, user_id, name, token_prefix, scopes,
+					created_at as "created_at: DateTime<FixedOffset>",
+					expires_at as "expires_at: DateTime<FixedOffset>",
+					last_used_at as "last_used_at: DateTime<FixedOffset>"
+				FROM api_tokens
+				WHERE id = ?
+			"#,
+        id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(token.map(|x| ApiToken::new(x, timezone)))
+}
+
+pub async fn read_api_token_by_hash(
+    db: &SqlitePool,
+    token_hash: &str,
+    timezone: Tz,
+) -> sqlx::Result<Option<ApiToken>> {
+    let token = sqlx::query_as!(
+        ApiTokenEntity,
+        r#"
+				SELECT id, user_id, name, token_prefix, scopes,
+					created_at as "created_at: DateTime<FixedOffset>",
+					expires_at as "expires_at: DateTime<FixedOffset>",
+					last_used_at as "last_used_at: DateTime<FixedOffset>"
+				FROM api_tokens
+				WHERE token_hash = ?
+			"#,
+        token_hash
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(token.map(|x| ApiToken::new(x, timezone)))
+}
+
+/// Reads the tokens of the user, newest first.
+pub async fn read_api_tokens(
+    db: &SqlitePool,
+    user_id: i64,
+    timezone: Tz,
+) -> sqlx::Result<Vec<ApiToken>> {
+    let tokens = sqlx::query_as!(
+        ApiTokenEntity,
+        r#"
+				SELECT id, user_id, name, token_prefix, scopes,
+					created_at as "created_at: DateTime<FixedOffset>",
+					expires_at as "expires_at: DateTime<FixedOffset>",
+					last_used_at as "last_used_at: DateTime<FixedOffset>"
+				FROM api_tokens
+				WHERE user_id = ?
+				ORDER BY id DESC
+			"#,
+        user_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(tokens
+        .into_iter()
+        .map(|x| ApiToken::new(x, timezone))
+        .collect())
+}
+
+pub async fn touch_api_token(
+    db: &SqlitePool,
+    id: i64,
+    used_at: DateTime<FixedOffset>,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+				UPDATE api_tokens
+				SET last_used_at = ?
+				WHERE id = ?
+			"#,
+        used_at,
+        id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Deletes the token if it belongs to the user, returns whether it did.
+pub async fn delete_api_token(db: &SqlitePool, user_id: i64, id: i64) -> sqlx::Result<bool> {
+    let result = sqlx::query!(
+        r#"
+				DELETE FROM api_tokens
+				WHERE id = ? AND user_id = ?
+			"#,
+        id,
+        user_id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Stores a new login session, forgetting the expired ones of the user.
+pub async fn create_login_session(
+    db: &SqlitePool,
+    session: &NewLoginSession<'_>,
+) -> sqlx::Result<()> {
+    let mut tx = db.begin().await?;
+
+    sqlx::query!(
+        r#"
+				DELETE FROM login_sessions
+				WHERE user_id = ? AND JULIANDAY(expires_at) <= JULIANDAY('now')
+			"#,
+        session.user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+		INSERT INTO login_sessions (id, user_id, created_at, last_seen_at, expires_at, ip, user_agent)
+		VALUES (?, ?, ?, ?, ?, ?, ?)
+	"#,
+        session.id,
+        session.user_id,
+        session.created_at,
+        session.created_at,
+        session.expires_at,
+        session.ip,
+        session.user_agent
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+		INSERT INTO refresh_tokens (hash, session_id, created_at)
+		VALUES (?, ?, ?)
+	"#,
+        session.refresh_hash,
+        session.id,
+        session.created_at
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Reads the session unless it expired or was revoked.
+pub async fn read_login_session(
+    db: &SqlitePool,
+    id: &str,
+    timezone: Tz,
+) -> sqlx::Result<Option<LoginSession>> {
+    let session = sqlx::query_as!(
+        LoginSessionEntity,
+        r#"
+				SELECT id, user_id,
+					created_at as "created_at: DateTime<FixedOffset>",
+					last_seen_at as "last_seen_at: DateTime<FixedOffset>",
+					ip, user_agent
+				FROM login_sessions
+				WHERE id = ? AND JULIANDAY(expires_at) > JULIANDAY('now')
+			"#,
+        id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(session.map(|x| LoginSession::new(x, timezone)))
+}
+
+/// Reads the active sessions of the user, most recently used first.
+pub async fn read_login_sessions(
+    db: &SqlitePool,
+    user_id: i64,
+    timezone: Tz,
+) -> sqlx::Result<Vec<LoginSession>> {
+    let sessions = sqlx::query_as!(
+        LoginSessionEntity,
+        r#"
+				SELECT id, user_id,
+					created_at as "created_at: DateTime<FixedOffset>",
+					last_seen_at as "last_seen_at: DateTime<FixedOffset>",
+					ip, user_agent
+				FROM login_sessions
+				WHERE user_id = ? AND JULIANDAY(expires_at) > JULIANDAY('now')
+				ORDER BY JULIANDAY(last_seen_at) DESC
+			"#,
+        user_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(sessions
+        .into_iter()
+        .map(|x| LoginSession::new(x, timezone))
+        .collect())
+}
+
+pub async fn touch_login_session(
+    db: &SqlitePool,
+    id: &str,
+    seen_at: DateTime<FixedOffset>,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+				UPDATE login_sessions
+				SET last_seen_at = ?
+				WHERE id = ?
+			"#,
+        seen_at,
+        id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Deletes the session if it belongs to the user, returns whether it did.
+pub async fn delete_login_session(db: &SqlitePool, user_id: i64, id: &str) -> sqlx::Result<bool> {
+    let result = sqlx::query!(
+        r#"
+				DELETE FROM login_sessions
+				WHERE id = ? AND user_id = ?
+			"#,
+        id,
+        user_id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Deletes all sessions of the user except the given one, returns how many were deleted.
+pub async fn delete_login_sessions(
+    db: &SqlitePool,
+    user_id: i64,
+    except: Option<&str>,
+) -> sqlx::Result<u64> {
+    let result = sqlx::query!(
+        r#"
+				DELETE FROM login_sessions
+				WHERE user_id = ?1 AND (?2 IS NULL OR id <> ?2)
+			"#,
+        user_id,
+        except
+    )
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Exchanges the refresh token for the new one, detecting reuse of tokens already exchanged.
+///
+/// A token exchanged less than `grace` ago is let through without storing the new one, since
+/// parallel requests of one page all try to refresh with the same token.
+pub async fn rotate_refresh_token(
+    db: &SqlitePool,
+    hash: &str,
+    new_hash: &str,
+    now: DateTime<FixedOffset>,
+    grace: chrono::Duration,
+) -> sqlx::Result<RefreshOutcome> {
+    let mut tx = db.begin().await?;
+
+    let token = sqlx::query!(
+        r#"
+				SELECT refresh_tokens.session_id, login_sessions.user_id,
+					refresh_tokens.used_at as "used_at: DateTime<FixedOffset>"
+				FROM refresh_tokens
+				JOIN login_sessions ON login_sessions.id = refresh_tokens.session_id
+				WHERE refresh_tokens.hash = ? AND JULIANDAY(login_sessions.expires_at) > JULIANDAY('now')
+			"#,
+        hash
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let Some(token) = token else {
+        return Ok(RefreshOutcome::Invalid);
+    };
+
+    let outcome = match token.used_at {
+        Some(used_at) if now - used_at < grace => RefreshOutcome::Raced {
+            session_id: token.session_id,
+            user_id: token.user_id,
+        },
+        Some(_) => {
+            sqlx::query!(
+                r#"
+				DELETE FROM login_sessions
+				WHERE id = ?
+			"#,
+                token.session_id
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            RefreshOutcome::Reused {
+                user_id: token.user_id,
+            }
+        }
+        None => {
+            sqlx::query!(
+                r#"
+				UPDATE refresh_tokens
+				SET used_at = ?
+				WHERE hash = ?
+			"#,
+                now,
+                hash
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            sqlx::query!(
+                r#"
+				INSERT INTO refresh_tokens (hash, session_id, created_at)
+				VALUES (?, ?, ?)
+			"#,
+                new_hash,
+                token.session_id,
+                now
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            RefreshOutcome::Rotated {
+                session_id: token.session_id,
+                user_id: token.user_id,
+            }
+        }
+    };
+
+    tx.commit().await?;
+
+    Ok(outcome)
+}
+
+/// Deletes the session the refresh token, current or already exchanged, belongs to.
+pub async fn delete_login_session_by_refresh_token(
+    db: &SqlitePool,
+    hash: &str,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+				DELETE FROM login_sessions
+				WHERE id IN (SELECT session_id FROM refresh_tokens WHERE hash = ?)
+			"#,
+        hash
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn read_user_totp(db: &SqlitePool, user_id: i64) -> sqlx::Result<Option<UserTotpEntity>> {
+    sqlx::query_as!(
+        UserTotpEntity,
+        r#"
+				SELECT secret, last_step
+				FROM user_totp
+				WHERE user_id = ?
+			"#,
+        user_id
+    )
+    .fetch_optional(db)
+    .await
+}
+
+/// Marks the time step as used, returns false when it or a later one already was.
+pub async fn use_totp_step(db: &SqlitePool, user_id: i64, step: i64) -> sqlx::Result<bool> {
+    let result = sqlx::query!(
+        r#"
+				UPDATE user_totp
+				SET last_step = ?
+				WHERE user_id = ? AND last_step < ?
+			"#,
+        step,
+        user_id,
+        step
+    )
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Marks the recovery code as used, returns false when it doesn't exist or was used before.
+pub async fn use_recovery_code(
+    db: &SqlitePool,
+    user_id: i64,
+    code_hash: &str,
+    used_at: DateTime<FixedOffset>,
+) -> sqlx::Result<bool> {
+    let result = sqlx::query!(
+        r#"
+				UPDATE recovery_codes
+				SET used_at = ?
+				WHERE user_id = ? AND code_hash = ? AND used_at IS NULL
+			"#,
+        used_at,
+        user_id,
+        code_hash
+    )
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Number of recovery codes the user can still use.
+pub async fn count_recovery_codes(db: &SqlitePool, user_id: i64) -> sqlx::Result<i64> {
+    sqlx::query_scalar!(
+        r#"
+				SELECT COUNT(*) FROM recovery_codes
+				WHERE user_id = ? AND used_at IS NULL
+			"#,
+        user_id
+    )
+    .fetch_one(db)
+    .await
+}
+
+/// Turns on two-factor authentication with the secret, confirmed by a code of the time step.
+pub async fn enable_two_factor(
+    db: &SqlitePool,
+    user_id: i64,
+    secret: &str,
+    step: i64,
+    created_at: DateTime<FixedOffset>,
+    recovery_code_hashes: &[String],
+) -> sqlx::Result<()> {
+    let mut tx = db.begin().await?;
+
+    sqlx::query!(
+        r#"
+		INSERT INTO user_totp (user_id, secret, created_at, last_step)
+		VALUES (?, ?, ?, ?)
+		ON CONFLICT (user_id) DO UPDATE
+		SET secret = excluded.secret, created_at = excluded.created_at, last_step = excluded.last_step
+	"#,
+        user_id,
+        secret,
+        created_at,
+        step
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    insert_recovery_codes(&mut tx, user_id, recovery_code_hashes).await?;
+
+    tx.commit().await
+}
+
+/// Replaces all recovery codes of the user.
+pub async fn replace_recovery_codes(
+    db: &SqlitePool,
+    user_id: i64,
+    recovery_code_hashes: &[String],
+) -> sqlx::Result<()> {
+    let mut tx = db.begin().await?;
+
+    insert_recovery_codes(&mut tx, user_id, recovery_code_hashes).await?;
+
+    tx.commit().await
+}
+
+async fn insert_recovery_codes(
+    db: &mut SqliteConnection,
+    user_id: i64,
+    recovery_code_hashes: &[String],
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+		DELETE FROM recovery_codes
+		WHERE user_id = ?
+	"#,
+        user_id
+    )
+    .execute(&mut *db)
+    .await?;
+
+    for hash in recovery_code_hashes {
+        sqlx::query!(
+            r#"
+			INSERT INTO recovery_codes (user_id, code_hash)
+			VALUES (?, ?)
+		"#,
+            user_id,
+            hash
+        )
+        .execute(&mut *db)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Turns off two-factor authentication, returns false when it wasn't on.
+pub async fn disable_two_factor(db: &SqlitePool, user_id: i64) -> sqlx::Result<bool> {
+    let mut tx = db.begin().await?;
+
+    let result = sqlx::query!(
+        r#"
+		DELETE FROM user_totp
+		WHERE user_id = ?
+	"#,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    insert_recovery_codes(&mut tx, user_id, &[]).await?;
+
+    tx.commit().await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+const REQUIRE_ADMIN_TWO_FACTOR_KEY: &str = "require_admin_two_factor";
+
+pub async fn read_settings(db: &SqlitePool) -> sqlx::Result<Settings> {
+    let rows = sqlx::query!(
+        r#"
+				SELECT key, value
+				FROM settings
+			"#
+    )
+    .fetch_all(db)
+    .await?;
+
+    // Unknown keys are left over from removed settings
+    let mut settings = Settings::default();
+    for row in rows {
+        if row.key == REQUIRE_ADMIN_TWO_FACTOR_KEY {
+            settings.require_admin_two_factor = row.value == "true";
+        }
+    }
+
+    Ok(settings)
+}
+
+pub async fn save_settings(db: &SqlitePool, settings: &Settings) -> sqlx::Result<()> {
+    let values = [(
+        REQUIRE_ADMIN_TWO_FACTOR_KEY,
+        settings.require_admin_two_factor.to_string(),
+    )];
+
+    let mut tx = db.begin().await?;
+
+    for (key, value) in values {
+        sqlx::query!(
+            r#"
+			INSERT INTO settings (key, value)
+			VALUES (?, ?)
+			ON CONFLICT (key) DO UPDATE SET value = excluded.value
+		"#,
+            key,
+            value
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await
+}
+
+/// Counts a failed login, starting over when the previous one was before the window. Returns the
+/// failures counted so far, with the lock the key had before.
+pub async fn record_login_failure(
+    db: impl SqliteExecutor<'_>,
+    key: &str,
+    failed_at: DateTime<FixedOffset>,
+    window_start: DateTime<FixedOffset>,
+) -> sqlx::Result<LoginFailureEntity> {
+    sqlx::query_as!(
+        LoginFailureEntity,
+        r#"
+		INSERT INTO login_failures (key, failures, last_failed_at)
+		VALUES (?, 1, ?)
+		ON CONFLICT (key) DO UPDATE SET
+			failures = CASE
+				WHEN JULIANDAY(last_failed_at) < JULIANDAY(?) THEN 1
+				ELSE failures + 1
+			END,
+			last_failed_at = excluded.last_failed_at
+		RETURNING failures, locked_until as "locked_until: DateTime<FixedOffset>"
+	"#,
+        key,
+        failed_at,
+        window_start
+    )
+    .fetch_one(db)
+    .await
+}
+
+pub async fn lock_login(
+    db: impl SqliteExecutor<'_>,
+    key: &str,
+    locked_until: DateTime<FixedOffset>,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+		UPDATE login_failures
+		SET locked_until = ?
+		WHERE key = ?
+	"#,
+        locked_until,
+        key
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Takes back a failure counted for an attempt that turned out right, along with the lock it
+/// caused unless a later failure replaced it.
+pub async fn refund_login_failure(
+    db: &SqlitePool,
+    key: &str,
+    previous_lock: Option<DateTime<FixedOffset>>,
+    lock: Option<DateTime<FixedOffset>>,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+		UPDATE login_failures
+		SET failures = MAX(failures - 1, 0),
+			locked_until = CASE WHEN locked_until IS ?2 THEN ?3 ELSE locked_until END
+		WHERE key = ?1
+	"#,
+        key,
+        lock,
+        previous_lock
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn clear_login_failures(db: &SqlitePool, key: &str) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+		DELETE FROM login_failures
+		WHERE key = ?
+	"#,
+        key
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Stores a reset link for the user, replacing the ones handed out before.
+pub async fn create_password_reset(
+    db: &SqlitePool,
+    user_id: i64,
+    token_hash: &str,
+    expires_at: DateTime<FixedOffset>,
+) -> sqlx::Result<()> {
+    let mut tx = db.begin().await?;
+
+    sqlx::query!(
+        r#"
+		DELETE FROM password_resets
+		WHERE user_id = ?
+	"#,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+		INSERT INTO password_resets (token_hash, user_id, expires_at)
+		VALUES (?, ?, ?)
+	"#,
+        token_hash,
+        user_id,
+        expires_at
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await
+}
+
+pub async fn read_password_reset(
+    db: &SqlitePool,
+    token_hash: &str,
+) -> sqlx::Result<Option<PasswordResetEntity>> {
+    sqlx::query_as!(
+        PasswordResetEntity,
+        r#"
+				SELECT user_id, expires_at as "expires_at: DateTime<FixedOffset>"
+				FROM password_resets
+				WHERE token_hash = ?
+			"#,
+        token_hash
+    )
+    .fetch_optional(db)
+    .await
+}
+
+/// Removes the reset link so it works only once, returns it when it was still there.
+pub async fn take_password_reset(
+    db: &SqlitePool,
+    token_hash: &str,
+) -> sqlx::Result<Option<PasswordResetEntity>> {
+    sqlx::query_as!(
+        PasswordResetEntity,
+        r#"
+				DELETE FROM password_resets
+				WHERE token_hash = ?
+				RETURNING user_id, expires_at as "expires_at: DateTime<FixedOffset>"
+			"#,
+        token_hash
+    )
+    .fetch_optional(db)
+    .await
+}
+
+/// Whether a user other than `except` has the name, in any case.
+pub async fn user_name_exists(
+    db: &SqlitePool,
+    name: &str,
+    except: Option<i64>,
+) -> sqlx::Result<bool> {
+    sqlx::query_scalar!(
+        r#"
+				SELECT EXISTS(
+					SELECT 1 FROM users WHERE name = ?1 COLLATE NOCASE AND (?2 IS NULL OR id <> ?2) AND deleted_at IS NULL
+				) as "exists!: bool"
+			"#,
+        name,
+        except
+    )
+    .fetch_one(db)
+    .await
+}
+
+pub async fn create_invite(
+    db: &SqlitePool,
+    invite: &NewInvite<'_>,
+    timezone: Tz,
+) -> sqlx::Result<Invite> {
+    let roles = invite
+        .roles
+        .iter()
+        .map(|role| role.as_str())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let id = sqlx::query!(
+        r#"
+		INSERT INTO invites (name, roles, token_hash, created_by, created_at, expires_at)
+		VALUES (?, ?, ?, ?, ?, ?)
+	"#,
+        invite.name,
+        roles,
+        invite.token_hash,
+        invite.created_by,
+        invite.created_at,
+        invite.expires_at
+    )
+    .execute(db)
+    .await?
+    .last_insert_rowid();
+
+    read_invite(db, id, timezone)
+        .await?
+        .ok_or(sqlx::Error::RowNotFound)
+}
+
+pub async fn read_invite(db: &SqlitePool, id: i64, timezone: Tz) -> sqlx::Result<Option<Invite>> {
+    let invite = sqlx::query_as!(
+        InviteEntity,
+        r#"
+				SELECT invites.id, invites.name, invites.roles, users.name as invited_by,
+					invites.created_at as "created_at: DateTime<FixedOffset>",
+					invites.expires_at as "expires_at: DateTime<FixedOffset>"
+				FROM invites
+				LEFT JOIN users ON users.id = invites.created_by
+				WHERE invites.id = ?
+			"#,
+        id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(invite.map(|x| Invite::new(x, timezone)))
+}
+
+pub async fn read_invite_by_hash(
+    db: &SqlitePool,
+    token_hash: &str,
+    timezone: Tz,
+) -> sqlx::Result<Option<Invite>> {
+    let invite = sqlx::query_as!(
+        InviteEntity,
+        r#"
+				SELECT invites.id, invites.name, invites.roles, users.name as invited_by,
+					invites.created_at as "created_at: DateTime<FixedOffset>",
+					invites.expires_at as "expires_at: DateTime<FixedOffset>"
+				FROM invites
+				LEFT JOIN users ON users.id = invites.created_by
+				WHERE invites.token_hash = ?
+			"#,
+        token_hash
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(invite.map(|x| Invite::new(x, timezone)))
+}
+
+/// Reads the invites nobody accepted yet, newest first.
+pub async fn read_invites(db: &SqlitePool, timezone: Tz) -> sqlx::Result<Vec<Invite>> {
+    let invites = sqlx::query_as!(
+        InviteEntity,
+        r#"
+				SELECT invites.id, invites.name, invites.roles, users.name as invited_by,
+					invites.created_at as "created_at: DateTime<FixedOffset>",
+					invites.expires_at as "expires_at: DateTime<FixedOffset>"
+				FROM invites
+				LEFT JOIN users ON users.id = invites.created_by
+				ORDER BY invites.id DESC
+			"#
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(invites
+        .into_iter()
+        .map(|x| Invite::new(x, timezone))
+        .collect())
+}
+
+pub async fn delete_invite(db: &SqlitePool, id: i64) -> sqlx::Result<bool> {
+    let result = sqlx::query!(
+        r#"
+				DELETE FROM invites
+				WHERE id = ?
+			"#,
+        id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Creates the user of the invite with the password the invitee picked, the invite is used up.
+///
+/// Returns nothing when the invite doesn't exist or has expired.
+pub async fn accept_invite(
+    db: &SqlitePool,
+    token_hash: &str,
+    password: &str,
+) -> sqlx::Result<Option<User>, CreateUserError> {
+    let hashed_password = hash_password(password);
+
+    let mut tx = db.begin().await?;
+
+    // Removed in the same transaction, an invite can't be accepted twice
+    let invite = sqlx::query!(
+        r#"
+		DELETE FROM invites
+		WHERE token_hash = ?
+		RETURNING name, roles, expires_at as "expires_at: DateTime<FixedOffset>"
+	"#,
+        token_hash
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let Some(invite) = invite.filter(|invite| invite.expires_at > Utc::now()) else {
+        return Ok(None);
+    };
+
+    let user_id = sqlx::query!(
+        r#"
+		INSERT INTO users (name, password)
+		VALUES (?, ?)
+	"#,
+        invite.name,
+        hashed_password,
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e: sqlx::Error| {
+        if let Some(db_error) = e.as_database_error() {
+            if db_error.kind() == ErrorKind::UniqueViolation {
+                return CreateUserError::DuplicateName(invite.name.clone());
+            }
+        }
+        CreateUserError::DbError(e)
+    })?
+    .last_insert_rowid();
+
+    insert_user_roles(&mut tx, user_id, &parse_roles(&invite.roles)).await?;
 
-    let user = read_user(db, user.last_insert_rowid()).await;
+    tx.commit().await?;
 
-    match user {
-        Ok(Some(user)) => Ok(user),
+    match read_user(db, user_id).await {
+        Ok(Some(user)) => Ok(Some(user)),
         _ => Err(CreateUserError::CantRead),
     }
 }
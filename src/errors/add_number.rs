@@ -1,13 +1,27 @@
 use thiserror::Error;
 
+use super::code_format::CodeFormatError;
+
 #[derive(Debug, Error)]
 pub enum AddNumberError {
-    #[error("Failed to parse suffix from the code: {0}")]
-    ParseSuffixError(String),
-
     #[error("Code '{0}' already exists")]
     DuplicateCode(String),
 
+    #[error("Couldn't allocate a number, the database stayed busy after {0} attempts")]
+    AllocationConflict(u32),
+
+    #[error("Batch size must be between 1 and {0}")]
+    InvalidBatchSize(u32),
+
+    #[error("Series with ID: '{0}' not found")]
+    SeriesNotFound(i64),
+
+    #[error("Series '{0}' is not active")]
+    SeriesInactive(String),
+
+    #[error("Series has an invalid format: {0}")]
+    InvalidFormat(#[from] CodeFormatError),
+
     #[error("Error communicating with database: '{0}'")]
     DbError(#[from] sqlx::Error),
 }
@@ -0,0 +1,40 @@
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum CodeFormatError {
+    #[error("Placeholder starting at position {0} is not terminated")]
+    UnterminatedPlaceholder(usize),
+
+    #[error("Unmatched '}}' at position {0}, use '}}}}' for a literal brace")]
+    UnmatchedBrace(usize),
+
+    #[error("Unknown placeholder '{{{0}}}'")]
+    UnknownPlaceholder(String),
+
+    #[error("Invalid date format '{0}'")]
+    InvalidDateFormat(String),
+
+    #[error("Invalid sequence width '{0}', expected e.g. '{{seq:03}}'")]
+    InvalidSeqWidth(String),
+
+    #[error("Invalid fiscal year start '{0}', expected a month between 1 and 12")]
+    InvalidFiscalStart(String),
+
+    #[error("Unknown reset period '{0}', expected e.g. 'daily' or 'fiscal:4'")]
+    InvalidResetPeriod(String),
+
+    #[error("Unknown check digit algorithm '{0}', expected 'luhn', 'mod97-10' or 'damm'")]
+    UnknownCheckDigit(String),
+
+    #[error("Unknown timezone '{0}', expected an IANA name like 'Europe/Prague'")]
+    UnknownTimezone(String),
+
+    #[error("Format must contain a '{{seq}}' placeholder")]
+    MissingSeq,
+
+    #[error("Format can contain only one '{{seq}}' placeholder")]
+    DuplicateSeq,
+
+    #[error("Format renders the same code in different {0} periods, add a date like '{{date}}' or reset less often")]
+    PeriodsNotDistinct(String),
+}
@@ -0,0 +1,25 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum CreateApiTokenError {
+    #[error("Token name cannot be empty")]
+    EmptyName,
+
+    #[error("Pick at least one scope")]
+    NoScopes,
+
+    #[error("Unknown scope '{0}'")]
+    UnknownScope(String),
+
+    #[error("You don't have the '{0}' permission yourself")]
+    ScopeNotGranted(String),
+
+    #[error("Invalid expiry '{0}', use a number of days")]
+    InvalidExpiry(String),
+
+    #[error("Tokens can't be created with another token")]
+    CreatedWithToken,
+
+    #[error("Error communicating with database: '{0}'")]
+    DbError(#[from] sqlx::Error),
+}
@@ -0,0 +1,21 @@
+use thiserror::Error;
+
+use super::code_format::CodeFormatError;
+
+#[derive(Debug, Error)]
+pub enum CreateSeriesError {
+    #[error("Series name cannot be empty")]
+    EmptyName,
+
+    #[error("Series '{0}' already exists")]
+    DuplicateName(String),
+
+    #[error("Invalid format: {0}")]
+    InvalidFormat(#[from] CodeFormatError),
+
+    #[error("Created series can't be found")]
+    CantRead,
+
+    #[error("Error communicating with database: '{0}'")]
+    DbError(#[from] sqlx::Error),
+}
@@ -2,6 +2,9 @@ use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum CreateUserError {
+    #[error("The username '{0}' is already taken")]
+    DuplicateName(String),
+
     #[error("Created user can't be found")]
     CantRead,
 
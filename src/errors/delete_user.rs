@@ -2,9 +2,12 @@ use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum DeleteUserError {
-    #[error("Can't delete the last admin user")]
+    #[error("Can't delete the last super admin")]
     CantDeleteLastAdmin,
 
+    #[error("Codes can only be handed to another existing user")]
+    InvalidReassignment,
+
     #[error("Error communicating with database: '{0}'")]
     DbError(#[from] sqlx::Error),
 }
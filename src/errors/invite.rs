@@ -0,0 +1,27 @@
+use thiserror::Error;
+
+use super::{create_user::CreateUserError, password_change::ChangePasswordError};
+
+#[derive(Debug, Error)]
+pub enum InviteError {
+    #[error("Username cannot be empty")]
+    EmptyName,
+
+    #[error("The username '{0}' is already taken")]
+    NameTaken(String),
+
+    #[error("'{0}' already has a pending invite, revoke it first")]
+    AlreadyInvited(String),
+
+    #[error("This invite is invalid or has expired, ask an admin for a new one")]
+    InvalidLink,
+
+    #[error("{0}")]
+    NewPassword(#[from] ChangePasswordError),
+
+    #[error("Failed to create the user: {0}")]
+    CreateUser(#[from] CreateUserError),
+
+    #[error("Error communicating with database: '{0}'")]
+    DbError(#[from] sqlx::Error),
+}
@@ -1,16 +1,28 @@
 use std::env::VarError;
 
+use code_format::CodeFormatError;
 use thiserror::Error;
 
 pub mod add_number;
 pub mod check_user_password;
+pub mod code_format;
+pub mod create_api_token;
+pub mod create_series;
 pub mod create_user;
 pub mod delete_user;
+pub mod invite;
 pub mod login_post_error;
+pub mod oidc_login;
 pub mod password_change;
+pub mod password_policy;
+pub mod password_reset;
 pub mod read_user;
 pub mod read_users;
 pub mod reset_codes;
+pub mod setup;
+pub mod two_factor;
+pub mod update_settings;
+pub mod update_user;
 
 #[derive(Error, Debug)]
 pub enum ApplicationError {
@@ -25,4 +37,18 @@ pub enum ApplicationError {
 
     #[error("Missing environment value: {1}")]
     EnvError(#[source] VarError, String),
+
+    #[error("Invalid value '{0}' in {1}")]
+    InvalidConfig(String, String),
+
+    #[error("Cannot read {1}. Error: {0}")]
+    CannotReadFile(#[source] std::io::Error, String),
+
+    #[error(
+        "Usernames have to be unique ignoring case, rename or delete users sharing a name: {0}"
+    )]
+    DuplicateUserNames(String),
+
+    #[error("Invalid code format in {1}. Error: {0}")]
+    InvalidCodeFormat(#[source] CodeFormatError, String),
 }
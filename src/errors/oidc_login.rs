@@ -0,0 +1,49 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum OidcLoginError {
+    #[error("Single sign-on is not configured")]
+    NotConfigured,
+
+    #[error("The login expired or was started elsewhere, try again")]
+    StateMismatch,
+
+    #[error("The identity provider refused the login: {0}")]
+    Refused(String),
+
+    #[error("Can't reach the identity provider: {0}")]
+    Request(#[from] reqwest::Error),
+
+    #[error("The identity provider claims to be '{0}'")]
+    IssuerMismatch(String),
+
+    #[error("Invalid identity provider address: {0}")]
+    InvalidUrl(#[from] url::ParseError),
+
+    #[error("The identity provider didn't accept the login: {0}")]
+    TokenExchange(String),
+
+    #[error("The ID token is signed with an unknown key")]
+    UnknownKey,
+
+    #[error("Invalid ID token: {0}")]
+    InvalidIdToken(#[from] jsonwebtoken::errors::Error),
+
+    #[error("The ID token belongs to another login")]
+    NonceMismatch,
+
+    #[error("Your account isn't allowed to use this application")]
+    NoRoles,
+
+    #[error("Your account has been deactivated")]
+    Deactivated,
+
+    #[error("A local account named '{0}' already exists")]
+    NameTaken(String),
+
+    #[error("Failed to keep the login in the session: {0}")]
+    Session(#[from] tower_sessions::session::Error),
+
+    #[error("Error communicating with database: '{0}'")]
+    DbError(#[from] sqlx::Error),
+}
@@ -1,7 +1,18 @@
 use thiserror::Error;
 
+use super::password_policy::PasswordPolicyError;
+
 #[derive(Debug, Error)]
 pub enum ChangePasswordError {
+    #[error("New password and retype password do not match")]
+    PasswordMismatch,
+
+    #[error("{0}")]
+    Policy(#[from] PasswordPolicyError),
+
+    #[error("Passwords can't be changed with a token")]
+    ChangedWithToken,
+
     #[error("Error communicating with database: '{0}'")]
     DbError(#[from] sqlx::Error),
 }
@@ -0,0 +1,13 @@
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum PasswordPolicyError {
+    #[error("Password must have at least {0} characters")]
+    TooShort(usize),
+
+    #[error("Password is too easy to guess: {0}")]
+    TooWeak(String),
+
+    #[error("Password appears in a list of breached passwords, choose another one")]
+    Breached,
+}
@@ -0,0 +1,18 @@
+use thiserror::Error;
+
+use super::{password_change::ChangePasswordError, read_users::ReadUsersError};
+
+#[derive(Debug, Error)]
+pub enum PasswordResetError {
+    #[error("This reset link is invalid or has expired, ask an admin for a new one")]
+    InvalidLink,
+
+    #[error("{0}")]
+    NewPassword(#[from] ChangePasswordError),
+
+    #[error("{0}")]
+    ReadUser(#[from] ReadUsersError),
+
+    #[error("Error communicating with database: '{0}'")]
+    DbError(#[from] sqlx::Error),
+}
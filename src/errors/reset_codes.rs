@@ -2,6 +2,12 @@ use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum ResetCodesError {
+    #[error("There is no reset to undo")]
+    NothingToUndo,
+
+    #[error("Can't undo the reset, some of the archived numbers were reserved again")]
+    UndoConflict,
+
     #[error("Error communicating with database: '{0}'")]
     DbError(#[from] sqlx::Error),
 }
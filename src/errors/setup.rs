@@ -0,0 +1,27 @@
+use thiserror::Error;
+
+use super::{create_user::CreateUserError, password_policy::PasswordPolicyError};
+
+#[derive(Debug, Error)]
+pub enum SetupError {
+    #[error("Invalid setup token, use the address from the log")]
+    InvalidToken,
+
+    #[error("The application is already set up")]
+    AlreadyDone,
+
+    #[error("Username or password cannot be empty")]
+    EmptyFields,
+
+    #[error("Password and retype password do not match")]
+    PasswordMismatch,
+
+    #[error("{0}")]
+    WeakPassword(#[from] PasswordPolicyError),
+
+    #[error("Failed to create the admin: {0}")]
+    CreateUser(#[from] CreateUserError),
+
+    #[error("Error communicating with database: '{0}'")]
+    DbError(#[from] sqlx::Error),
+}
@@ -0,0 +1,28 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum TwoFactorError {
+    #[error("Invalid code")]
+    InvalidCode,
+
+    #[error("The setup expired, start it again")]
+    SetupExpired,
+
+    #[error("Two-factor authentication is already set up")]
+    AlreadyEnabled,
+
+    #[error("Two-factor authentication isn't set up")]
+    NotEnabled,
+
+    #[error("Admins have to keep two-factor authentication")]
+    Required,
+
+    #[error("Two-factor authentication can't be changed with a token")]
+    ChangedWithToken,
+
+    #[error("Failed to keep the setup in the session: {0}")]
+    Session(#[from] tower_sessions::session::Error),
+
+    #[error("Error communicating with database: '{0}'")]
+    DbError(#[from] sqlx::Error),
+}
@@ -0,0 +1,15 @@
+use thiserror::Error;
+
+use super::read_users::ReadUsersError;
+
+#[derive(Debug, Error)]
+pub enum UpdateSettingsError {
+    #[error("Set up two-factor authentication for your own account first")]
+    OwnTwoFactorMissing,
+
+    #[error("{0}")]
+    ReadUsers(#[from] ReadUsersError),
+
+    #[error("Error communicating with database: '{0}'")]
+    DbError(#[from] sqlx::Error),
+}
@@ -0,0 +1,28 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum UpdateUserError {
+    #[error("User not found")]
+    NotFound,
+
+    #[error("Username cannot be empty")]
+    EmptyName,
+
+    #[error("The username '{0}' is already taken")]
+    NameTaken(String),
+
+    #[error("Only super admins can change super admins")]
+    SuperAdminOnly,
+
+    #[error("Can't take the role from the last super admin")]
+    CantDemoteLastAdmin,
+
+    #[error("Can't deactivate the last super admin")]
+    CantDeactivateLastAdmin,
+
+    #[error("You can't deactivate your own account")]
+    CantDeactivateSelf,
+
+    #[error("Error communicating with database: '{0}'")]
+    DbError(#[from] sqlx::Error),
+}
@@ -1,5 +1,8 @@
+use chrono::NaiveDate;
 use serde::Deserialize;
 
+use crate::permissions::Role;
+
 /// Struct for holding data from the user login form.
 #[derive(Debug, Deserialize)]
 pub struct LoginUserSchema {
@@ -15,11 +18,152 @@ pub struct ChangePasswordSchema {
     pub retype_password: String,
 }
 
-/// Struct for holding data from the change password form.
+/// New password set through the reset link of an admin.
+#[derive(Debug, Deserialize)]
+pub struct ResetPasswordSchema {
+    pub token: String,
+    pub new_password: String,
+    pub retype_password: String,
+}
+
+/// Name and roles of the user to invite.
+#[derive(Debug, Deserialize)]
+pub struct CreateInviteSchema {
+    pub name: String,
+    #[serde(default, rename = "role")]
+    pub roles: Vec<Role>,
+}
+
+/// New name and roles of an existing user.
 #[derive(Debug, Deserialize)]
-pub struct CreateUserSchema {
+pub struct UpdateUserSchema {
     pub name: String,
+    #[serde(default, rename = "role")]
+    pub roles: Vec<Role>,
+}
+
+/// Struct for holding who takes over the codes of a deleted user.
+#[derive(Debug, Deserialize)]
+pub struct DeleteUserSchema {
+    /// Id of the user, the codes stay with the deleted user when empty.
+    #[serde(default)]
+    pub reassign_to: String,
+}
+
+/// Password picked by the invitee.
+#[derive(Debug, Deserialize)]
+pub struct AcceptInviteSchema {
+    pub token: String,
     pub password: String,
+    pub retype_password: String,
+}
+
+/// Struct for holding the series selected on the dashboard.
+#[derive(Debug, Deserialize)]
+pub struct SelectSeriesSchema {
+    pub series_id: Option<i64>,
+}
+
+/// Struct for holding data from the batch reservation form.
+#[derive(Debug, Deserialize)]
+pub struct ReserveBatchSchema {
+    pub series_id: Option<i64>,
+    pub count: u32,
+}
+
+/// Struct for holding the code to validate.
+#[derive(Debug, Deserialize)]
+pub struct ValidateCodeSchema {
+    pub code: String,
+    pub series_id: Option<i64>,
+}
+
+/// Struct for holding the scope of a reset, everything when empty.
+#[derive(Debug, Deserialize)]
+pub struct ResetCodesSchema {
+    pub series_id: Option<i64>,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+/// Struct for holding the filters of the audit log page.
+#[derive(Debug, Deserialize)]
+pub struct AuditLogSchema {
+    #[serde(default)]
+    pub actor: String,
+    #[serde(default)]
+    pub action: String,
     #[serde(default)]
-    pub is_admin: bool,
+    pub from: String,
+    #[serde(default)]
+    pub to: String,
+    pub page: Option<i64>,
+}
+
+/// Struct for holding data from the create API token form.
+#[derive(Debug, Deserialize)]
+pub struct CreateApiTokenSchema {
+    pub name: String,
+    /// Days until the token expires, never when empty.
+    #[serde(default)]
+    pub expires_in_days: String,
+    #[serde(default, rename = "scope")]
+    pub scopes: Vec<String>,
+}
+
+/// Struct for holding data from the create series form.
+#[derive(Debug, Deserialize)]
+pub struct CreateSeriesSchema {
+    pub name: String,
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default)]
+    pub format: String,
+    #[serde(default)]
+    pub reset_period: String,
+    #[serde(default)]
+    pub check_digit: String,
+    #[serde(default)]
+    pub timezone: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+/// Struct for holding the answer of the identity provider to a login.
+#[derive(Debug, Deserialize)]
+pub struct OidcCallbackSchema {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    /// Set instead of the code when the login failed.
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+/// Struct for holding a code of the authenticator app or a recovery code.
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorCodeSchema {
+    pub code: String,
+}
+
+/// Struct for holding data from the settings form.
+#[derive(Debug, Deserialize)]
+pub struct SettingsSchema {
+    /// Checkbox, only sent when checked.
+    pub require_admin_two_factor: Option<String>,
+}
+
+/// Struct for holding the token of the setup page address.
+#[derive(Debug, Deserialize)]
+pub struct SetupTokenSchema {
+    #[serde(default)]
+    pub token: String,
+}
+
+/// Struct for holding data from the form creating the first admin.
+#[derive(Debug, Deserialize)]
+pub struct SetupSchema {
+    pub token: String,
+    pub username: String,
+    pub password: String,
+    pub retype_password: String,
 }
@@ -1,8 +1,31 @@
+use std::sync::OnceLock;
+
 use argon2::{
-    password_hash::{rand_core::OsRng, SaltString},
+    password_hash::{
+        rand_core::{OsRng, RngCore},
+        SaltString,
+    },
     Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
 };
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tower_sessions::cookie::{time::Duration, Cookie, SameSite};
+
+/// Cookie holding the short-lived access token.
+pub const ACCESS_COOKIE: &str = "token";
+
+/// Cookie holding the refresh token, which gets a new access token once it expires.
+pub const REFRESH_COOKIE: &str = "refresh_token";
+
+/// Minutes an access token is accepted, a leaked one is useless soon after.
+pub const ACCESS_TOKEN_MINUTES: i64 = 15;
+
+/// Days a login lasts before the user has to log in again.
+pub const LOGIN_SESSION_DAYS: i64 = 7;
+
+/// Seconds a replaced refresh token is still accepted, for requests racing the refresh.
+pub const REFRESH_REUSE_GRACE_SECONDS: i64 = 30;
 
 /// Struct for holding data from the JWT.
 #[derive(Debug, Deserialize, Serialize)]
@@ -10,6 +33,91 @@ pub struct TokenClaims {
     pub sub: String,
     pub iat: usize,
     pub exp: usize,
+    /// Id of the login session, the token is revoked together with it.
+    pub jti: String,
+}
+
+/// Creates a random id for a login session.
+pub fn generate_session_id() -> String {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+
+    hex::encode(bytes)
+}
+
+/// Creates a random refresh token.
+pub fn generate_refresh_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+
+    hex::encode(bytes)
+}
+
+/// Hashes the refresh token for storage, it's random enough not to need a slow hash.
+pub fn hash_refresh_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Signs a short-lived access token for the login session.
+pub fn encode_access_token(secret: &str, user_id: i64, session_id: &str) -> String {
+    let now = chrono::Utc::now();
+    let claims = TokenClaims {
+        sub: user_id.to_string(),
+        iat: now.timestamp() as usize,
+        exp: (now + chrono::Duration::minutes(ACCESS_TOKEN_MINUTES)).timestamp() as usize,
+        jti: session_id.to_string(),
+    };
+
+    encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(secret.as_ref()),
+    )
+    .unwrap()
+}
+
+/// Checks the signature and, unless `allow_expired`, the expiry of the access token.
+pub fn decode_access_token(secret: &str, token: &str, allow_expired: bool) -> Option<TokenClaims> {
+    let mut validation = Validation::default();
+    validation.validate_exp = !allow_expired;
+
+    decode::<TokenClaims>(
+        token,
+        &DecodingKey::from_secret(secret.as_ref()),
+        &validation,
+    )
+    .ok()
+    .map(|data| data.claims)
+}
+
+pub fn access_cookie(token: String) -> Cookie<'static> {
+    Cookie::build((ACCESS_COOKIE, token))
+        .path("/")
+        .max_age(Duration::minutes(ACCESS_TOKEN_MINUTES))
+        .same_site(SameSite::Lax)
+        .http_only(true)
+        .build()
+}
+
+pub fn refresh_cookie(token: String) -> Cookie<'static> {
+    Cookie::build((REFRESH_COOKIE, token))
+        .path("/")
+        .max_age(Duration::days(LOGIN_SESSION_DAYS))
+        .same_site(SameSite::Lax)
+        .http_only(true)
+        .build()
+}
+
+/// Cookies replacing both tokens with already expired ones.
+pub fn removed_cookies() -> [Cookie<'static>; 2] {
+    [ACCESS_COOKIE, REFRESH_COOKIE].map(|name| {
+        Cookie::build((name, ""))
+            .path("/")
+            .max_age(Duration::hours(-1))
+            .same_site(SameSite::Lax)
+            .http_only(true)
+            .build()
+    })
 }
 
 pub fn hash_password(password: &str) -> String {
@@ -31,12 +139,24 @@ pub fn verify_password(password: &str, hashed_password: &str) -> bool {
         Ok(parsed_hash) => Argon2::default()
             .verify_password(password.as_bytes(), &parsed_hash)
             .map_or(false, |_| true),
-        Err(_err) => false,
+        // Accounts without a password, like single sign-on ones, take as long to refuse
+        Err(_err) => {
+            verify_password(password, dummy_password_hash());
+            false
+        }
     };
 
     is_valid
 }
 
+/// Hash no password matches, checked against when there's nothing to compare with so refusing
+/// takes as long as for a real password.
+pub fn dummy_password_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+    DUMMY_HASH.get_or_init(|| hash_password(&generate_refresh_token()))
+}
+
 #[cfg(test)]
 mod test {
 
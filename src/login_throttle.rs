@@ -0,0 +1,198 @@
+use chrono::{DateTime, Duration, FixedOffset};
+use sqlx::{SqliteConnection, SqlitePool};
+
+use crate::db::{clear_login_failures, lock_login, record_login_failure, refund_login_failure};
+
+/// When failed logins of a key start to slow down further attempts, and when they lock it out.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// Failures allowed without waiting.
+    pub free_attempts: i64,
+    /// Failures after which the key is locked for the whole lockout.
+    pub lockout_after: i64,
+}
+
+/// Limits for a single username, guessing the password of one account.
+pub const USERNAME_LIMITS: Limits = Limits {
+    free_attempts: 3,
+    lockout_after: 10,
+};
+
+/// Limits for a client address, which may be shared by a whole office.
+pub const IP_LIMITS: Limits = Limits {
+    free_attempts: 10,
+    lockout_after: 50,
+};
+
+/// How long a key stays locked out, also the longest wait between attempts.
+const LOCKOUT_MINUTES: i64 = 15;
+
+/// Failures older than this are forgotten at the next one.
+const FAILURE_WINDOW_HOURS: i64 = 24;
+
+/// Usernames are compared without case like SQLite's `NOCASE`, so any spelling shares one count.
+pub fn username_key(username: &str) -> String {
+    format!("user:{}", username.to_ascii_lowercase())
+}
+
+pub fn ip_key(ip: &str) -> String {
+    format!("ip:{}", ip)
+}
+
+/// Keys a login attempt is counted for, unknown usernames count like real ones.
+pub fn login_keys(username: &str, ip: Option<&str>) -> Vec<(String, Limits)> {
+    let mut keys = vec![(username_key(username), USERNAME_LIMITS)];
+    if let Some(ip) = ip {
+        keys.push((ip_key(ip), IP_LIMITS));
+    }
+    keys
+}
+
+/// Time to wait before trying again after the number of failures, doubling with each one.
+pub fn backoff(limits: Limits, failures: i64) -> Option<Duration> {
+    let lockout = Duration::minutes(LOCKOUT_MINUTES);
+
+    if failures < limits.free_attempts {
+        return None;
+    }
+    if failures >= limits.lockout_after {
+        return Some(lockout);
+    }
+
+    let exponent = (failures - limits.free_attempts).min(30) as u32;
+    Some(Duration::seconds(2i64.pow(exponent)).min(lockout))
+}
+
+/// Failure counted for a key, with the lock the key had before and the one it got.
+#[derive(Debug)]
+pub struct CountedFailure {
+    key: String,
+    previous_lock: Option<DateTime<FixedOffset>>,
+    lock: Option<DateTime<FixedOffset>>,
+}
+
+/// Login attempt counted as failed until it turns out right.
+#[derive(Debug)]
+pub struct Attempt(Vec<CountedFailure>);
+
+/// Counts the attempt as failed for the keys before the password or code is checked, so parallel
+/// attempts can't all get past the limits. Returns the time to wait instead while a key is
+/// locked, the attempt isn't counted then.
+pub async fn start_attempt(
+    db: &SqlitePool,
+    keys: &[(String, Limits)],
+    now: DateTime<FixedOffset>,
+) -> sqlx::Result<Result<Attempt, DateTime<FixedOffset>>> {
+    let mut tx = db.begin().await?;
+
+    // Counting first takes the write lock, the next attempt waits and sees the lock set here
+    let counted = record_failure(&mut tx, keys, now).await?;
+
+    let locked_until = counted
+        .iter()
+        .filter_map(|failure| failure.previous_lock)
+        .filter(|locked_until| *locked_until > now)
+        .max();
+    if let Some(locked_until) = locked_until {
+        tx.rollback().await?;
+        return Ok(Err(locked_until));
+    }
+
+    tx.commit().await?;
+
+    Ok(Ok(Attempt(counted)))
+}
+
+/// Takes back the failures counted for an attempt with the right password or code.
+pub async fn refund_attempt(db: &SqlitePool, attempt: Attempt) -> sqlx::Result<()> {
+    for failure in attempt.0 {
+        refund_login_failure(db, &failure.key, failure.previous_lock, failure.lock).await?;
+    }
+
+    Ok(())
+}
+
+/// Counts a failed login for the keys and locks the ones that have to wait.
+pub async fn record_failure(
+    db: &mut SqliteConnection,
+    keys: &[(String, Limits)],
+    now: DateTime<FixedOffset>,
+) -> sqlx::Result<Vec<CountedFailure>> {
+    let window_start = now - Duration::hours(FAILURE_WINDOW_HOURS);
+    let mut counted = Vec::new();
+
+    for (key, limits) in keys {
+        let failure = record_login_failure(&mut *db, key, now, window_start).await?;
+
+        let lock = backoff(*limits, failure.failures).map(|wait| now + wait);
+        if let Some(lock) = lock {
+            lock_login(&mut *db, key, lock).await?;
+        }
+
+        counted.push(CountedFailure {
+            key: key.clone(),
+            previous_lock: failure.locked_until,
+            lock,
+        });
+    }
+
+    Ok(counted)
+}
+
+/// Forgets the failed logins of the user after a successful one.
+///
+/// The address keeps its count, or an attacker could reset it by logging in to their own account.
+pub async fn record_success(db: &SqlitePool, username: &str) -> sqlx::Result<()> {
+    clear_login_failures(db, &username_key(username)).await
+}
+
+/// Wait until the time, like `3 minutes`, for telling the user when to try again.
+pub fn describe_wait(now: DateTime<FixedOffset>, until: DateTime<FixedOffset>) -> String {
+    let seconds = (until - now).num_seconds().max(1);
+    let (amount, unit) = if seconds < 60 {
+        (seconds, "second")
+    } else {
+        ((seconds + 59) / 60, "minute")
+    };
+
+    if amount == 1 {
+        format!("1 {}", unit)
+    } else {
+        format!("{} {}s", amount, unit)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use crate::login_throttle::{backoff, describe_wait, IP_LIMITS, USERNAME_LIMITS};
+
+    #[test]
+    fn backoff_doubles_then_locks() {
+        assert_eq!(backoff(USERNAME_LIMITS, 2), None);
+        assert_eq!(backoff(USERNAME_LIMITS, 3), Some(Duration::seconds(1)));
+        assert_eq!(backoff(USERNAME_LIMITS, 4), Some(Duration::seconds(2)));
+        assert_eq!(backoff(USERNAME_LIMITS, 9), Some(Duration::seconds(64)));
+        assert_eq!(backoff(USERNAME_LIMITS, 10), Some(Duration::minutes(15)));
+        assert_eq!(backoff(USERNAME_LIMITS, 1000), Some(Duration::minutes(15)));
+    }
+
+    #[test]
+    fn addresses_get_more_attempts() {
+        assert_eq!(backoff(IP_LIMITS, 9), None);
+        assert_eq!(backoff(IP_LIMITS, 49), Some(Duration::minutes(15)));
+    }
+
+    #[test]
+    fn wait_descriptions() {
+        let now = Utc
+            .with_ymd_and_hms(2025, 1, 6, 12, 0, 0)
+            .unwrap()
+            .fixed_offset();
+
+        assert_eq!(describe_wait(now, now + Duration::seconds(5)), "5 seconds");
+        assert_eq!(describe_wait(now, now + Duration::seconds(61)), "2 minutes");
+        assert_eq!(describe_wait(now, now), "1 second");
+    }
+}
@@ -1,23 +1,50 @@
-use std::env;
+use std::{
+    env,
+    net::{IpAddr, SocketAddr},
+};
 
-use db::create_db_pool;
+use chrono_tz::Tz;
+use code_format::{parse_timezone, CodeFormat, DEFAULT_CODE_FORMAT};
+use db::{
+    create_db_pool, has_super_admin, read_all_series, read_sequence_checks, save_sequence_checks,
+};
 use dotenvy::dotenv;
 use errors::ApplicationError;
+use jwt::generate_refresh_token;
+use oidc::{parse_role_mapping, OidcConfig, DEFAULT_ROLE_CLAIM};
+use password_policy::{
+    parse_breached_passwords, PasswordPolicy, DEFAULT_MIN_LENGTH, DEFAULT_MIN_STRENGTH,
+    MAX_STRENGTH,
+};
 use router::setup_router;
+use state::AppState;
 use tokio::net::TcpListener;
-use tracing::info;
+use tracing::{info, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use url::Url;
+
+/// Largest block of numbers reserved by one request when `SERIGEN_MAX_BATCH_SIZE` isn't set.
+const DEFAULT_MAX_BATCH_SIZE: u32 = 50;
 
 mod actions;
+mod api_token;
+mod audit;
+mod check_digit;
+mod code_format;
 mod db;
 mod errors;
 mod forms;
 mod jwt;
+mod login_throttle;
 mod middleware;
 mod models;
+mod oidc;
+mod password_policy;
+mod permissions;
 mod router;
 mod state;
 mod templates;
+mod totp;
 mod utils;
 
 #[tokio::main]
@@ -36,9 +63,48 @@ async fn run() -> Result<(), ApplicationError> {
 
     let (host, port, jwt_secret, data_file) = setup_env()?;
 
+    let (code_prefix, code_format) = setup_code_format()?;
+
+    let max_batch_size = setup_max_batch_size()?;
+
+    let timezone = setup_timezone()?;
+
+    let oidc = setup_oidc()?;
+
+    let password_policy = setup_password_policy()?;
+
+    let trusted_proxies = setup_trusted_proxies()?;
+
+    let public_url = setup_public_url()?;
+
     let db = setup_db(data_file).await?;
 
-    let app = setup_router(db, &jwt_secret);
+    validate_series(&db, &code_prefix, &code_format, timezone).await?;
+    check_sequences(&db, &code_prefix, &code_format, timezone).await?;
+
+    let setup_token = setup_first_run(&db, &host, &port).await?;
+
+    let mut state = AppState::new(
+        db,
+        &jwt_secret,
+        &code_prefix,
+        code_format,
+        max_batch_size,
+        timezone,
+    )
+    .with_password_policy(password_policy)
+    .with_trusted_proxies(trusted_proxies);
+    if let Some(oidc) = oidc {
+        state = state.with_oidc(oidc);
+    }
+    if let Some(public_url) = public_url {
+        state = state.with_public_url(&public_url);
+    }
+    if let Some(setup_token) = setup_token {
+        state = state.with_setup_token(&setup_token);
+    }
+
+    let app = setup_router(state);
 
     let address = format!("{}:{}", host, port);
     info!("Starting server on {}", address);
@@ -49,9 +115,12 @@ async fn run() -> Result<(), ApplicationError> {
 
     info!("Listening on: {}", listener.local_addr().unwrap());
 
-    axum::serve(listener, app)
-        .await
-        .map_err(ApplicationError::CannotServe)?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .map_err(ApplicationError::CannotServe)?;
     Ok(())
 }
 
@@ -71,12 +140,93 @@ fn setup_tracing() {
 }
 
 async fn setup_db(data_file: String) -> Result<sqlx::Pool<sqlx::Sqlite>, ApplicationError> {
-    let db = create_db_pool(&data_file)
-        .await
-        .map_err(ApplicationError::from)?;
+    let db = create_db_pool(&data_file).await?;
     Ok(db)
 }
 
+/// Makes sure every stored series has a usable code format before serving requests.
+async fn validate_series(
+    db: &sqlx::Pool<sqlx::Sqlite>,
+    code_prefix: &str,
+    code_format: &CodeFormat,
+    timezone: Tz,
+) -> Result<(), ApplicationError> {
+    for series in read_all_series(db).await? {
+        series
+            .scheme(code_prefix, code_format, timezone)
+            .and_then(|scheme| scheme.format.check_reset(&scheme.reset))
+            .map_err(|e| {
+                ApplicationError::InvalidCodeFormat(e, format!("series '{}'", series.name))
+            })?;
+    }
+
+    Ok(())
+}
+
+/// Finishes migrated codes the way reservations number them, which SQL alone can't do.
+///
+/// Sequence numbers are parsed out of the codes with their series format, codes whose number
+/// can't be found keep what the migrations guessed. Period keys are computed in the series' timezone.
+async fn check_sequences(
+    db: &sqlx::Pool<sqlx::Sqlite>,
+    code_prefix: &str,
+    code_format: &CodeFormat,
+    timezone: Tz,
+) -> Result<(), ApplicationError> {
+    for series in read_all_series(db).await? {
+        let mut checks = read_sequence_checks(db, series.id).await?;
+        if checks.is_empty() {
+            continue;
+        }
+
+        let scheme = series
+            .scheme(code_prefix, code_format, timezone)
+            .map_err(|e| {
+                ApplicationError::InvalidCodeFormat(e, format!("series '{}'", series.name))
+            })?;
+        for check in &mut checks {
+            check.seq = scheme
+                .legacy_seq(&check.code, &check.created_at)
+                .or(check.seq);
+            check.period_key = Some(
+                scheme
+                    .reset
+                    .period_key(&check.created_at.with_timezone(&scheme.timezone)),
+            );
+        }
+
+        save_sequence_checks(db, series.id, &checks).await?;
+        info!(
+            "Checked sequence numbers of {} codes in series '{}'",
+            checks.len(),
+            series.name
+        );
+    }
+
+    Ok(())
+}
+
+/// Prints the address of the setup page when there's no admin yet, returning its token.
+///
+/// The token changes with every start and only works until the first admin is created.
+async fn setup_first_run(
+    db: &sqlx::Pool<sqlx::Sqlite>,
+    host: &str,
+    port: &str,
+) -> Result<Option<String>, ApplicationError> {
+    if has_super_admin(db).await? {
+        return Ok(None);
+    }
+
+    let token = generate_refresh_token();
+    warn!(
+        "No admin account exists yet, create one at http://{}:{}/setup?token={}",
+        host, port, token
+    );
+
+    Ok(Some(token))
+}
+
 fn setup_env() -> Result<(String, String, String, String), ApplicationError> {
     dotenv().ok();
 
@@ -90,3 +240,168 @@ fn setup_env() -> Result<(String, String, String, String), ApplicationError> {
         .map_err(|e| ApplicationError::EnvError(e, "DATABASE_PATH".to_string()))?;
     Ok((host, port, jwt_secret, data_file))
 }
+
+fn setup_code_format() -> Result<(String, CodeFormat), ApplicationError> {
+    let code_prefix = env::var("SERIGEN_CODE_PREFIX").unwrap_or_else(|_| "V".to_string());
+    let code_format = env::var("SERIGEN_CODE_FORMAT")
+        .unwrap_or_else(|_| DEFAULT_CODE_FORMAT.to_string())
+        .parse::<CodeFormat>()
+        .map_err(|e| ApplicationError::InvalidCodeFormat(e, "SERIGEN_CODE_FORMAT".to_string()))?;
+
+    info!(
+        "Using code format '{}' with prefix '{}'",
+        code_format, code_prefix
+    );
+
+    Ok((code_prefix, code_format))
+}
+
+fn setup_max_batch_size() -> Result<u32, ApplicationError> {
+    let max_batch_size = match env::var("SERIGEN_MAX_BATCH_SIZE") {
+        Ok(value) => value.parse::<u32>().ok().filter(|size| *size > 0).ok_or(
+            ApplicationError::InvalidConfig(value, "SERIGEN_MAX_BATCH_SIZE".to_string()),
+        )?,
+        Err(_) => DEFAULT_MAX_BATCH_SIZE,
+    };
+
+    info!("Reserving at most {} numbers at once", max_batch_size);
+
+    Ok(max_batch_size)
+}
+
+fn setup_timezone() -> Result<Tz, ApplicationError> {
+    let timezone = match env::var("SERIGEN_TIMEZONE") {
+        Ok(name) => parse_timezone(&name)
+            .map_err(|_| ApplicationError::InvalidConfig(name, "SERIGEN_TIMEZONE".to_string()))?,
+        Err(_) => Tz::UTC,
+    };
+
+    info!("Using timezone '{}'", timezone);
+
+    Ok(timezone)
+}
+
+/// Reads the rules for new passwords, breached passwords are only rejected with a list file.
+fn setup_password_policy() -> Result<PasswordPolicy, ApplicationError> {
+    let min_length = match env::var("SERIGEN_PASSWORD_MIN_LENGTH") {
+        Ok(value) => value
+            .parse::<usize>()
+            .ok()
+            .filter(|length| *length > 0)
+            .ok_or(ApplicationError::InvalidConfig(
+                value,
+                "SERIGEN_PASSWORD_MIN_LENGTH".to_string(),
+            ))?,
+        Err(_) => DEFAULT_MIN_LENGTH,
+    };
+    let min_strength = match env::var("SERIGEN_PASSWORD_MIN_STRENGTH") {
+        Ok(value) => value
+            .parse::<u8>()
+            .ok()
+            .filter(|strength| *strength <= MAX_STRENGTH)
+            .ok_or(ApplicationError::InvalidConfig(
+                value,
+                "SERIGEN_PASSWORD_MIN_STRENGTH".to_string(),
+            ))?,
+        Err(_) => DEFAULT_MIN_STRENGTH,
+    };
+
+    let mut policy = PasswordPolicy::new(min_length, min_strength);
+
+    if let Ok(path) = env::var("SERIGEN_BREACHED_PASSWORDS_FILE") {
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| ApplicationError::CannotReadFile(e, path.clone()))?;
+        let breached = parse_breached_passwords(&contents);
+
+        info!(
+            "Rejecting {} breached passwords from '{}'",
+            breached.len(),
+            path
+        );
+
+        policy = policy.with_breached_passwords(breached);
+    }
+
+    info!(
+        "Passwords need at least {} characters and strength {}",
+        min_length, min_strength
+    );
+
+    Ok(policy)
+}
+
+/// Reads the reverse proxies allowed to tell the client address and public host, none unless configured.
+fn setup_trusted_proxies() -> Result<Vec<IpAddr>, ApplicationError> {
+    let Ok(value) = env::var("SERIGEN_TRUSTED_PROXIES") else {
+        return Ok(Vec::new());
+    };
+
+    let trusted_proxies = value
+        .split(',')
+        .map(str::trim)
+        .filter(|ip| !ip.is_empty())
+        .map(|ip| {
+            ip.parse::<IpAddr>().map_err(|_| {
+                ApplicationError::InvalidConfig(
+                    ip.to_string(),
+                    "SERIGEN_TRUSTED_PROXIES".to_string(),
+                )
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    info!("Trusting X-Forwarded headers from {:?}", trusted_proxies);
+
+    Ok(trusted_proxies)
+}
+
+/// Reads the address users reach the application at, links are built from the request's host unless configured.
+fn setup_public_url() -> Result<Option<String>, ApplicationError> {
+    let Ok(value) = env::var("SERIGEN_PUBLIC_URL") else {
+        return Ok(None);
+    };
+
+    let public_url = Url::parse(&value)
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https") && url.has_host())
+        .ok_or_else(|| ApplicationError::InvalidConfig(value, "SERIGEN_PUBLIC_URL".to_string()))?;
+
+    info!("Building links from {}", public_url);
+
+    Ok(Some(public_url.to_string()))
+}
+
+/// Reads the identity provider settings, single sign-on is enabled by `SERIGEN_OIDC_ISSUER`.
+fn setup_oidc() -> Result<Option<OidcConfig>, ApplicationError> {
+    let Ok(issuer) = env::var("SERIGEN_OIDC_ISSUER") else {
+        return Ok(None);
+    };
+
+    let required =
+        |name: &str| env::var(name).map_err(|e| ApplicationError::EnvError(e, name.to_string()));
+    let client_id = required("SERIGEN_OIDC_CLIENT_ID")?;
+    let redirect_url = required("SERIGEN_OIDC_REDIRECT_URL")?;
+    let role_mapping = required("SERIGEN_OIDC_ROLE_MAPPING")?;
+    // Public clients only have PKCE
+    let client_secret = env::var("SERIGEN_OIDC_CLIENT_SECRET").ok();
+    let role_claim =
+        env::var("SERIGEN_OIDC_ROLE_CLAIM").unwrap_or_else(|_| DEFAULT_ROLE_CLAIM.to_string());
+
+    let role_mapping = parse_role_mapping(&role_mapping).map_err(|entry| {
+        ApplicationError::InvalidConfig(entry, "SERIGEN_OIDC_ROLE_MAPPING".to_string())
+    })?;
+
+    info!(
+        "Single sign-on through '{}', roles from claim '{}'",
+        issuer, role_claim
+    );
+
+    Ok(Some(OidcConfig::new(
+        &issuer,
+        &client_id,
+        client_secret,
+        &redirect_url,
+        &role_claim,
+        role_mapping,
+    )))
+}
@@ -1,21 +1,54 @@
 use axum::{
     extract::{Request, State},
+    http::{
+        header::{ACCEPT, SET_COOKIE},
+        HeaderName, StatusCode,
+    },
     middleware::Next,
-    response::{IntoResponse, Redirect, Response},
+    response::{AppendHeaders, IntoResponse, Redirect, Response},
+    Extension, Json,
 };
 use axum_extra::extract::CookieJar;
-use jsonwebtoken::{decode, DecodingKey, Validation};
-use tower_sessions::Session;
+use chrono::{Duration, Utc};
+use tower_sessions::{cookie::Cookie, Session};
+use tracing::{error, warn};
 
 use crate::{
-    db::read_user_by_id,
-    jwt::TokenClaims,
+    api_token::{hash_api_token, API_TOKEN_PREFIX},
+    db::{
+        read_api_token_by_hash, read_login_session, read_user_by_id, rotate_refresh_token,
+        touch_api_token, touch_login_session,
+    },
+    jwt::{
+        access_cookie, decode_access_token, encode_access_token, generate_refresh_token,
+        hash_refresh_token, refresh_cookie, removed_cookies, ACCESS_COOKIE, REFRESH_COOKIE,
+        REFRESH_REUSE_GRACE_SECONDS,
+    },
+    models::{RefreshOutcome, User},
+    permissions::{Permission, Permissions},
     state::AppState,
-    templates::{errors::Error401Template, HtmlTemplate},
+    templates::{
+        errors::{Error401Template, Error403Template},
+        HtmlTemplate,
+    },
 };
 
 pub const FROM_PROTECTED_KEY: &str = "from_protected";
 
+/// Page users who have to change their password are limited to.
+pub const CHANGE_PASSWORD_PATH: &str = "/change-password";
+
+/// Reason given to deactivated users, whatever they authenticate with.
+pub const DEACTIVATED: &str = "Your account has been deactivated";
+
+/// Id of the login session the request was authenticated with.
+#[derive(Debug, Clone)]
+pub struct CurrentSession(pub String);
+
+/// Set on requests authenticated with a personal access token instead of a login.
+#[derive(Debug, Clone, Copy)]
+pub struct ApiTokenAuth;
+
 pub async fn auth_middleware(
     State(state): State<AppState>,
     session: Session,
@@ -24,7 +57,7 @@ pub async fn auth_middleware(
     next: Next,
 ) -> Result<Response, Response> {
     let token_option = cookie_jar
-        .get("token")
+        .get(ACCESS_COOKIE)
         .map(|cookie| cookie.value().to_string())
         .or_else(|| {
             req.headers()
@@ -32,48 +65,269 @@ pub async fn auth_middleware(
                 .and_then(|value| value.to_str().ok())
                 .and_then(|value| value.strip_prefix("Bearer ").map(|s| s.to_string()))
         });
+    let refresh_token = cookie_jar
+        .get(REFRESH_COOKIE)
+        .map(|cookie| cookie.value().to_string());
 
-    let token = if let Some(tk) = token_option {
-        tk
-    } else {
+    if token_option.is_none() && refresh_token.is_none() {
         session.insert(FROM_PROTECTED_KEY, false).await.unwrap();
 
         Err(Redirect::to("/login").into_response())?
+    }
+
+    // Personal access tokens are looked up instead of decoded
+    if let Some(token) = token_option
+        .as_deref()
+        .filter(|token| token.starts_with(API_TOKEN_PREFIX))
+    {
+        let user = api_token_user(&state, token).await.map_err(|reason| {
+            (
+                StatusCode::UNAUTHORIZED,
+                HtmlTemplate(Error401Template {
+                    reason,
+                    from_protected: false,
+                    permissions: Permissions::default(),
+                    logged_user: None,
+                }),
+            )
+                .into_response()
+        })?;
+
+        req.extensions_mut().insert(ApiTokenAuth);
+        req.extensions_mut().insert(user);
+
+        return Ok(next.run(req).await);
+    }
+
+    let claims = token_option
+        .as_deref()
+        .and_then(|token| decode_access_token(&state.jwt_secret, token, false));
+
+    // Access tokens expire within minutes, the refresh token replaces them without the user noticing
+    let mut new_cookies = None;
+    let (user_id, session_id) = match (claims, refresh_token) {
+        (Some(claims), _) => (claims.sub, claims.jti),
+        (None, Some(refresh_token)) => {
+            let (user_id, session_id, cookies) = refresh_access(&state, &refresh_token)
+                .await
+                .map_err(|reason| {
+                    let headers = removed_cookies().map(|cookie| (SET_COOKIE, cookie.to_string()));
+                    (
+                        AppendHeaders(headers),
+                        HtmlTemplate(Error401Template {
+                            reason,
+                            from_protected: false,
+                            permissions: Permissions::default(),
+                            logged_user: None,
+                        }),
+                    )
+                        .into_response()
+                })?;
+            new_cookies = cookies;
+            (user_id.to_string(), session_id)
+        }
+        (None, None) => Err(HtmlTemplate(Error401Template {
+            reason: "Invalid token".to_string(),
+            from_protected: false,
+            permissions: Permissions::default(),
+            logged_user: None,
+        })
+        .into_response())?,
     };
 
-    let claims = if let Ok(clm) = decode::<TokenClaims>(
-        &token,
-        &DecodingKey::from_secret(state.jwt_secret.as_ref()),
-        &Validation::default(),
-    ) {
-        clm.claims
-    } else {
+    // The token is only valid as long as its session, which can be revoked
+    let login_session = read_login_session(&state.db, &session_id, state.timezone)
+        .await
+        .ok()
+        .flatten()
+        .filter(|login_session| login_session.user_id.to_string() == user_id);
+
+    let Some(login_session) = login_session else {
         Err(HtmlTemplate(Error401Template {
-            reason: "Invalid token".to_string(),
+            reason: "Session has ended, please log in again".to_string(),
             from_protected: false,
-            is_admin: false,
+            permissions: Permissions::default(),
             logged_user: None,
         })
         .into_response())?
     };
 
-    let user_id = &claims.sub;
-    let user = read_user_by_id(&state.db, user_id).await;
+    let now = Utc::now().with_timezone(&state.timezone).fixed_offset();
+    if let Err(e) = touch_login_session(&state.db, &login_session.id, now).await {
+        error!("Failed to update last use of session: {}", e);
+    }
+
+    let user = read_user_by_id(&state.db, &user_id).await;
 
     match user {
+        Ok(user) if user.deactivated => Err(HtmlTemplate(Error401Template {
+            reason: DEACTIVATED.to_string(),
+            from_protected: false,
+            permissions: Permissions::default(),
+            logged_user: None,
+        })
+        .into_response())?,
+        // Nothing else until the password is changed
+        Ok(user) if user.must_change_password && req.uri().path() != CHANGE_PASSWORD_PATH => {
+            Err(to_change_password(&req))?
+        }
         Ok(user) => {
             session.insert(FROM_PROTECTED_KEY, true).await.unwrap();
 
             req.extensions_mut().insert(user);
+            req.extensions_mut()
+                .insert(CurrentSession(login_session.id));
         }
         Err(e) => Err(HtmlTemplate(Error401Template {
             reason: e.to_string(),
             from_protected: false,
-            is_admin: false,
+            permissions: Permissions::default(),
             logged_user: None,
         })
         .into_response())?,
     }
 
-    Ok::<Response, _>(next.run(req).await)
+    let mut response = next.run(req).await;
+    for cookie in new_cookies.into_iter().flatten() {
+        if let Ok(value) = cookie.to_string().parse() {
+            response.headers_mut().append(SET_COOKIE, value);
+        }
+    }
+
+    Ok(response)
+}
+
+/// Sends the browser to the change password page, htmx follows only its own redirect header.
+fn to_change_password(req: &Request) -> Response {
+    if req.headers().contains_key("hx-request") {
+        (
+            AppendHeaders([(HeaderName::from_static("hx-redirect"), CHANGE_PASSWORD_PATH)]),
+            (),
+        )
+            .into_response()
+    } else {
+        Redirect::to(CHANGE_PASSWORD_PATH).into_response()
+    }
+}
+
+/// Exchanges the refresh token, returning the user and session ids with cookies holding the new
+/// tokens. There are no new cookies when a concurrent request already refreshed.
+async fn refresh_access(
+    state: &AppState,
+    refresh_token: &str,
+) -> Result<(i64, String, Option<[Cookie<'static>; 2]>), String> {
+    let ended = || "Session has ended, please log in again".to_string();
+
+    let new_refresh_token = generate_refresh_token();
+    let now = Utc::now().with_timezone(&state.timezone).fixed_offset();
+    let outcome = rotate_refresh_token(
+        &state.db,
+        &hash_refresh_token(refresh_token),
+        &hash_refresh_token(&new_refresh_token),
+        now,
+        Duration::seconds(REFRESH_REUSE_GRACE_SECONDS),
+    )
+    .await
+    .map_err(|e| e.to_string())?;
+
+    match outcome {
+        RefreshOutcome::Rotated {
+            session_id,
+            user_id,
+        } => {
+            let access_token = encode_access_token(&state.jwt_secret, user_id, &session_id);
+            let cookies = [
+                access_cookie(access_token),
+                refresh_cookie(new_refresh_token),
+            ];
+            Ok((user_id, session_id, Some(cookies)))
+        }
+        RefreshOutcome::Raced {
+            session_id,
+            user_id,
+        } => Ok((user_id, session_id, None)),
+        RefreshOutcome::Reused { user_id } => {
+            warn!(
+                "Refresh token of user {} was used twice, ending the session",
+                user_id
+            );
+            Err(ended())
+        }
+        RefreshOutcome::Invalid => Err(ended()),
+    }
+}
+
+/// Finds the owner of the token, limited to the scopes of the token.
+async fn api_token_user(state: &AppState, token: &str) -> Result<User, String> {
+    let api_token = read_api_token_by_hash(&state.db, &hash_api_token(token), state.timezone)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Invalid token".to_string())?;
+
+    if api_token.expired {
+        return Err("Token expired".to_string());
+    }
+
+    let mut user = read_user_by_id(&state.db, &api_token.user_id.to_string())
+        .await
+        .map_err(|e| e.to_string())?;
+    if user.deactivated {
+        return Err(DEACTIVATED.to_string());
+    }
+    // Tokens can't change the password, the owner has to log in for that first
+    if user.must_change_password {
+        return Err("Password has to be changed before using tokens".to_string());
+    }
+    user.permissions = user.permissions.intersection(api_token.scopes);
+
+    let now = Utc::now().with_timezone(&state.timezone).fixed_offset();
+    if let Err(e) = touch_api_token(&state.db, api_token.id, now).await {
+        error!(
+            "Failed to update last use of API token {}: {}",
+            api_token.id, e
+        );
+    }
+
+    Ok(user)
+}
+
+/// Lets through only users with the permission, must run after `auth_middleware` which
+/// provides the user.
+///
+/// Clients asking for JSON get a JSON error body, everyone else gets the error page.
+pub async fn permission_middleware(
+    State(permission): State<Permission>,
+    Extension(user): Extension<User>,
+    req: Request,
+    next: Next,
+) -> Result<Response, Response> {
+    if user.can(permission) {
+        return Ok(next.run(req).await);
+    }
+
+    let reason = "You don't have permission to do this".to_string();
+    let wants_json = req
+        .headers()
+        .get(ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("application/json"));
+
+    if wants_json {
+        Err((
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({ "error": reason })),
+        )
+            .into_response())
+    } else {
+        Err((
+            StatusCode::FORBIDDEN,
+            HtmlTemplate(Error403Template {
+                reason,
+                from_protected: true,
+                permissions: user.permissions,
+                logged_user: Some(user.name),
+            }),
+        )
+            .into_response())
+    }
 }
@@ -1,72 +1,506 @@
-use chrono::NaiveDateTime;
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
 
-use crate::utils::format_date;
+use crate::{
+    check_digit::CheckDigit,
+    code_format::{parse_timezone, CodeFormat, CodeScheme, ResetPeriod},
+    errors::code_format::CodeFormatError,
+    permissions::{permissions_of, Permission, Permissions, Role},
+    utils::format_date,
+};
+
+/// Series used when a reservation doesn't name one.
+pub const DEFAULT_SERIES_ID: i64 = 1;
 
 pub struct CodeEntity {
     #[allow(dead_code)]
     pub id: i64,
     pub code: String,
-    pub created_at: NaiveDateTime,
+    pub created_at: DateTime<FixedOffset>,
     #[allow(dead_code)]
     pub user_id: i64,
     pub user_name: String,
 }
 
+/// Code queued for reading its sequence number back out of the rendered code.
 #[derive(Debug)]
+pub struct SequenceCheck {
+    pub id: i64,
+    pub code: String,
+    pub created_at: DateTime<FixedOffset>,
+    pub period_key: Option<String>,
+    pub seq: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
 pub struct Code {
     pub code: String,
     pub created_at: String,
     pub user_name: String,
 }
 
-impl From<CodeEntity> for Code {
-    fn from(code: CodeEntity) -> Self {
+impl Code {
+    /// Shows the dates in the timezone.
+    pub fn new(code: CodeEntity, timezone: Tz) -> Self {
         Code {
             code: code.code,
-            created_at: format_date(code.created_at),
+            created_at: format_date(code.created_at, timezone),
             user_name: code.user_name,
         }
     }
 }
 
-pub struct CodeValueEntity {
-    pub code: String,
-}
-
-#[derive(Debug)]
-pub struct CodeValue {
-    pub code: String,
-}
-
-impl From<CodeValueEntity> for CodeValue {
-    fn from(code: CodeValueEntity) -> Self {
-        CodeValue { code: code.code }
-    }
-}
-
 #[derive(Debug)]
 pub struct UserEntity {
     #[allow(dead_code)]
     pub id: i64,
     pub name: String,
     pub password: String,
-    pub is_admin: i64,
+    /// Comma separated role names
+    pub roles: Option<String>,
+    pub two_factor: bool,
+    pub failed_logins: i64,
+    pub must_change_password: bool,
+    pub deactivated: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct User {
     pub id: i64,
     #[allow(dead_code)]
     pub name: String,
-    pub is_admin: bool,
+    pub roles: Vec<Role>,
+    #[serde(skip)]
+    pub permissions: Permissions,
+    /// Whether logging in needs a code of an authenticator app.
+    pub two_factor: bool,
+    /// Failed logins since the last successful one.
+    #[serde(skip)]
+    pub failed_logins: i64,
+    /// Whether the user has to change the password before doing anything else.
+    #[serde(skip)]
+    pub must_change_password: bool,
+    /// Deactivated users keep their row but can't log in.
+    pub deactivated: bool,
+}
+
+impl User {
+    pub fn can(&self, permission: Permission) -> bool {
+        self.permissions.contains(permission)
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.permissions.is_admin()
+    }
+
+    pub fn has_role(&self, role: &Role) -> bool {
+        self.roles.contains(role)
+    }
 }
 
 impl From<UserEntity> for User {
     fn from(val: UserEntity) -> Self {
+        let roles = parse_roles(&val.roles.unwrap_or_default());
+
         User {
             id: val.id,
             name: val.name,
-            is_admin: val.is_admin == 1,
+            permissions: permissions_of(&roles),
+            roles,
+            two_factor: val.two_factor,
+            failed_logins: val.failed_logins,
+            must_change_password: val.must_change_password,
+            deactivated: val.deactivated,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SeriesEntity {
+    pub id: i64,
+    pub name: String,
+    pub prefix: Option<String>,
+    pub format: Option<String>,
+    pub reset_period: String,
+    pub check_digit: Option<String>,
+    pub timezone: Option<String>,
+    pub description: String,
+    pub is_active: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Series {
+    pub id: i64,
+    pub name: String,
+    pub prefix: Option<String>,
+    pub format: Option<String>,
+    pub reset_period: String,
+    pub check_digit: Option<String>,
+    pub timezone: Option<String>,
+    pub description: String,
+    pub is_active: bool,
+}
+
+/// Settings of a series about to be created.
+#[derive(Debug)]
+pub struct NewSeries<'a> {
+    pub name: &'a str,
+    pub prefix: Option<&'a str>,
+    pub format: Option<&'a str>,
+    pub reset: ResetPeriod,
+    pub check_digit: Option<CheckDigit>,
+    pub timezone: Option<Tz>,
+    pub description: &'a str,
+}
+
+/// Codes archived by a reset. Unset fields don't limit the scope.
+#[derive(Debug, Default, Serialize)]
+pub struct ResetScope {
+    pub series_id: Option<i64>,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+impl Series {
+    /// Timezone the codes of the series are dated in, falling back to the configured default.
+    pub fn display_timezone(&self, default_timezone: Tz) -> Tz {
+        self.timezone
+            .as_deref()
+            .and_then(|timezone| parse_timezone(timezone).ok())
+            .unwrap_or(default_timezone)
+    }
+
+    /// Resolves how the series generates codes, falling back to the configured defaults.
+    pub fn scheme(
+        &self,
+        default_prefix: &str,
+        default_format: &CodeFormat,
+        default_timezone: Tz,
+    ) -> Result<CodeScheme, CodeFormatError> {
+        let format = match &self.format {
+            Some(format) => format.parse()?,
+            None => default_format.clone(),
+        };
+
+        Ok(CodeScheme {
+            series_id: self.id,
+            prefix: self
+                .prefix
+                .clone()
+                .unwrap_or_else(|| default_prefix.to_string()),
+            format,
+            reset: self.reset_period.parse::<ResetPeriod>()?,
+            check_digit: self
+                .check_digit
+                .as_deref()
+                .map(str::parse::<CheckDigit>)
+                .transpose()?,
+            timezone: match &self.timezone {
+                Some(timezone) => parse_timezone(timezone)?,
+                None => default_timezone,
+            },
+        })
+    }
+}
+
+impl From<SeriesEntity> for Series {
+    fn from(val: SeriesEntity) -> Self {
+        Series {
+            id: val.id,
+            name: val.name,
+            prefix: val.prefix,
+            format: val.format,
+            reset_period: val.reset_period,
+            check_digit: val.check_digit,
+            timezone: val.timezone,
+            description: val.description,
+            is_active: val.is_active == 1,
+        }
+    }
+}
+
+/// Result of validating a code typed in by hand.
+#[derive(Debug, Serialize)]
+pub struct CodeValidation {
+    pub code: String,
+    pub exists: bool,
+    pub series: String,
+    pub check_digit: Option<String>,
+    /// `None` when the series doesn't use check digits.
+    pub check_digit_valid: Option<bool>,
+}
+
+/// Audit log entry about to be written.
+#[derive(Debug)]
+pub struct NewAuditEvent {
+    pub created_at: DateTime<FixedOffset>,
+    pub actor_id: Option<i64>,
+    pub actor_name: Option<String>,
+    pub action: String,
+    pub target: Option<String>,
+    pub before_json: Option<String>,
+    pub after_json: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+pub struct AuditEventEntity {
+    pub id: i64,
+    pub created_at: DateTime<FixedOffset>,
+    pub actor_name: Option<String>,
+    pub action: String,
+    pub target: Option<String>,
+    pub before_json: Option<String>,
+    pub after_json: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct AuditEvent {
+    #[allow(dead_code)]
+    pub id: i64,
+    pub created_at: String,
+    pub actor_name: Option<String>,
+    pub action: String,
+    pub target: Option<String>,
+    pub before_json: Option<String>,
+    pub after_json: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl From<AuditEventEntity> for AuditEvent {
+    fn from(val: AuditEventEntity) -> Self {
+        AuditEvent {
+            id: val.id,
+            created_at: val.created_at.format("%Y-%m-%d %H:%M:%S %:z").to_string(),
+            actor_name: val.actor_name,
+            action: val.action,
+            target: val.target,
+            before_json: val.before_json,
+            after_json: val.after_json,
+            ip: val.ip,
+            user_agent: val.user_agent,
+        }
+    }
+}
+
+/// Filters of the audit log. Unset fields match everything.
+#[derive(Debug, Default)]
+pub struct AuditFilter {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    /// First day (YYYY-MM-DD) in the timezone the events were stored with.
+    pub from: Option<String>,
+    /// Last day (YYYY-MM-DD) in the timezone the events were stored with.
+    pub to: Option<String>,
+}
+
+/// Personal access token about to be stored.
+pub struct NewApiToken<'a> {
+    pub user_id: i64,
+    pub name: &'a str,
+    pub token_hash: String,
+    pub token_prefix: String,
+    pub scopes: Permissions,
+    pub created_at: DateTime<FixedOffset>,
+    pub expires_at: Option<DateTime<FixedOffset>>,
+}
+
+pub struct ApiTokenEntity {
+    pub id: i64,
+    pub user_id: i64,
+    pub name: String,
+    pub token_prefix: String,
+    /// Comma separated permission names
+    pub scopes: String,
+    pub created_at: DateTime<FixedOffset>,
+    pub expires_at: Option<DateTime<FixedOffset>>,
+    pub last_used_at: Option<DateTime<FixedOffset>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiToken {
+    pub id: i64,
+    pub user_id: i64,
+    pub name: String,
+    pub token_prefix: String,
+    pub scopes: Permissions,
+    pub created_at: String,
+    pub expires_at: Option<String>,
+    pub last_used_at: Option<String>,
+    pub expired: bool,
+}
+
+impl ApiToken {
+    /// Shows the dates in the timezone.
+    pub fn new(token: ApiTokenEntity, timezone: Tz) -> Self {
+        ApiToken {
+            id: token.id,
+            user_id: token.user_id,
+            name: token.name,
+            token_prefix: token.token_prefix,
+            // Scopes removed from the application are ignored
+            scopes: token
+                .scopes
+                .split(',')
+                .filter_map(|name| name.parse().ok())
+                .collect(),
+            created_at: format_date(token.created_at, timezone),
+            expires_at: token.expires_at.map(|expires_at| {
+                expires_at
+                    .with_timezone(&timezone)
+                    .format("%Y-%m-%d")
+                    .to_string()
+            }),
+            last_used_at: token
+                .last_used_at
+                .map(|last_used_at| format_date(last_used_at, timezone)),
+            expired: token
+                .expires_at
+                .is_some_and(|expires_at| expires_at <= Utc::now()),
+        }
+    }
+}
+
+/// Browser login about to be stored.
+pub struct NewLoginSession<'a> {
+    pub id: &'a str,
+    pub user_id: i64,
+    pub created_at: DateTime<FixedOffset>,
+    pub expires_at: DateTime<FixedOffset>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    /// Hash of the first refresh token.
+    pub refresh_hash: String,
+}
+
+pub struct LoginSessionEntity {
+    pub id: String,
+    pub user_id: i64,
+    pub created_at: DateTime<FixedOffset>,
+    pub last_seen_at: DateTime<FixedOffset>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LoginSession {
+    pub id: String,
+    pub user_id: i64,
+    pub created_at: String,
+    pub last_seen_at: String,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl LoginSession {
+    /// Shows the dates in the timezone.
+    pub fn new(session: LoginSessionEntity, timezone: Tz) -> Self {
+        LoginSession {
+            id: session.id,
+            user_id: session.user_id,
+            created_at: format_date(session.created_at, timezone),
+            last_seen_at: format_date(session.last_seen_at, timezone),
+            ip: session.ip,
+            user_agent: session.user_agent,
+        }
+    }
+}
+
+/// Result of exchanging a refresh token for a new one.
+#[derive(Debug, PartialEq)]
+pub enum RefreshOutcome {
+    /// The token was replaced by the new one.
+    Rotated { session_id: String, user_id: i64 },
+    /// The token was replaced moments ago by a concurrent request, the new one isn't stored.
+    Raced { session_id: String, user_id: i64 },
+    /// The token was replaced long ago so it must have leaked, the session was ended.
+    Reused { user_id: i64 },
+    /// Unknown token or the session is over.
+    Invalid,
+}
+
+#[derive(Debug)]
+pub struct UserTotpEntity {
+    pub secret: String,
+    pub last_step: i64,
+}
+
+/// Settings admins change at runtime.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Settings {
+    /// Admins have to use two-factor authentication to log in with a password.
+    pub require_admin_two_factor: bool,
+}
+
+/// Failed logins of a username or client address, with the lock they caused.
+#[derive(Debug)]
+pub struct LoginFailureEntity {
+    pub failures: i64,
+    pub locked_until: Option<DateTime<FixedOffset>>,
+}
+
+/// One-time link for setting a new password.
+#[derive(Debug)]
+pub struct PasswordResetEntity {
+    pub user_id: i64,
+    pub expires_at: DateTime<FixedOffset>,
+}
+
+/// Invitation about to be stored.
+pub struct NewInvite<'a> {
+    pub name: &'a str,
+    pub roles: &'a [Role],
+    pub token_hash: String,
+    pub created_by: i64,
+    pub created_at: DateTime<FixedOffset>,
+    pub expires_at: DateTime<FixedOffset>,
+}
+
+pub struct InviteEntity {
+    pub id: i64,
+    pub name: String,
+    /// Comma separated role names
+    pub roles: String,
+    pub invited_by: Option<String>,
+    pub created_at: DateTime<FixedOffset>,
+    pub expires_at: DateTime<FixedOffset>,
+}
+
+/// Invitation to create an account with the name and roles.
+#[derive(Debug, Clone, Serialize)]
+pub struct Invite {
+    pub id: i64,
+    pub name: String,
+    pub roles: Vec<Role>,
+    pub invited_by: Option<String>,
+    pub created_at: String,
+    pub expires_at: String,
+    pub expired: bool,
+}
+
+impl Invite {
+    /// Shows the dates in the timezone.
+    pub fn new(invite: InviteEntity, timezone: Tz) -> Self {
+        Invite {
+            id: invite.id,
+            name: invite.name,
+            roles: parse_roles(&invite.roles),
+            invited_by: invite.invited_by,
+            created_at: format_date(invite.created_at, timezone),
+            expires_at: format_date(invite.expires_at, timezone),
+            expired: invite.expires_at <= Utc::now(),
         }
     }
 }
+
+/// Roles from comma separated names, ones removed from the application are ignored.
+pub fn parse_roles(roles: &str) -> Vec<Role> {
+    roles
+        .split(',')
+        .filter_map(|name| name.parse().ok())
+        .collect()
+}
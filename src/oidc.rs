@@ -0,0 +1,372 @@
+use std::time::Duration;
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{AlgorithmParameters, JwkSet},
+    DecodingKey, Validation,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use crate::{errors::oidc_login::OidcLoginError, permissions::Role};
+
+/// Session key of the login waiting for the identity provider to send the user back.
+pub const OIDC_LOGIN_KEY: &str = "oidc_login";
+
+/// Claim holding the groups of the user when `SERIGEN_OIDC_ROLE_CLAIM` isn't set.
+pub const DEFAULT_ROLE_CLAIM: &str = "groups";
+
+/// Scopes asked for, the profile and email hold the name of the user.
+const SCOPES: &str = "openid profile email";
+
+/// Longest wait for the identity provider, it's called while the user waits.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// OpenID Connect identity provider users can log in with.
+#[derive(Debug, Clone)]
+pub struct OidcConfig {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    /// Address of the callback route, as registered at the provider.
+    pub redirect_url: String,
+    /// Claim listing the groups of the user, nested claims are separated by dots.
+    pub role_claim: String,
+    /// Role given to members of each group.
+    pub role_mapping: Vec<(String, Role)>,
+    http: reqwest::Client,
+}
+
+/// Login waiting for the identity provider, kept in the session until the callback.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PendingLogin {
+    pub state: String,
+    pub nonce: String,
+    pub code_verifier: String,
+}
+
+impl PendingLogin {
+    pub fn new() -> Self {
+        PendingLogin {
+            state: random_value(),
+            nonce: random_value(),
+            code_verifier: random_value(),
+        }
+    }
+}
+
+/// Endpoints of the provider from its discovery document.
+#[derive(Debug, Deserialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+/// Claims of a verified ID token.
+#[derive(Debug, Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub nonce: Option<String>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+impl IdTokenClaims {
+    /// Name of the account, from the first of `preferred_username`, `email` and `sub` present.
+    pub fn username(&self) -> &str {
+        ["preferred_username", "email"]
+            .into_iter()
+            .find_map(|claim| self.other.get(claim).and_then(Value::as_str))
+            .unwrap_or(&self.sub)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+impl OidcConfig {
+    pub fn new(
+        issuer: &str,
+        client_id: &str,
+        client_secret: Option<String>,
+        redirect_url: &str,
+        role_claim: &str,
+        role_mapping: Vec<(String, Role)>,
+    ) -> Self {
+        Self {
+            issuer: issuer.to_string(),
+            client_id: client_id.to_string(),
+            client_secret,
+            redirect_url: redirect_url.to_string(),
+            role_claim: role_claim.to_string(),
+            role_mapping,
+            http: reqwest::Client::builder()
+                .timeout(HTTP_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Reads the endpoints of the provider.
+    ///
+    /// It's asked on every login, so the provider being down doesn't keep the application from
+    /// starting and local logins keep working.
+    pub async fn discover(&self) -> Result<ProviderMetadata, OidcLoginError> {
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            self.issuer.trim_end_matches('/')
+        );
+        let metadata: ProviderMetadata = self
+            .http
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        if metadata.issuer != self.issuer {
+            return Err(OidcLoginError::IssuerMismatch(metadata.issuer));
+        }
+
+        Ok(metadata)
+    }
+
+    /// Address of the provider's login page the user is sent to.
+    pub fn authorization_url(
+        &self,
+        metadata: &ProviderMetadata,
+        login: &PendingLogin,
+    ) -> Result<Url, OidcLoginError> {
+        let mut url = Url::parse(&metadata.authorization_endpoint)?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.client_id)
+            .append_pair("redirect_uri", &self.redirect_url)
+            .append_pair("scope", SCOPES)
+            .append_pair("state", &login.state)
+            .append_pair("nonce", &login.nonce)
+            .append_pair("code_challenge", &pkce_challenge(&login.code_verifier))
+            .append_pair("code_challenge_method", "S256");
+
+        Ok(url)
+    }
+
+    /// Exchanges the authorization code for an ID token and returns its verified claims.
+    pub async fn exchange_code(
+        &self,
+        metadata: &ProviderMetadata,
+        login: &PendingLogin,
+        code: &str,
+    ) -> Result<IdTokenClaims, OidcLoginError> {
+        let params = [
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.redirect_url),
+            ("client_id", &self.client_id),
+            ("code_verifier", &login.code_verifier),
+        ];
+        let mut request = self.http.post(&metadata.token_endpoint).form(&params);
+        if let Some(secret) = &self.client_secret {
+            request = request.basic_auth(&self.client_id, Some(secret));
+        }
+
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            let reason = response.text().await.unwrap_or_default();
+            return Err(OidcLoginError::TokenExchange(reason));
+        }
+        let tokens: TokenResponse = response.json().await?;
+
+        let keys: JwkSet = self
+            .http
+            .get(&metadata.jwks_uri)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let claims = verify_id_token(&tokens.id_token, &keys, &metadata.issuer, &self.client_id)?;
+        if claims.nonce.as_deref() != Some(login.nonce.as_str()) {
+            return Err(OidcLoginError::NonceMismatch);
+        }
+
+        Ok(claims)
+    }
+
+    /// Roles given to the groups the user is in.
+    pub fn roles(&self, claims: &IdTokenClaims) -> Vec<Role> {
+        let groups = claim_values(&claims.other, &self.role_claim);
+
+        let mut roles = vec![];
+        for (group, role) in &self.role_mapping {
+            if groups.contains(&group.as_str()) && !roles.contains(role) {
+                roles.push(*role);
+            }
+        }
+        roles
+    }
+}
+
+/// Parses mappings of groups to roles, like `staff=reserver,it=super_admin`.
+///
+/// Returns the first invalid entry as error.
+pub fn parse_role_mapping(value: &str) -> Result<Vec<(String, Role)>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (group, role) = entry.split_once('=').ok_or_else(|| entry.to_string())?;
+            let role = role.trim().parse::<Role>().map_err(|_| entry.to_string())?;
+
+            Ok((group.trim().to_string(), role))
+        })
+        .collect()
+}
+
+/// S256 challenge of the PKCE code verifier.
+pub fn pkce_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+fn random_value() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn verify_id_token(
+    token: &str,
+    keys: &JwkSet,
+    issuer: &str,
+    client_id: &str,
+) -> Result<IdTokenClaims, OidcLoginError> {
+    let header = decode_header(token)?;
+
+    let key = match &header.kid {
+        Some(kid) => keys.find(kid),
+        None => keys.keys.first(),
+    }
+    .ok_or(OidcLoginError::UnknownKey)?;
+
+    // Secret keys have no business in a public key set
+    if matches!(key.algorithm, AlgorithmParameters::OctetKey(_)) {
+        return Err(OidcLoginError::UnknownKey);
+    }
+
+    // The algorithm has to match the kind of the key, so trusting the header is fine
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[issuer]);
+    validation.set_audience(&[client_id]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+    let data = decode::<IdTokenClaims>(token, &DecodingKey::from_jwk(key)?, &validation)?;
+
+    Ok(data.claims)
+}
+
+/// Values of the claim at the dotted path, a single string counts as one value.
+fn claim_values<'a>(claims: &'a Map<String, Value>, path: &str) -> Vec<&'a str> {
+    let mut names = path.split('.');
+    let first = names.next().and_then(|name| claims.get(name));
+    let value = names.fold(first, |value, name| value.and_then(|value| value.get(name)));
+
+    match value {
+        Some(Value::String(value)) => vec![value.as_str()],
+        Some(Value::Array(values)) => values.iter().filter_map(Value::as_str).collect(),
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use crate::{
+        oidc::{parse_role_mapping, pkce_challenge, IdTokenClaims, OidcConfig},
+        permissions::Role,
+    };
+
+    fn config(role_claim: &str) -> OidcConfig {
+        OidcConfig::new(
+            "https://sso.example.com",
+            "serigen",
+            None,
+            "https://serigen.example.com/login/oidc/callback",
+            role_claim,
+            parse_role_mapping("staff=reserver, it=super_admin, staff=viewer").unwrap(),
+        )
+    }
+
+    fn claims(value: serde_json::Value) -> IdTokenClaims {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn role_mapping() {
+        assert_eq!(
+            parse_role_mapping("staff=reserver,it=super_admin,"),
+            Ok(vec![
+                ("staff".to_string(), Role::Reserver),
+                ("it".to_string(), Role::SuperAdmin)
+            ])
+        );
+        assert_eq!(
+            parse_role_mapping("staff=reserver,it=admin"),
+            Err("it=admin".to_string())
+        );
+        assert_eq!(parse_role_mapping("staff"), Err("staff".to_string()));
+    }
+
+    #[test]
+    fn roles_from_groups() {
+        let claims = claims(json!({ "sub": "1", "groups": ["staff", "other"] }));
+
+        assert_eq!(
+            config("groups").roles(&claims),
+            vec![Role::Reserver, Role::Viewer]
+        );
+    }
+
+    #[test]
+    fn roles_from_nested_claim() {
+        let claims = claims(json!({ "sub": "1", "realm_access": { "roles": "it" } }));
+
+        assert_eq!(
+            config("realm_access.roles").roles(&claims),
+            vec![Role::SuperAdmin]
+        );
+        assert!(config("groups").roles(&claims).is_empty());
+    }
+
+    #[test]
+    fn username_fallbacks() {
+        let named = claims(json!({ "sub": "1", "preferred_username": "jane", "email": "j@x" }));
+        let email = claims(json!({ "sub": "1", "email": "j@x" }));
+        let bare = claims(json!({ "sub": "1" }));
+
+        assert_eq!(named.username(), "jane");
+        assert_eq!(email.username(), "j@x");
+        assert_eq!(bare.username(), "1");
+    }
+
+    #[test]
+    fn challenge() {
+        // Example of RFC 7636
+        assert_eq!(
+            pkce_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+}
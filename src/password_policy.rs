@@ -0,0 +1,140 @@
+use std::{collections::HashSet, sync::Arc};
+
+use crate::errors::password_policy::PasswordPolicyError;
+
+/// Characters a new password needs at least, unless configured otherwise.
+pub const DEFAULT_MIN_LENGTH: usize = 12;
+
+/// Strength estimate a new password needs at least, from 0 (guessed at once) to 4.
+pub const DEFAULT_MIN_STRENGTH: u8 = 3;
+
+/// Highest strength estimate there is.
+pub const MAX_STRENGTH: u8 = 4;
+
+/// Characters of a password looked at by the strength estimate, which slows down with length.
+const MAX_ESTIMATED_LENGTH: usize = 100;
+
+/// Rules new passwords are checked against.
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub min_strength: u8,
+    /// Known breached passwords in lowercase.
+    breached: Arc<HashSet<String>>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self::new(DEFAULT_MIN_LENGTH, DEFAULT_MIN_STRENGTH)
+    }
+}
+
+impl PasswordPolicy {
+    pub fn new(min_length: usize, min_strength: u8) -> Self {
+        Self {
+            min_length,
+            min_strength,
+            breached: Arc::new(HashSet::new()),
+        }
+    }
+
+    pub fn with_breached_passwords(mut self, breached: HashSet<String>) -> Self {
+        self.breached = Arc::new(breached);
+        self
+    }
+
+    /// Checks a new password, `user_inputs` like the username make it weaker when it contains them.
+    pub fn check(&self, password: &str, user_inputs: &[&str]) -> Result<(), PasswordPolicyError> {
+        if password.chars().count() < self.min_length {
+            return Err(PasswordPolicyError::TooShort(self.min_length));
+        }
+
+        if self.breached.contains(&password.to_lowercase()) {
+            return Err(PasswordPolicyError::Breached);
+        }
+
+        let estimated: String = password.chars().take(MAX_ESTIMATED_LENGTH).collect();
+        let entropy = zxcvbn::zxcvbn(&estimated, user_inputs);
+        if u8::from(entropy.score()) < self.min_strength {
+            return Err(PasswordPolicyError::TooWeak(weakness_hint(&entropy)));
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads a breached password list, one password per line.
+pub fn parse_breached_passwords(contents: &str) -> HashSet<String> {
+    contents
+        .lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_lowercase())
+        .collect()
+}
+
+/// What makes the password weak, or how to make it stronger.
+fn weakness_hint(entropy: &zxcvbn::Entropy) -> String {
+    let feedback = entropy.feedback();
+    let warning = feedback.and_then(|feedback| feedback.warning());
+    let suggestion = feedback.and_then(|feedback| feedback.suggestions().first());
+
+    match (warning, suggestion) {
+        (Some(warning), _) => warning.to_string(),
+        (None, Some(suggestion)) => suggestion.to_string(),
+        (None, None) => "Add another word or two.".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        errors::password_policy::PasswordPolicyError,
+        password_policy::{parse_breached_passwords, PasswordPolicy},
+    };
+
+    #[test]
+    fn short_passwords_are_rejected() {
+        let policy = PasswordPolicy::default();
+
+        assert_eq!(
+            policy.check("", &[]),
+            Err(PasswordPolicyError::TooShort(12))
+        );
+        assert_eq!(
+            policy.check("Xq7#vL2!", &[]),
+            Err(PasswordPolicyError::TooShort(12))
+        );
+    }
+
+    #[test]
+    fn weak_passwords_are_rejected() {
+        let policy = PasswordPolicy::default();
+
+        assert!(matches!(
+            policy.check("passwordpassword", &[]),
+            Err(PasswordPolicyError::TooWeak(_))
+        ));
+        assert!(matches!(
+            policy.check("Alice12345678", &["Alice"]),
+            Err(PasswordPolicyError::TooWeak(_))
+        ));
+        assert_eq!(policy.check("correct horse battery staple", &[]), Ok(()));
+    }
+
+    #[test]
+    fn breached_passwords_are_rejected() {
+        let breached = parse_breached_passwords("correct horse battery staple\r\n\nhunter2\n");
+        let policy = PasswordPolicy::new(1, 0).with_breached_passwords(breached);
+
+        assert_eq!(
+            policy.check("Correct Horse Battery Staple", &[]),
+            Err(PasswordPolicyError::Breached)
+        );
+        assert_eq!(
+            policy.check("hunter2", &[]),
+            Err(PasswordPolicyError::Breached)
+        );
+        assert_eq!(policy.check("hunter3", &[]), Ok(()));
+    }
+}
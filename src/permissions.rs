@@ -0,0 +1,264 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::{ser::SerializeSeq, Deserialize, Serialize, Serializer};
+
+/// Something a user is allowed to do, checked before the matching handlers run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Permission {
+    ViewCodes,
+    ReserveCodes,
+    ResetCodes,
+    ManageSeries,
+    ManageUsers,
+    ViewAuditLog,
+    ManageSettings,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 7] = [
+        Permission::ViewCodes,
+        Permission::ReserveCodes,
+        Permission::ResetCodes,
+        Permission::ManageSeries,
+        Permission::ManageUsers,
+        Permission::ViewAuditLog,
+        Permission::ManageSettings,
+    ];
+
+    /// Name used for the scopes of API tokens.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::ViewCodes => "codes:view",
+            Permission::ReserveCodes => "codes:reserve",
+            Permission::ResetCodes => "codes:reset",
+            Permission::ManageSeries => "series:manage",
+            Permission::ManageUsers => "users:manage",
+            Permission::ViewAuditLog => "audit:view",
+            Permission::ManageSettings => "settings:manage",
+        }
+    }
+
+    /// Human readable name for the UI.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Permission::ViewCodes => "View numbers",
+            Permission::ReserveCodes => "Reserve numbers",
+            Permission::ResetCodes => "Reset counting",
+            Permission::ManageSeries => "Manage series",
+            Permission::ManageUsers => "Manage users",
+            Permission::ViewAuditLog => "View audit log",
+            Permission::ManageSettings => "Manage settings",
+        }
+    }
+
+    /// Whether the permission goes beyond using the numbers, to changing or wiping them.
+    pub fn is_admin(&self) -> bool {
+        !matches!(self, Permission::ViewCodes | Permission::ReserveCodes)
+    }
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+impl FromStr for Permission {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Permission::ALL
+            .into_iter()
+            .find(|permission| permission.as_str() == value)
+            .ok_or_else(|| value.to_string())
+    }
+}
+
+impl Display for Permission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Set of permissions granted to a user by their roles.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Permissions(u8);
+
+impl Permissions {
+    pub fn contains(&self, permission: Permission) -> bool {
+        self.0 & permission.bit() != 0
+    }
+
+    /// Permissions present in both sets.
+    pub fn intersection(&self, other: Permissions) -> Permissions {
+        Permissions(self.0 & other.0)
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.iter().any(|permission| permission.is_admin())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Permission> + '_ {
+        Permission::ALL
+            .into_iter()
+            .filter(|permission| self.contains(*permission))
+    }
+}
+
+/// Serialized as the list of permission names.
+impl Serialize for Permissions {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(None)?;
+        for permission in self.iter() {
+            seq.serialize_element(permission.as_str())?;
+        }
+        seq.end()
+    }
+}
+
+impl FromIterator<Permission> for Permissions {
+    fn from_iter<I: IntoIterator<Item = Permission>>(iter: I) -> Self {
+        Permissions(iter.into_iter().fold(0, |bits, p| bits | p.bit()))
+    }
+}
+
+/// Role a user can be given, stored by name in the `roles` table.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Viewer,
+    Reserver,
+    SeriesManager,
+    UserAdmin,
+    SuperAdmin,
+}
+
+impl Role {
+    pub const ALL: [Role; 5] = [
+        Role::Viewer,
+        Role::Reserver,
+        Role::SeriesManager,
+        Role::UserAdmin,
+        Role::SuperAdmin,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Reserver => "reserver",
+            Role::SeriesManager => "series_manager",
+            Role::UserAdmin => "user_admin",
+            Role::SuperAdmin => "super_admin",
+        }
+    }
+
+    /// Human readable name for the UI.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Role::Viewer => "Viewer",
+            Role::Reserver => "Reserver",
+            Role::SeriesManager => "Series manager",
+            Role::UserAdmin => "User admin",
+            Role::SuperAdmin => "Super admin",
+        }
+    }
+
+    pub fn permissions(&self) -> &'static [Permission] {
+        use Permission::*;
+
+        match self {
+            Role::Viewer => &[ViewCodes],
+            Role::Reserver => &[ViewCodes, ReserveCodes],
+            Role::SeriesManager => &[ViewCodes, ManageSeries, ResetCodes],
+            Role::UserAdmin => &[ViewCodes, ManageUsers],
+            Role::SuperAdmin => &[
+                ViewCodes,
+                ReserveCodes,
+                ResetCodes,
+                ManageSeries,
+                ManageUsers,
+                ViewAuditLog,
+                ManageSettings,
+            ],
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Role::ALL
+            .into_iter()
+            .find(|role| role.as_str() == value)
+            .ok_or_else(|| value.to_string())
+    }
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Collects the permissions granted by any of the roles.
+pub fn permissions_of(roles: &[Role]) -> Permissions {
+    roles
+        .iter()
+        .flat_map(|role| role.permissions().iter().copied())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::permissions::{permissions_of, Permission, Permissions, Role};
+
+    #[test]
+    fn permissions_combine() {
+        let permissions = permissions_of(&[Role::Viewer, Role::SeriesManager]);
+
+        assert!(permissions.contains(Permission::ViewCodes));
+        assert!(permissions.contains(Permission::ManageSeries));
+        assert!(!permissions.contains(Permission::ReserveCodes));
+        assert!(!permissions.contains(Permission::ManageUsers));
+    }
+
+    #[test]
+    fn admins() {
+        assert!(!permissions_of(&[Role::Viewer, Role::Reserver]).is_admin());
+        assert!(permissions_of(&[Role::SeriesManager]).is_admin());
+        assert!(permissions_of(&[Role::SuperAdmin]).contains(Permission::ManageSettings));
+        assert!(!permissions_of(&[Role::UserAdmin]).contains(Permission::ManageSettings));
+    }
+
+    #[test]
+    fn intersection() {
+        let user = permissions_of(&[Role::Reserver]);
+        let scopes: Permissions = [Permission::ReserveCodes, Permission::ManageUsers]
+            .into_iter()
+            .collect();
+
+        let permissions = user.intersection(scopes);
+
+        assert_eq!(
+            permissions.iter().collect::<Vec<_>>(),
+            vec![Permission::ReserveCodes]
+        );
+    }
+
+    #[test]
+    fn no_roles_no_permissions() {
+        let permissions = permissions_of(&[]);
+
+        assert!(!permissions.contains(Permission::ViewCodes));
+    }
+
+    #[test]
+    fn role_names() {
+        for role in Role::ALL {
+            assert_eq!(role.as_str().parse::<Role>(), Ok(role));
+        }
+        for permission in Permission::ALL {
+            assert_eq!(permission.as_str().parse::<Permission>(), Ok(permission));
+        }
+        assert!("admin".parse::<Role>().is_err());
+    }
+}
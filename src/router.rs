@@ -3,77 +3,192 @@ use axum::{
     routing::{delete, get, post},
     Router,
 };
-use sqlx::SqlitePool;
 use tower_http::{
     services::{ServeDir, ServeFile},
     trace::TraceLayer,
 };
-use tower_sessions::{MemoryStore, SessionManagerLayer};
+use tower_sessions::{cookie::SameSite, MemoryStore, SessionManagerLayer};
 
 use crate::{
     actions::{
-        admin::{create_user, delete_user, get_users},
-        auth::{change_password, change_password_post, login, login_post, logout_post},
-        codes::{add_code, reset_codes},
+        admin::{
+            confirm_delete_user, create_user_password_reset, deactivate_user, delete_user,
+            edit_user, get_audit_log, get_settings, get_user, get_users, reactivate_user,
+            update_settings, update_user,
+        },
+        auth::{
+            change_password, change_password_post, login, login_post, login_two_factor_post,
+            logout_post, oidc_callback, oidc_login,
+        },
+        codes::{add_code, add_codes, list_codes, reset_codes, undo_reset, validate_code},
+        invites::{accept_invite_post, create_user_invite, invite, revoke_user_invite},
         pages::index,
+        password_reset::{reset_password, reset_password_post},
+        series::{create_series, get_series, toggle_series},
+        sessions::{get_sessions, revoke_all_sessions, revoke_session},
+        setup::{setup, setup_post},
+        tokens::{create_token, get_tokens, revoke_token},
+        two_factor::{
+            disable_two_factor, enable_two_factor, regenerate_recovery_codes, setup_two_factor,
+        },
     },
-    middleware::auth_middleware,
+    middleware::{auth_middleware, permission_middleware},
+    permissions::Permission,
     state::AppState,
 };
 
-pub fn setup_router(db: SqlitePool, jwt_secret: &str) -> Router {
+pub fn setup_router(app_state: AppState) -> Router {
     let session_store = MemoryStore::default();
-    let session_layer = SessionManagerLayer::new(session_store);
-    let app_state = AppState::new(db, jwt_secret);
+    // The identity provider sends the user back from another site, a strict cookie would stay behind
+    let session_layer = SessionManagerLayer::new(session_store).with_same_site(SameSite::Lax);
     Router::new()
+        .merge(with_permission(
+            &app_state,
+            Permission::ViewCodes,
+            Router::new()
+                .route("/", get(index))
+                .route("/code", get(list_codes))
+                .route("/code/validate", get(validate_code)),
+        ))
+        .merge(with_permission(
+            &app_state,
+            Permission::ReserveCodes,
+            Router::new()
+                .route("/code", post(add_code))
+                .route("/code/batch", post(add_codes)),
+        ))
+        .merge(with_permission(
+            &app_state,
+            Permission::ResetCodes,
+            Router::new()
+                .route("/code/reset", post(reset_codes))
+                .route("/code/reset/undo", post(undo_reset)),
+        ))
+        .merge(with_permission(
+            &app_state,
+            Permission::ManageUsers,
+            Router::new()
+                .route("/admin/user", get(get_users))
+                .route(
+                    "/admin/user/:id",
+                    get(get_user).put(update_user).delete(delete_user),
+                )
+                .route("/admin/user/:id/edit", get(edit_user))
+                .route("/admin/user/:id/delete", get(confirm_delete_user))
+                .route("/admin/user/:id/deactivate", post(deactivate_user))
+                .route("/admin/user/:id/reactivate", post(reactivate_user))
+                .route(
+                    "/admin/user/:id/password-reset",
+                    post(create_user_password_reset),
+                )
+                .route("/admin/invite", post(create_user_invite))
+                .route("/admin/invite/:id", delete(revoke_user_invite)),
+        ))
+        .merge(with_permission(
+            &app_state,
+            Permission::ManageSeries,
+            Router::new()
+                .route("/admin/series", get(get_series).post(create_series))
+                .route("/admin/series/:id/toggle", post(toggle_series)),
+        ))
+        .merge(with_permission(
+            &app_state,
+            Permission::ViewAuditLog,
+            Router::new().route("/admin/audit", get(get_audit_log)),
+        ))
+        .merge(with_permission(
+            &app_state,
+            Permission::ManageSettings,
+            Router::new().route("/admin/settings", get(get_settings).post(update_settings)),
+        ))
         .route(
-            "/",
-            get(index).route_layer(middleware::from_fn_with_state(
+            "/sessions",
+            get(get_sessions).route_layer(middleware::from_fn_with_state(
                 app_state.clone(),
                 auth_middleware,
             )),
         )
         .route(
-            "/code",
-            post(add_code).route_layer(middleware::from_fn_with_state(
+            "/sessions/revoke-all",
+            post(revoke_all_sessions).route_layer(middleware::from_fn_with_state(
                 app_state.clone(),
                 auth_middleware,
             )),
         )
         .route(
-            "/code/reset",
-            post(reset_codes).route_layer(middleware::from_fn_with_state(
+            "/sessions/:id",
+            delete(revoke_session).route_layer(middleware::from_fn_with_state(
+                app_state.clone(),
+                auth_middleware,
+            )),
+        )
+        .route(
+            "/tokens",
+            get(get_tokens)
+                .post(create_token)
+                .route_layer(middleware::from_fn_with_state(
+                    app_state.clone(),
+                    auth_middleware,
+                )),
+        )
+        .route(
+            "/tokens/:id",
+            delete(revoke_token).route_layer(middleware::from_fn_with_state(
                 app_state.clone(),
                 auth_middleware,
             )),
         )
         .route("/login", get(login).post(login_post))
+        .route("/login/two-factor", post(login_two_factor_post))
+        .route("/login/oidc", get(oidc_login))
+        .route("/login/oidc/callback", get(oidc_callback))
         .route("/logout", post(logout_post))
+        .route("/setup", get(setup).post(setup_post))
+        .route("/reset-password", post(reset_password_post))
+        .route("/invite", post(accept_invite_post))
+        .route("/invite/:token", get(invite))
+        .route("/reset-password/:token", get(reset_password))
         .route(
             "/change-password",
             get(change_password).post(change_password_post).route_layer(
                 middleware::from_fn_with_state(app_state.clone(), auth_middleware),
             ),
         )
-        .route(
-            "/admin/user",
-            get(get_users)
-                .post(create_user)
+        .merge(
+            Router::new()
+                .route("/two-factor/setup", post(setup_two_factor))
+                .route("/two-factor/enable", post(enable_two_factor))
+                .route("/two-factor/disable", post(disable_two_factor))
+                .route(
+                    "/two-factor/recovery-codes",
+                    post(regenerate_recovery_codes),
+                )
                 .route_layer(middleware::from_fn_with_state(
                     app_state.clone(),
                     auth_middleware,
                 )),
         )
-        .route(
-            "/admin/user/:id",
-            delete(delete_user).route_layer(middleware::from_fn_with_state(
-                app_state.clone(),
-                auth_middleware,
-            )),
-        )
         .nest_service("/assets", ServeDir::new("assets"))
         .nest_service("/favicon.ico", ServeFile::new("assets/favicon.ico"))
         .layer(session_layer)
         .layer(TraceLayer::new_for_http())
         .with_state(app_state)
 }
+
+/// Lets only authenticated users with the permission use the routes. The layer added last runs
+/// first, so the user is known by the time the permission is checked.
+fn with_permission(
+    app_state: &AppState,
+    permission: Permission,
+    routes: Router<AppState>,
+) -> Router<AppState> {
+    routes
+        .route_layer(middleware::from_fn_with_state(
+            permission,
+            permission_middleware,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            auth_middleware,
+        ))
+}
@@ -1,16 +1,76 @@
+use std::net::IpAddr;
+
+use chrono_tz::Tz;
 use sqlx::SqlitePool;
 
+use crate::{code_format::CodeFormat, oidc::OidcConfig, password_policy::PasswordPolicy};
+
 #[derive(Debug, Clone)]
 pub struct AppState {
     pub db: SqlitePool,
     pub jwt_secret: String,
+    pub code_prefix: String,
+    pub code_format: CodeFormat,
+    pub max_batch_size: u32,
+    pub timezone: Tz,
+    /// Identity provider for single sign-on, only local logins when not set.
+    pub oidc: Option<OidcConfig>,
+    /// Token for creating the first admin on the setup page, only set while there is none.
+    pub setup_token: Option<String>,
+    /// Rules for new passwords.
+    pub password_policy: PasswordPolicy,
+    /// Reverse proxies whose `X-Forwarded-*` headers tell the client address and the public host.
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Address users reach the application at, without a trailing slash.
+    pub public_url: Option<String>,
 }
 
 impl AppState {
-    pub fn new(db: SqlitePool, jwt_secret: &str) -> Self {
+    pub fn new(
+        db: SqlitePool,
+        jwt_secret: &str,
+        code_prefix: &str,
+        code_format: CodeFormat,
+        max_batch_size: u32,
+        timezone: Tz,
+    ) -> Self {
         Self {
             db,
             jwt_secret: jwt_secret.to_string(),
+            code_prefix: code_prefix.to_string(),
+            code_format,
+            max_batch_size,
+            timezone,
+            oidc: None,
+            setup_token: None,
+            password_policy: PasswordPolicy::default(),
+            trusted_proxies: Vec::new(),
+            public_url: None,
         }
     }
+
+    pub fn with_oidc(mut self, oidc: OidcConfig) -> Self {
+        self.oidc = Some(oidc);
+        self
+    }
+
+    pub fn with_setup_token(mut self, setup_token: &str) -> Self {
+        self.setup_token = Some(setup_token.to_string());
+        self
+    }
+
+    pub fn with_password_policy(mut self, password_policy: PasswordPolicy) -> Self {
+        self.password_policy = password_policy;
+        self
+    }
+
+    pub fn with_trusted_proxies(mut self, trusted_proxies: Vec<IpAddr>) -> Self {
+        self.trusted_proxies = trusted_proxies;
+        self
+    }
+
+    pub fn with_public_url(mut self, public_url: &str) -> Self {
+        self.public_url = Some(public_url.trim_end_matches('/').to_string());
+        self
+    }
 }
@@ -1,6 +1,10 @@
 use askama::Template;
 
-use crate::models::User;
+use crate::{
+    audit::AuditAction,
+    models::{AuditEvent, Invite, Settings, User},
+    permissions::{Permission, Permissions, Role},
+};
 
 use super::WithLayout;
 
@@ -8,9 +12,13 @@ use super::WithLayout;
 #[template(path = "pages/user_management/page.html")]
 pub struct UserManagementTemplate {
     pub from_protected: bool,
-    pub is_admin: bool,
+    pub permissions: Permissions,
     pub logged_user: Option<String>,
     pub users: Vec<User>,
+    pub roles: [Role; 5],
+    pub invites: Vec<Invite>,
+    /// Link of the invite just created, shown only once.
+    pub created: Option<String>,
 }
 
 impl WithLayout for UserManagementTemplate {}
@@ -20,3 +28,81 @@ impl WithLayout for UserManagementTemplate {}
 pub struct UserTemplate {
     pub user: User,
 }
+
+/// Row of the user turned into a form.
+#[derive(Template)]
+#[template(path = "pages/user_management/user_edit.html")]
+pub struct UserEditTemplate {
+    pub user: User,
+    /// Name and roles as entered, kept when the form comes back with an error.
+    pub name: String,
+    pub selected: Vec<Role>,
+    pub roles: [Role; 5],
+    pub error: Option<String>,
+}
+
+/// Row of the user asking where their codes go before deleting them.
+#[derive(Template)]
+#[template(path = "pages/user_management/user_delete.html")]
+pub struct UserDeleteTemplate {
+    pub user: User,
+    /// Everyone else who could take over the codes.
+    pub users: Vec<User>,
+}
+
+/// Pending invites, with the link of the one just created.
+#[derive(Template)]
+#[template(path = "pages/user_management/invites.html")]
+pub struct InvitesSectionTemplate {
+    pub invites: Vec<Invite>,
+    pub created: Option<String>,
+}
+
+/// Reset link shown below the user it was created for.
+#[derive(Template)]
+#[template(path = "pages/user_management/reset_link.html")]
+pub struct PasswordResetLinkTemplate {
+    pub username: String,
+    pub link: String,
+    pub expires_at: String,
+}
+
+#[derive(Template)]
+#[template(path = "pages/audit_log/page.html")]
+pub struct AuditLogTemplate {
+    pub from_protected: bool,
+    pub permissions: Permissions,
+    pub logged_user: Option<String>,
+    pub events: Vec<AuditEvent>,
+    pub actions: &'static [AuditAction],
+    pub actor: String,
+    pub action: String,
+    pub from: String,
+    pub to: String,
+    pub page: i64,
+    pub pages: i64,
+}
+
+impl WithLayout for AuditLogTemplate {}
+
+#[derive(Template)]
+#[template(path = "pages/settings/page.html")]
+pub struct SettingsTemplate {
+    pub from_protected: bool,
+    pub permissions: Permissions,
+    pub logged_user: Option<String>,
+    pub settings: Settings,
+    pub error: Option<String>,
+    pub saved: bool,
+}
+
+impl WithLayout for SettingsTemplate {}
+
+#[derive(Template)]
+#[template(path = "pages/settings/section.html")]
+pub struct SettingsSectionTemplate {
+    pub settings: Settings,
+    pub error: Option<String>,
+    /// Whether to confirm the settings were just saved.
+    pub saved: bool,
+}
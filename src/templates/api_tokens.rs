@@ -0,0 +1,28 @@
+use askama::Template;
+
+use crate::{
+    models::ApiToken,
+    permissions::{Permission, Permissions},
+};
+
+use super::WithLayout;
+
+#[derive(Template)]
+#[template(path = "pages/api_tokens/page.html")]
+pub struct ApiTokensTemplate {
+    pub from_protected: bool,
+    pub permissions: Permissions,
+    pub logged_user: Option<String>,
+    pub tokens: Vec<ApiToken>,
+    pub created: Option<String>,
+}
+
+impl WithLayout for ApiTokensTemplate {}
+
+#[derive(Template)]
+#[template(path = "pages/api_tokens/section.html")]
+pub struct ApiTokensSectionTemplate {
+    pub tokens: Vec<ApiToken>,
+    /// Secret of the token just created, shown only this once.
+    pub created: Option<String>,
+}
@@ -1,16 +1,23 @@
 use askama::Template;
 
+use crate::{
+    permissions::{Permission, Permissions},
+    totp::Enrollment,
+};
+
 use super::WithLayout;
 
 #[derive(Template)]
 #[template(path = "pages/login/page.html")]
 pub struct LoginPageTemplate {
     pub from_protected: bool,
-    pub is_admin: bool,
+    pub permissions: Permissions,
     pub username: String,
     pub password: String,
     pub error: Option<String>,
     pub logged_user: Option<String>,
+    /// Whether to offer logging in through the identity provider.
+    pub sso: bool,
 }
 
 impl WithLayout for LoginPageTemplate {}
@@ -23,13 +30,67 @@ pub struct LoginSectionTemplate {
     pub error: Option<String>,
 }
 
+/// Second login step, asking for a code of the authenticator app.
+#[derive(Template)]
+#[template(path = "pages/login/two_factor.html")]
+pub struct LoginTwoFactorTemplate {
+    /// Secret to set up first, for accounts that have to use two-factor authentication.
+    pub enrollment: Option<Enrollment>,
+    pub error: Option<String>,
+}
+
+/// Second login step as a whole page, for logins coming back from the identity provider.
+#[derive(Template)]
+#[template(path = "pages/login/two_factor_page.html")]
+pub struct LoginTwoFactorPageTemplate {
+    pub from_protected: bool,
+    pub permissions: Permissions,
+    pub logged_user: Option<String>,
+    pub enrollment: Option<Enrollment>,
+    pub error: Option<String>,
+}
+
+impl WithLayout for LoginTwoFactorPageTemplate {}
+
+#[derive(Template)]
+#[template(path = "pages/login/recovery_codes.html")]
+pub struct LoginRecoveryCodesTemplate {
+    pub recovery_codes: Vec<String>,
+}
+
+/// Creating the first admin, reached through the address printed to the log.
+#[derive(Template)]
+#[template(path = "pages/setup/page.html")]
+pub struct SetupPageTemplate {
+    pub from_protected: bool,
+    pub permissions: Permissions,
+    pub logged_user: Option<String>,
+    pub token: String,
+    pub username: String,
+    pub error: Option<String>,
+}
+
+impl WithLayout for SetupPageTemplate {}
+
+#[derive(Template)]
+#[template(path = "pages/setup/section.html")]
+pub struct SetupSectionTemplate {
+    pub token: String,
+    pub username: String,
+    pub error: Option<String>,
+}
+
 #[derive(Template)]
 #[template(path = "pages/password_change/page.html")]
 pub struct ChangePasswordPageTemplate {
     pub from_protected: bool,
     pub logged_user: Option<String>,
-    pub is_admin: bool,
+    pub permissions: Permissions,
     pub error: Option<String>,
+    /// Whether the user is here because the password has to change first.
+    pub must_change_password: bool,
+    pub two_factor: TwoFactorState,
+    pub two_factor_error: Option<String>,
 }
 
 impl WithLayout for ChangePasswordPageTemplate {}
@@ -45,3 +106,73 @@ pub struct ChangePasswordSectionTemplate {
 pub struct ChangePasswordSuccessTemplate {}
 
 impl WithLayout for ChangePasswordSuccessTemplate {}
+
+/// Setting a new password through the reset link of an admin.
+#[derive(Template)]
+#[template(path = "pages/password_reset/page.html")]
+pub struct ResetPasswordPageTemplate {
+    pub from_protected: bool,
+    pub permissions: Permissions,
+    pub logged_user: Option<String>,
+    pub token: String,
+    pub username: String,
+    pub error: Option<String>,
+    /// Whether the link still works, only then the form is shown.
+    pub valid: bool,
+}
+
+impl WithLayout for ResetPasswordPageTemplate {}
+
+#[derive(Template)]
+#[template(path = "pages/password_reset/section.html")]
+pub struct ResetPasswordSectionTemplate {
+    pub token: String,
+    pub username: String,
+    pub error: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "pages/password_reset/success.html")]
+pub struct ResetPasswordSuccessTemplate {}
+
+/// Creating an account from an invite.
+#[derive(Template)]
+#[template(path = "pages/invite/page.html")]
+pub struct InvitePageTemplate {
+    pub from_protected: bool,
+    pub permissions: Permissions,
+    pub logged_user: Option<String>,
+    pub token: String,
+    pub username: String,
+    pub error: Option<String>,
+    /// Whether the invite still works, only then the form is shown.
+    pub valid: bool,
+}
+
+impl WithLayout for InvitePageTemplate {}
+
+#[derive(Template)]
+#[template(path = "pages/invite/section.html")]
+pub struct InviteSectionTemplate {
+    pub token: String,
+    pub username: String,
+    pub error: Option<String>,
+}
+
+/// What the two-factor section of the profile page shows.
+pub enum TwoFactorState {
+    Disabled,
+    Setup(Enrollment),
+    Enabled {
+        recovery_codes_left: i64,
+    },
+    /// Recovery codes just created, shown only this once.
+    RecoveryCodes(Vec<String>),
+}
+
+#[derive(Template)]
+#[template(path = "pages/two_factor/section.html")]
+pub struct TwoFactorSectionTemplate {
+    pub two_factor: TwoFactorState,
+    pub two_factor_error: Option<String>,
+}
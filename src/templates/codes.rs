@@ -1,6 +1,9 @@
 use askama::Template;
 
-use crate::models::Code;
+use crate::{
+    models::{Code, Series},
+    permissions::{Permission, Permissions},
+};
 
 use super::WithLayout;
 
@@ -8,9 +11,12 @@ use super::WithLayout;
 #[template(path = "pages/index/page.html")]
 pub struct IndexPageTemplate {
     pub codes: Vec<Code>,
+    pub series: Vec<Series>,
+    pub selected_series: i64,
     pub from_protected: bool,
     pub logged_user: Option<String>,
-    pub is_admin: bool,
+    pub permissions: Permissions,
+    pub max_batch_size: u32,
 }
 
 impl WithLayout for IndexPageTemplate {}
@@ -22,7 +28,7 @@ pub struct IndexSectionTemplate {
 }
 
 #[derive(Template)]
-#[template(path = "pages/index/code_item.html")]
+#[template(path = "pages/index/code_block.html")]
 pub struct CodeItemTemplate {
-    pub code: Code,
+    pub codes: Vec<Code>,
 }
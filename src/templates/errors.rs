@@ -1,5 +1,7 @@
 use askama::Template;
 
+use crate::permissions::{Permission, Permissions};
+
 use super::WithLayout;
 
 /// Error 401 page template
@@ -8,19 +10,31 @@ use super::WithLayout;
 pub struct Error401Template {
     pub reason: String,
     pub from_protected: bool,
-    pub is_admin: bool,
+    pub permissions: Permissions,
     pub logged_user: Option<String>,
 }
 
 impl WithLayout for Error401Template {}
 
+/// Error 403 page template
+#[derive(Template)]
+#[template(path = "errors/403.html")]
+pub struct Error403Template {
+    pub reason: String,
+    pub from_protected: bool,
+    pub permissions: Permissions,
+    pub logged_user: Option<String>,
+}
+
+impl WithLayout for Error403Template {}
+
 /// Error 500 page template
 #[derive(Template)]
 #[template(path = "errors/500.html")]
 pub struct Error500Template {
     pub reason: String,
     pub from_protected: bool,
-    pub is_admin: bool,
+    pub permissions: Permissions,
     pub logged_user: Option<String>,
 }
 
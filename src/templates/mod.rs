@@ -3,9 +3,12 @@ use axum::http::StatusCode;
 use axum::response::{Html, IntoResponse, Response};
 
 pub mod admin;
+pub mod api_tokens;
 pub mod auth;
 pub mod codes;
 pub mod errors;
+pub mod series;
+pub mod sessions;
 
 pub trait WithLayout {
     fn version(&self) -> &'static str {
@@ -0,0 +1,31 @@
+use askama::Template;
+
+use crate::{
+    models::Series,
+    permissions::{Permission, Permissions},
+};
+
+use super::WithLayout;
+
+#[derive(Template)]
+#[template(path = "pages/series_management/page.html")]
+pub struct SeriesManagementTemplate {
+    pub from_protected: bool,
+    pub permissions: Permissions,
+    pub logged_user: Option<String>,
+    pub series: Vec<Series>,
+    pub default_prefix: String,
+    pub default_format: String,
+    pub default_timezone: String,
+}
+
+impl WithLayout for SeriesManagementTemplate {}
+
+#[derive(Template)]
+#[template(path = "pages/series_management/series.html")]
+pub struct SeriesTemplate {
+    pub series: Series,
+    pub default_prefix: String,
+    pub default_format: String,
+    pub default_timezone: String,
+}
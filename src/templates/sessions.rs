@@ -0,0 +1,21 @@
+use askama::Template;
+
+use crate::{
+    models::LoginSession,
+    permissions::{Permission, Permissions},
+};
+
+use super::WithLayout;
+
+#[derive(Template)]
+#[template(path = "pages/sessions/page.html")]
+pub struct SessionsTemplate {
+    pub from_protected: bool,
+    pub permissions: Permissions,
+    pub logged_user: Option<String>,
+    pub sessions: Vec<LoginSession>,
+    /// Session of the request, it can't be revoked on its own.
+    pub current: Option<String>,
+}
+
+impl WithLayout for SessionsTemplate {}
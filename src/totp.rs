@@ -0,0 +1,239 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use hmac::{Hmac, Mac};
+use qrcode::{render::svg, QrCode};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+/// Seconds each code is valid, the default of authenticator apps.
+const STEP_SECONDS: i64 = 30;
+
+/// Digits of a code.
+const DIGITS: u32 = 6;
+
+/// Steps before and after the current one still accepted, for clocks running a bit off.
+const ALLOWED_DRIFT: i64 = 1;
+
+/// Recovery codes handed out at once.
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Name shown for the account in authenticator apps.
+const ISSUER: &str = "Serigen";
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Secret being set up, shown as QR code until the user confirms it with a code.
+#[derive(Debug, Clone)]
+pub struct Enrollment {
+    pub secret: String,
+    /// SVG of the QR code with the `otpauth://` address of the secret.
+    pub qr_code: String,
+}
+
+impl Enrollment {
+    pub fn new(account: &str, secret: String) -> Self {
+        Enrollment {
+            qr_code: qr_code_svg(&otpauth_url(account, &secret)),
+            secret,
+        }
+    }
+}
+
+/// Creates a new random secret, base32 encoded like authenticator apps expect it.
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 20];
+    OsRng.fill_bytes(&mut bytes);
+
+    base32_encode(&bytes)
+}
+
+/// Time step the code is for, if it's valid for the secret around the time.
+///
+/// The caller has to remember the step and reject it next time, otherwise a code could be used
+/// twice.
+pub fn verify(secret: &str, code: &str, timestamp: i64) -> Option<i64> {
+    let key = base32_decode(secret)?;
+    let code = code.trim();
+    let current = timestamp.div_euclid(STEP_SECONDS);
+
+    (current - ALLOWED_DRIFT..=current + ALLOWED_DRIFT).find(|step| hotp(&key, *step) == code)
+}
+
+/// Code of the authenticator app at the time, for tests acting as the app.
+#[cfg(test)]
+pub fn code_at(secret: &str, timestamp: i64) -> String {
+    let key = base32_decode(secret).expect("valid secret");
+
+    hotp(&key, timestamp.div_euclid(STEP_SECONDS))
+}
+
+/// Whether the input looks like a code of an authenticator app rather than a recovery code.
+pub fn is_totp_code(code: &str) -> bool {
+    let code = code.trim();
+    code.len() == DIGITS as usize && code.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Address authenticator apps read from the QR code.
+fn otpauth_url(account: &str, secret: &str) -> String {
+    let label = format!("{}:{}", ISSUER, account);
+
+    format!(
+        "otpauth://totp/{}?secret={}&issuer={}&digits={}&period={}",
+        url::form_urlencoded::byte_serialize(label.as_bytes()).collect::<String>(),
+        secret,
+        ISSUER,
+        DIGITS,
+        STEP_SECONDS
+    )
+}
+
+/// QR code of the address as inline SVG.
+fn qr_code_svg(data: &str) -> String {
+    match QrCode::new(data.as_bytes()) {
+        Ok(code) => code
+            .render::<svg::Color>()
+            .min_dimensions(200, 200)
+            .quiet_zone(true)
+            .build(),
+        Err(_) => String::new(),
+    }
+}
+
+/// Creates a set of recovery codes like `k3xq-8fmt`.
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = [0u8; 5];
+            OsRng.fill_bytes(&mut bytes);
+
+            let code = base32_encode(&bytes).to_lowercase();
+            format!("{}-{}", &code[..4], &code[4..])
+        })
+        .collect()
+}
+
+/// Hashes the recovery code for storage, ignoring case and separators people type differently.
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+
+    hex::encode(Sha256::digest(normalized.as_bytes()))
+}
+
+/// HOTP value of the counter, RFC 4226.
+fn hotp(key: &[u8], counter: i64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+
+    format!(
+        "{:0width$}",
+        value % 10u32.pow(DIGITS),
+        width = DIGITS as usize
+    )
+}
+
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+
+    encoded
+}
+
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut bytes = vec![];
+    let mut buffer = 0u32;
+    let mut bits = 0;
+
+    for c in encoded.chars().filter(|c| *c != '=' && !c.is_whitespace()) {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|a| *a as char == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::totp::{
+        base32_decode, base32_encode, generate_recovery_codes, generate_secret, hash_recovery_code,
+        hotp, is_totp_code, verify,
+    };
+
+    #[test]
+    fn rfc_6238_codes() {
+        // Test vectors of RFC 6238 for SHA-1, cut to six digits
+        let key = b"12345678901234567890";
+
+        assert_eq!(hotp(key, 59 / 30), "287082");
+        assert_eq!(hotp(key, 1111111109 / 30), "081804");
+        assert_eq!(hotp(key, 2000000000 / 30), "279037");
+    }
+
+    #[test]
+    fn base32_round_trip() {
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(base32_decode("MZXW6YTBOI").unwrap(), b"foobar");
+        assert_eq!(base32_decode("mzxw 6ytb oi======").unwrap(), b"foobar");
+        assert!(base32_decode("MZXW1").is_none());
+
+        let secret = generate_secret();
+        assert_eq!(secret.len(), 32);
+        assert_eq!(base32_decode(&secret).unwrap().len(), 20);
+    }
+
+    #[test]
+    fn verify_allows_drift() {
+        let secret = base32_encode(b"12345678901234567890");
+
+        assert_eq!(verify(&secret, "287082", 59), Some(1));
+        assert_eq!(verify(&secret, " 287082 ", 89), Some(1));
+        assert_eq!(verify(&secret, "287082", 120), None);
+        assert_eq!(verify(&secret, "000000", 59), None);
+    }
+
+    #[test]
+    fn recovery_codes() {
+        let codes = generate_recovery_codes();
+
+        assert_eq!(codes.len(), 10);
+        assert!(codes
+            .iter()
+            .all(|code| code.len() == 9 && !is_totp_code(code)));
+        assert_eq!(
+            hash_recovery_code("K3XQ 8FMT"),
+            hash_recovery_code("k3xq-8fmt")
+        );
+        assert!(is_totp_code("123456"));
+    }
+}
@@ -1,16 +1,78 @@
-use chrono::NaiveDateTime;
+use std::net::SocketAddr;
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRef, FromRequestParts},
+    http::{header::HOST, request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, FixedOffset, Utc};
+use chrono_tz::Tz;
 use tower_sessions::Session;
 
-use crate::middleware::FROM_PROTECTED_KEY;
+use crate::{middleware::FROM_PROTECTED_KEY, state::AppState};
 
-pub fn format_date(date: NaiveDateTime) -> String {
-    if date.date() == chrono::Local::now().date_naive() {
+/// Formats the time in the timezone it's shown in, omitting the date when it's today there.
+pub fn format_date(date: DateTime<FixedOffset>, timezone: Tz) -> String {
+    let date = date.with_timezone(&timezone);
+    if date.date_naive() == Utc::now().with_timezone(&timezone).date_naive() {
         date.format("%H:%M:%S").to_string()
     } else {
         date.format("%d.%m. %H:%M:%S").to_string()
     }
 }
 
+/// Address users reach the application at, for links sent to other people.
+///
+/// `SERIGEN_PUBLIC_URL` when it's configured, the host the request was sent to otherwise.
+/// Anyone can send `X-Forwarded-Proto` and `X-Forwarded-Host`, so they're only read when the
+/// peer is a trusted proxy.
+pub struct BaseUrl(String);
+
+impl BaseUrl {
+    /// Absolute address of the path.
+    pub fn join(&self, path: &str) -> String {
+        format!("{}{}", self.0, path)
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for BaseUrl
+where
+    AppState: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let state = AppState::from_ref(state);
+        if let Some(public_url) = state.public_url {
+            return Ok(BaseUrl(public_url));
+        }
+
+        let header = |name: &str| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string())
+        };
+        let behind_proxy = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .is_some_and(|ConnectInfo(address)| state.trusted_proxies.contains(&address.ip()));
+        let forwarded = |name: &str| header(name).filter(|_| behind_proxy);
+
+        // Behind a TLS terminating proxy the browser used https
+        let scheme = forwarded("x-forwarded-proto").unwrap_or_else(|| "http".to_string());
+        let host = forwarded("x-forwarded-host")
+            .or_else(|| header(HOST.as_str()))
+            .ok_or_else(|| (StatusCode::BAD_REQUEST, "Missing Host header").into_response())?;
+
+        Ok(BaseUrl(format!("{}://{}", scheme, host)))
+    }
+}
+
 pub async fn get_protected(session: Session) -> bool {
     let from_protected: bool = session
         .get(FROM_PROTECTED_KEY)
@@ -19,3 +81,30 @@ pub async fn get_protected(session: Session) -> bool {
         .unwrap_or_default();
     from_protected
 }
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+    use chrono_tz::Tz;
+
+    use crate::utils::format_date;
+
+    #[test]
+    fn format_date_in_timezone() {
+        let date = Utc
+            .with_ymd_and_hms(2024, 1, 1, 23, 30, 0)
+            .unwrap()
+            .fixed_offset();
+
+        assert_eq!(format_date(date, Tz::UTC), "01.01. 23:30:00");
+        assert_eq!(format_date(date, Tz::Europe__Prague), "02.01. 00:30:00");
+
+        // Today is judged in the timezone too
+        let now = Utc::now().fixed_offset();
+        let there = now.with_timezone(&Tz::Pacific__Kiritimati);
+        assert_eq!(
+            format_date(now, Tz::Pacific__Kiritimati),
+            there.format("%H:%M:%S").to_string()
+        );
+    }
+}